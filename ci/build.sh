@@ -5,6 +5,7 @@ case "$TARGET" in
     x86_64-unknown-linux-gnu)
         cargo build --verbose --all
         cargo test --verbose --all
+        cargo build --verbose --no-default-features
         ;;
     aarch64-unknown-linux-gnu)
         export RUSTFLAGS="-C linker=aarch64-linux-gnu-gcc-6 -C ar=aarch64-linux-gnu-gcc-ar-6"
@@ -7,19 +7,29 @@ extern crate env_logger;
 extern crate nom;
 extern crate colored;
 extern crate error_chain;
+#[macro_use] extern crate serde_json;
 #[macro_use] extern crate log;
 
 use colored::Colorize;
 
 use tr1pd::{Result, ResultExt};
-use tr1pd::blocks::InnerBlock;
+use tr1pd::blocks::{Block, InnerBlock, KeyId, KeyUsage, RekeyPolicy, TxTracker};
 use tr1pd::cli;
+use tr1pd::compress;
 use tr1pd::config;
 use tr1pd::crypto::{self, PublicKey};
+use tr1pd::envelope::{self, AuditorKey};
+use tr1pd::journal::CursorFile;
+use tr1pd::ledger;
 use tr1pd::sandbox;
 use tr1pd::storage::{DiskStorage, BlockStorage};
 use tr1pd::recipe::{BlockRecipe, InfoBlockPipe};
-use tr1pd::rpc::{ClientBuilder, CtlRequest};
+use tr1pd::rpc::{Client, ClientBuilder, Status};
+use tr1pd::sensors::{SensorCheck, SensorKey, SensorRegistry};
+use tr1pd::sequence::{SourceCounter, SourceTracker, Verdict, Writer};
+use tr1pd::spool::Spool;
+#[cfg(feature="zmq")]
+use tr1pd::rpc::{self, CurveKey, CurveClient};
 use tr1pd::wire;
 
 use nom::IResult;
@@ -33,6 +43,7 @@ use std::fs::File;
 use std::fs::OpenOptions;
 use std::os::unix::fs::OpenOptionsExt;
 use std::process::{Command, Stdio};
+use std::time::Duration;
 
 
 fn load_pubkey(pk: &str) -> Result<PublicKey> {
@@ -44,6 +55,171 @@ fn load_pubkey(pk: &str) -> Result<PublicKey> {
     Ok(pk)
 }
 
+#[cfg(feature="zmq")]
+fn load_curve(client: ClientBuilder, args: &cli::tr1pctl::Args, config: &config::Config) -> Result<ClientBuilder> {
+    let (pk, sk) = match (args.curve_pk.as_ref(), args.curve_sk.as_ref()) {
+        (Some(pk), Some(sk)) => (pk, sk),
+        (None, None) => return Ok(client),
+        _ => return Err("--curve-pk and --curve-sk need to be used together".into()),
+    };
+
+    let server = match args.curve_server.as_ref() {
+        Some(server) => server.as_str(),
+        None => match config.curve_keypair() {
+            Some((server, _)) => server,
+            None => return Err("--curve-server is required".into()),
+        },
+    };
+
+    Ok(client.curve(CurveClient {
+        public_key: CurveKey::load(pk).chain_err(|| "failed to load curve public key")?,
+        secret_key: CurveKey::load(sk).chain_err(|| "failed to load curve secret key")?,
+        server_key: CurveKey::load(server).chain_err(|| "failed to load curve server key")?,
+    }))
+}
+
+#[cfg(not(feature="zmq"))]
+fn load_curve(client: ClientBuilder, args: &cli::tr1pctl::Args, _config: &config::Config) -> Result<ClientBuilder> {
+    if args.curve_pk.is_some() || args.curve_sk.is_some() || args.curve_server.is_some() {
+        return Err("curve requires zmq, tr1pctl was built without zmq support".into());
+    }
+    Ok(client)
+}
+
+fn write_keyfile(path: &Path, key: &[u8], mode: u32, force: bool) -> Result<()> {
+    let mut file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .create_new(!force)
+                    .mode(mode)
+                    .open(path)?;
+    file.write_all(key)?;
+    Ok(())
+}
+
+/// With a spool, tr1pctl starts even if the daemon is unreachable.
+fn connect_spooled(client: &ClientBuilder, spool: Option<&String>) -> Result<(Client, Option<Spool>)> {
+    let spool = match spool {
+        Some(dir) => Spool::open(dir.as_str())
+                        .chain_err(|| format!("failed to open spool {:?}", dir))?,
+        None => return Ok((client.connect()?, None)),
+    };
+
+    let client = match client.connect() {
+        Ok(client) => client,
+        Err(ref err) if err.is_unreachable() => {
+            warn!("daemon is unreachable, spooling messages: {}", err);
+            client.build()
+        },
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok((client, Some(spool)))
+}
+
+fn load_decrypt_key(path: Option<&String>) -> Result<Option<AuditorKey>> {
+    match path {
+        Some(path) => {
+            let key = AuditorKey::load(path)
+                        .chain_err(|| format!("failed to load auditor key {:?}", path))?;
+            Ok(Some(key))
+        },
+        None => Ok(None),
+    }
+}
+
+/// The stored message of the block, `None` if it's encrypted and the key
+/// can't decrypt it.
+fn plaintext(block: &Block, bytes: Vec<u8>, key: Option<&AuditorKey>) -> Result<Option<Vec<u8>>> {
+    let bytes = match (block.envelope(), key) {
+        (None, _) => bytes,
+        (Some(envelope), Some(key)) => {
+            let msg = key.open(envelope, &bytes)
+                .chain_err(|| format!("failed to decrypt {:x}", block.sha3()))?;
+            match msg {
+                Some(msg) => msg,
+                None => return Ok(None),
+            }
+        },
+        (Some(_), None) => return Ok(None),
+    };
+
+    decompress(block, bytes).map(Some)
+}
+
+/// Decompress the message if the block is flagged as compressed, it needs
+/// to be decrypted first.
+fn decompress(block: &Block, bytes: Vec<u8>) -> Result<Vec<u8>> {
+    match block.compressed() {
+        Some(compressed) => {
+            let msg = compress::decompress(compressed, &bytes)
+                .chain_err(|| format!("failed to decompress {:x}", block.sha3()))?;
+            Ok(msg)
+        },
+        None => Ok(bytes),
+    }
+}
+
+fn load_sign_key(path: Option<String>) -> Result<Option<SensorKey>> {
+    match path {
+        Some(path) => {
+            let key = SensorKey::load(&path)
+                        .chain_err(|| format!("failed to load sensor key {:?}", path))?;
+            info!("signing messages with sensor key {}", key.key_id());
+            Ok(Some(key))
+        },
+        None => Ok(None),
+    }
+}
+
+fn open_source(source: Option<String>, seq_file: Option<String>) -> Result<Option<SourceCounter>> {
+    match (source, seq_file) {
+        (Some(source), Some(seq_file)) => {
+            let counter = SourceCounter::open(source, seq_file.as_str())
+                            .chain_err(|| format!("failed to open sequence file {:?}", seq_file))?;
+            Ok(Some(counter))
+        },
+        (Some(_), None) => Err("--source requires --seq-file".into()),
+        (None, Some(_)) => Err("--seq-file requires --source".into()),
+        (None, None) => Ok(None),
+    }
+}
+
+fn print_status(status: &Status, ledger: Option<&str>, as_json: bool) -> Result<()> {
+    if as_json {
+        let status = json!({
+            "version": status.version,
+            "ledger": ledger,
+            "uptime": status.uptime,
+            "head": format!("{:x}", status.head),
+            "session": format!("{:x}", status.session),
+            "session_blocks": status.session_blocks,
+            "datadir": status.datadir,
+            "sandbox": status.sandbox,
+            "config_fingerprint": status.config_fingerprint,
+        });
+        println!("{}", serde_json::to_string_pretty(&status)
+                            .chain_err(|| "failed to serialize status")?);
+    } else {
+        let sandbox = if status.sandbox.is_empty() {
+            "-".to_string()
+        } else {
+            status.sandbox.join(", ")
+        };
+
+        println!("version:        {}", status.version);
+        println!("uptime:         {}s", status.uptime);
+        println!("ledger:         {}", ledger.unwrap_or("-"));
+        println!("head:           {:x}", status.head);
+        println!("session:        {:x}", status.session);
+        println!("session blocks: {}", status.session_blocks);
+        println!("datadir:        {}", status.datadir);
+        println!("sandbox:        {}", sandbox);
+        println!("config:         {}", status.config_fingerprint);
+    }
+    Ok(())
+}
+
 fn run() -> Result<()> {
     env_logger::init();
 
@@ -60,18 +236,33 @@ fn run() -> Result<()> {
 
     let config = config::load_config();
 
-    let path = args.data_dir.unwrap_or_else(|| config.datadir().to_string());
-    let storage = DiskStorage::new(path);
+    let socket = args.socket.clone().unwrap_or_else(|| config.socket().to_string());
+    let ledger = args.ledger.as_ref().map(|name| name.as_str());
+    // retried writes are only committed once
+    let mut client = ClientBuilder::new(socket)
+        .retries(args.retries)
+        .request_ids(args.retries > 0)
+        .ledger(ledger);
+    if let Some(timeout) = args.timeout {
+        let timeout = match timeout {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        client = client.timeout(timeout);
+    }
+    let client = load_curve(client, &args, &config)?;
 
-    let socket = args.socket.unwrap_or_else(|| config.socket().to_string());
-    let client = ClientBuilder::new(socket);
+    let datadir = args.data_dir.clone().unwrap_or_else(|| config.datadir().to_string());
+    let mut storage = DiskStorage::new(ledger::path(datadir, ledger)?);
+    let (pub_key, sec_key) = config.ledger_keys(ledger)
+        .chain_err(|| "invalid ledger keys")?;
 
     use cli::tr1pctl::SubCommand;
     match args.subcommand {
         SubCommand::Init(matches) => {
             let (pk, sk) = crypto::gen_keypair();
-            let pk_path = Path::new(config.pub_key());
-            let sk_path = Path::new(config.sec_key());
+            let pk_path = Path::new(pub_key);
+            let sk_path = Path::new(sec_key);
 
             // TODO: create folder with correct permissions
 
@@ -98,7 +289,7 @@ fn run() -> Result<()> {
             }
         },
         SubCommand::Get(matches) => {
-            let longterm_pk = load_pubkey(config.pub_key())?;
+            let longterm_pk = load_pubkey(pub_key)?;
 
             let pointer = storage.resolve_pointer(matches.block).expect("failed to resolve pointer");
             let block = storage.get(&pointer).expect("failed to load block");
@@ -109,9 +300,16 @@ fn run() -> Result<()> {
                 println!("{:?}", block);
             } else if matches.parent {
                 println!("{:x}", block.prev());
-            } else if let Some(bytes) = block.msg() {
-                let mut stdout = io::stdout();
-                stdout.write_all(&bytes)?;
+            } else if block.msg().is_some() {
+                let key = load_decrypt_key(matches.decrypt_key.as_ref())?;
+                let bytes = match storage.get_msg(&block)? {
+                    Some(bytes) => bytes,
+                    None => return Err("message has been redacted".into()),
+                };
+                match plaintext(&block, bytes, key.as_ref())? {
+                    Some(bytes) => io::stdout().write_all(&bytes)?,
+                    None => return Err("message is encrypted for other auditors, use --decrypt-key".into()),
+                }
             }
         },
 
@@ -122,9 +320,10 @@ fn run() -> Result<()> {
         },
 
         SubCommand::Ls(matches) => {
-            let longterm_pk = load_pubkey(config.pub_key())?;
+            let longterm_pk = load_pubkey(pub_key)?;
 
             let range = storage.resolve_range(matches.spec).expect("failed to expand range");
+            let key = load_decrypt_key(matches.decrypt_key.as_ref())?;
 
             let mut stdout = io::stdout();
             for pointer in storage.expand_range(range)? {
@@ -133,27 +332,81 @@ fn run() -> Result<()> {
                 // TODO: verify session as well
                 block.verify_longterm(&longterm_pk).expect("verify_longterm");
 
-                if let Some(bytes) = block.msg() {
-                    stdout.write_all(&bytes)?;
+                if block.msg().is_some() {
+                    // the parts of a split line are printed as one line
+                    let continued = block.split()
+                        .map(|part| !part.is_first() || block.continued())
+                        .unwrap_or(false);
+
+                    // the parts of a transaction are numbered, the first one starts the group
+                    if let Some(part) = block.transaction() {
+                        write!(stdout, "[tx {}] ", part)?;
+                    }
+                    if let Some(spooled) = block.spooled() {
+                        write!(stdout, "[spooled {}] ", spooled)?;
+                    }
+                    if matches.show_origin && !continued {
+                        match (block.origin(), block.client_key(), block.syslog()) {
+                            (Some(origin), _, _) => write!(stdout, "[{}] ", origin)?,
+                            (None, Some(key), _) => write!(stdout, "[curve={}] ", key)?,
+                            (None, None, Some(syslog)) => write!(stdout, "[{}] ", syslog)?,
+                            (None, None, None) => write!(stdout, "[-] ")?,
+                        }
+                        if let Some(id) = block.request_id() {
+                            write!(stdout, "[id={}] ", id)?;
+                        }
+                        if let Some(seq) = block.sequence() {
+                            write!(stdout, "[seq {}] ", seq)?;
+                        }
+                        // not verified, see fsck
+                        if let Some(sig) = block.sensor_signature() {
+                            write!(stdout, "[sensor {}] ", sig.key_id)?;
+                        }
+                    }
+                    let bytes = match storage.get_msg(&block)? {
+                        Some(bytes) => bytes,
+                        None => {
+                            writeln!(stdout, "[redacted]")?;
+                            continue;
+                        },
+                    };
+                    match plaintext(&block, bytes, key.as_ref())? {
+                        Some(bytes) => stdout.write_all(&bytes)?,
+                        None => writeln!(stdout, "[encrypted for {}]", block.envelope().expect("message is encrypted"))?,
+                    }
                 }
             }
         },
 
         SubCommand::Write(matches) => {
-            let client = client.connect()?;
+            let source = open_source(matches.source, matches.seq_file)?;
+            let sign_key = load_sign_key(matches.sign_key)?;
+            let (client, spool) = connect_spooled(&client, matches.spool.as_ref())?;
 
             let mut pipe = InfoBlockPipe::new(client, stdin());
+            pipe.batch_size = matches.batch_size;
+            pipe.batch_delay = Duration::from_millis(matches.batch_delay);
+            pipe.transaction = matches.transaction;
+            pipe.spool = spool;
+            pipe.source = source;
+            pipe.sign_key = sign_key;
 
             match matches.size {
-                Some(size) => pipe.start_bytes(size),
-                None       => pipe.start_lines(),
+                Some(size) => pipe.start_bytes(size)?,
+                None       => pipe.start_lines()?,
             };
+            pipe.close()?;
         },
 
         SubCommand::From(matches) => {
-            let client = client.connect()?;
+            let source = open_source(matches.source, matches.seq_file)?;
+            let sign_key = load_sign_key(matches.sign_key)?;
+            let (client, spool) = connect_spooled(&client, matches.spool.as_ref())?;
 
             let size = matches.size;
+            let batch_size = matches.batch_size;
+            let batch_delay = Duration::from_millis(matches.batch_delay);
+            let transaction = matches.transaction;
 
             let prog = matches.prog;
             let args = matches.args;
@@ -167,13 +420,36 @@ fn run() -> Result<()> {
 
             let stdout = child.stdout.take().unwrap();
             let mut pipe = InfoBlockPipe::new(client, stdout);
-
-            match size {
+            pipe.batch_size = batch_size;
+            pipe.batch_delay = batch_delay;
+            pipe.transaction = transaction;
+            pipe.spool = spool;
+            pipe.source = source;
+            pipe.sign_key = sign_key;
+
+            let result = match size {
                 Some(size) => pipe.start_bytes(size),
                 None       => pipe.start_lines(),
-            };
+            }.and_then(|_| pipe.close());
 
             let _status = child.wait().expect("failed to wait on child");
+            result?;
+        },
+
+        SubCommand::Journal(matches) => {
+            let source = open_source(matches.source, matches.seq_file)?;
+            let sign_key = load_sign_key(matches.sign_key)?;
+            let (client, spool) = connect_spooled(&client, matches.spool.as_ref())?;
+
+            let mut pipe = InfoBlockPipe::new(client, stdin());
+            pipe.batch_size = matches.batch_size;
+            pipe.batch_delay = Duration::from_millis(matches.batch_delay);
+            pipe.spool = spool;
+            pipe.source = source;
+            pipe.sign_key = sign_key;
+
+            pipe.start_journal(matches.cursor_file.map(CursorFile::new))?;
+            pipe.close()?;
         },
 
         SubCommand::Rekey => {
@@ -186,7 +462,7 @@ fn run() -> Result<()> {
         },
 
         SubCommand::Fsck(matches) => {
-            let longterm_pk = load_pubkey(config.pub_key())?;
+            let longterm_pk = load_pubkey(pub_key)?;
 
             let paranoid = matches.paranoid;
 
@@ -194,6 +470,25 @@ fn run() -> Result<()> {
 
             let mut session = None;
 
+            // the policy is unknown until the range reaches an init block
+            let mut policy: Option<RekeyPolicy> = None;
+            let mut usage = KeyUsage::default();
+            let mut violations = 0;
+            let mut unverifiable = 0;
+
+            let mut transactions = TxTracker::default();
+            let mut broken_transactions = 0;
+
+            // sequences continue across sessions, they're only checked with --sources
+            let mut sources = SourceTracker::default();
+
+            let sensors = SensorRegistry::load(&config.sensors)
+                .chain_err(|| "failed to load sensor keys")?;
+            let mut invalid_sensors = 0;
+
+            let mut redacted = 0;
+            let mut corrupted_payloads = 0;
+
             // The first block in the spec parameter is trusted
             // If this is an init block this is non-fatal in paranoid mode
             let mut first_block = true;
@@ -219,6 +514,13 @@ fn run() -> Result<()> {
                             }
 
                             session = Some(*init.pubkey());
+                            policy = Some(RekeyPolicy::default());
+                            usage.reset();
+
+                            if !transactions.init() {
+                                print!("{} ... ", "transaction interrupted".red());
+                                broken_transactions += 1;
+                            }
                             // println!("ALERT: init: {:?}", session);
                         },
                         InnerBlock::Rekey(ref rekey) => {
@@ -228,6 +530,7 @@ fn run() -> Result<()> {
                             rekey.verify_session(&session.unwrap())?;
 
                             session = Some(*rekey.pubkey());
+                            usage.reset();
                             // println!("rekey: {:?}", session);
                         },
                         InnerBlock::Alert(ref alert) => {
@@ -236,7 +539,12 @@ fn run() -> Result<()> {
 
                             alert.verify_session(&session.unwrap())?;
 
+                            if transactions.alert(alert.bytes()) {
+                                print!("{} ... ", "transaction aborted".yellow());
+                            }
+
                             session = Some(*alert.pubkey());
+                            usage.reset();
                             // println!("alert: {:?}", session);
                         },
                         InnerBlock::Info(ref info) => {
@@ -245,6 +553,89 @@ fn run() -> Result<()> {
 
                             info.verify_session(&session.unwrap())?;
                             // println!("info");
+
+                            // the signatures cover the ciphertext, no key is needed
+                            if block.envelope().is_some() {
+                                print!("encrypted ... ");
+                            }
+                            if block.compressed().is_some() {
+                                print!("compressed ... ");
+                            }
+
+                            // the signatures only cover the commitment, the payload is stored separately
+                            let msg = if block.commitment().is_some() {
+                                match storage.get_msg(&block) {
+                                    Ok(Some(msg)) => Some(msg),
+                                    Ok(None) => {
+                                        print!("{} ... ", "redacted".yellow());
+                                        redacted += 1;
+                                        None
+                                    },
+                                    Err(err) => {
+                                        print!("{} ... ", format!("payload is corrupted: {}", err).red());
+                                        corrupted_payloads += 1;
+                                        None
+                                    },
+                                }
+                            } else {
+                                Some(info.clone_bytes())
+                            };
+
+                            // the key should have been replaced before this block
+                            if let Some(ref policy) = policy {
+                                if policy.is_due(&usage, None) {
+                                    print!("{} ... ", "rekey policy violated".red());
+                                    violations += 1;
+                                }
+                            }
+
+                            let part = block.transaction().or_else(|| block.split());
+                            if !transactions.info(part) {
+                                print!("{} ... ", "transaction interrupted".red());
+                                broken_transactions += 1;
+                            }
+
+                            // the signatures cover the compressed bytes, sensors sign the message
+                            let plain = match msg {
+                                Some(ref msg) if block.envelope().is_none() => decompress(&block, msg.clone()).map(Some),
+                                ref msg => Ok(msg.clone()),
+                            };
+
+                            match plain.map(|plain| sensors.verify_msg(&block, plain.as_ref().map(|msg| msg.as_slice()))) {
+                                Ok(SensorCheck::Unsigned) => (),
+                                Ok(SensorCheck::Valid(name)) => print!("sensor {} ... ", name),
+                                Ok(SensorCheck::Sealed(name)) => print!("sensor {} (encrypted) ... ", name),
+                                Ok(SensorCheck::Redacted(name)) => print!("sensor {} (redacted) ... ", name),
+                                Ok(SensorCheck::UnknownKey(key_id)) => print!("{} ... ", format!("unknown sensor key {}", key_id).yellow()),
+                                Ok(SensorCheck::Invalid(name)) => {
+                                    print!("{} ... ", format!("invalid signature of sensor {}", name).red());
+                                    invalid_sensors += 1;
+                                },
+                                Err(err) => {
+                                    print!("{} ... ", err.to_string().red());
+                                    corrupted_payloads += 1;
+                                },
+                            }
+
+                            match block.sequence() {
+                                Some(seq) if matches.sources => match sources.add(&Writer::of(&block), seq) {
+                                    Verdict::Ok => (),
+                                    Verdict::Gap(start, end) => print!("{} ... ", format!("{}: missing #{} to #{}", seq.source, start, end).red()),
+                                    Verdict::Duplicate => print!("{} ... ", format!("{} is a duplicate", seq).red()),
+                                    Verdict::Reordered => print!("{} ... ", format!("{} is out of order", seq).red()),
+                                },
+                                _ => (),
+                            }
+
+                            if let Some(announced) = block.rekey_policy() {
+                                if let Some(seconds) = announced.max_age() {
+                                    print!("{} ... ", format!("time limit of {} seconds can't be verified", seconds).yellow());
+                                    unverifiable += 1;
+                                }
+                                policy = Some(announced.clone());
+                            }
+                            // redacted payloads aren't counted, fsck can only miss a violation
+                            usage.add(msg.map(|msg| msg.len()).unwrap_or(0));
                         },
                     };
                 } else {
@@ -254,19 +645,118 @@ fn run() -> Result<()> {
                 println!("{}", "ok".green());
                 first_block = false;
             }
+
+            if transactions.is_open() {
+                println!("{}", "last transaction is incomplete".red());
+                broken_transactions += 1;
+            }
+
+            let mut broken_sources = 0;
+            for (&(ref writer, ref source), report) in sources.reports() {
+                if report.is_ok() {
+                    println!("source {} ({}): {}", source, writer, report);
+                } else {
+                    println!("source {} ({}): {}", source, writer, report.to_string().red());
+                    broken_sources += 1;
+                }
+            }
+
+            if redacted > 0 {
+                println!("{} payloads have been redacted", redacted);
+            }
+
+            if corrupted_payloads > 0 {
+                return Err(format!("{} payloads are corrupted", corrupted_payloads).into());
+            }
+
+            if broken_transactions > 0 {
+                return Err(format!("{} transactions are incomplete or interleaved", broken_transactions).into());
+            }
+
+            if invalid_sensors > 0 {
+                return Err(format!("{} info blocks have an invalid sensor signature", invalid_sensors).into());
+            }
+
+            if broken_sources > 0 {
+                return Err(format!("{} sources have missing, duplicate or reordered messages", broken_sources).into());
+            }
+
+            if violations > 0 {
+                return Err(format!("{} info blocks have been signed with a key that should have been replaced", violations).into());
+            }
+
+            if unverifiable > 0 {
+                return Err(format!("{} sessions have a rekey policy with a time limit that can't be verified", unverifiable).into());
+            }
+        },
+
+        SubCommand::Redact(matches) => {
+            let longterm_pk = load_pubkey(pub_key)?;
+
+            let pointer = storage.resolve_pointer(matches.block)?;
+            let block = storage.get(&pointer)?;
+            block.verify_longterm(&longterm_pk)?;
+
+            if block.commitment().is_none() {
+                return Err(format!("{:x} has no commitment, its payload is part of the block", pointer).into());
+            }
+
+            if storage.remove_payload(&pointer)? {
+                println!("redacted {:x}", pointer);
+            } else {
+                println!("{:x} has already been redacted", pointer);
+            }
         },
 
         SubCommand::Ping(matches) => {
             let mut client = client.connect()?;
 
-            let req = CtlRequest::Ping;
-            client.send(&req)?;
+            client.ping()?;
 
             if !matches.quiet {
                 println!("pong");
             }
         },
 
+        SubCommand::Status(matches) => {
+            let mut client = client.connect()?;
+
+            let status = client.status()?;
+            print_status(&status, ledger, matches.json)?;
+        },
+
+        #[cfg(feature="zmq")]
+        SubCommand::CurveKeygen(matches) => {
+            let (pk, sk) = rpc::curve::gen_keypair();
+
+            write_keyfile(Path::new(&matches.pub_key), pk.bytes(), 0o640, matches.force)?;
+            write_keyfile(Path::new(&matches.sec_key), sk.bytes(), 0o600, matches.force)?;
+
+            println!("{}", pk.to_z85());
+        },
+        #[cfg(not(feature="zmq"))]
+        SubCommand::CurveKeygen(_) => {
+            return Err("curve requires zmq, tr1pctl was built without zmq support".into());
+        },
+
+        SubCommand::SensorKeygen(matches) => {
+            let (pk, sk) = crypto::gen_keypair();
+
+            write_keyfile(Path::new(&matches.pub_key), &pk.0, 0o644, matches.force)?;
+            write_keyfile(Path::new(&matches.sec_key), &sk.0, 0o600, matches.force)?;
+
+            println!("{}", KeyId::of(&pk));
+        },
+
+        SubCommand::AuditorKeygen(matches) => {
+            let (pk, sk) = envelope::gen_keypair();
+
+            write_keyfile(Path::new(&matches.pub_key), &pk.0, 0o644, matches.force)?;
+            write_keyfile(Path::new(&matches.sec_key), &sk.0, 0o600, matches.force)?;
+
+            println!("{}", envelope::key_id(&pk));
+        },
+
         SubCommand::BashCompletion => {
             cli::gen_completions::<cli::tr1pctl::Args>("tr1pctl");
         }
@@ -8,16 +8,48 @@ extern crate error_chain;
 #[macro_use] extern crate log;
 
 use tr1pd::Result;
+use tr1pd::blocks::{self, Attribute, Origin};
+use tr1pd::compress::{self, Compressor};
+use tr1pd::recipe::BlockRecipe;
 use tr1pd::storage::DiskStorage;
-use tr1pd::engine::Engine;
+use tr1pd::syslog::{self, SyslogMessage};
+use tr1pd::engine::{self, Engine};
+use tr1pd::envelope::{self, Sealer};
+use tr1pd::hooks::{self, HookRunner};
+use tr1pd::ledger::{self, Ledgers};
+use tr1pd::metrics::{self, Metrics};
+use tr1pd::ratelimit::{RateLimiter, Verdict};
+use tr1pd::reload::Reloader;
+use tr1pd::rules::Rules;
+use tr1pd::signals::{Signal, Signals};
 use tr1pd::cli;
 use tr1pd::config;
+use tr1pd::dedup::{DedupWindow, Lookup};
 use tr1pd::crypto::{SignRing, PublicKey, SecretKey};
 use tr1pd::sandbox::{self, ResultExt};
-use tr1pd::rpc::{Server, CtlRequest, CtlResponse};
+use tr1pd::rpc::{self, Server, Request, ClientId, CtlRequest, CtlResponse, Hello, NackCode, Status};
+#[cfg(feature="zmq")]
+use tr1pd::rpc::{CurveKey, CurveServer};
 
+use log::LevelFilter;
+
+use std::env;
 use std::fs::File;
 use std::io::prelude::*;
+use std::net::IpAddr;
+use std::process;
+use std::slice;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Writes waiting for the engine thread, clients are rejected if this is full.
+const QUEUE_LEN: usize = 1024;
+/// How often the rate limiter is checked for clients that need a summary.
+const SUMMARY_POLL_SECS: u64 = 1;
+/// How often the engine checks if the session key expired.
+const REKEY_POLL_SECS: u64 = 1;
 
 
 fn load_keypair(pk: &str, sk: &str) -> Result<(PublicKey, SecretKey)> {
@@ -40,11 +72,76 @@ fn load_keypair(pk: &str, sk: &str) -> Result<(PublicKey, SecretKey)> {
     Ok((pk, sk))
 }
 
-fn run() -> Result<()> {
-    env_logger::init();
+#[cfg(feature="zmq")]
+fn load_curve(config: &config::Config) -> Result<Option<CurveServer>> {
+    let (pk, sk) = match config.curve_keypair() {
+        Some(keypair) => keypair,
+        None => return Ok(None),
+    };
 
-    sandbox::activate_stage1()
-        .chain_err(|| "sandbox stage1")?;
+    let public_key = CurveKey::load(pk)
+        .chain_err(|| "failed to load curve public key")?;
+    let secret_key = CurveKey::load(sk)
+        .chain_err(|| "failed to load curve secret key")?;
+
+    let mut clients = Vec::new();
+    for client in &config.daemon.curve_clients {
+        let key = CurveKey::from_z85(client)
+            .chain_err(|| format!("invalid curve client key: {:?}", client))?;
+        clients.push(key);
+    }
+    if clients.is_empty() {
+        return Err("curve is enabled, but no curve_clients are configured".into());
+    }
+
+    Ok(Some(CurveServer {
+        public_key,
+        secret_key,
+        clients,
+    }))
+}
+
+#[cfg(feature="zmq")]
+fn bind(config: &config::Config) -> Result<Server> {
+    let curve = load_curve(config)?;
+    let server = Server::bind_curve(config.socket(), curve.as_ref())?;
+    Ok(server)
+}
+
+#[cfg(not(feature="zmq"))]
+fn bind(config: &config::Config) -> Result<Server> {
+    if config.curve_keypair().is_some() {
+        return Err("curve requires zmq, tr1pd was built without zmq support".into());
+    }
+    let server = Server::bind(config.socket())?;
+    Ok(server)
+}
+
+/// Returns `true` if the verbosity is set with `RUST_LOG`, the config can
+/// only change it otherwise.
+fn init_logger() -> bool {
+    let mut builder = env_logger::Builder::new();
+    let from_env = match env::var("RUST_LOG") {
+        Ok(filters) => {
+            builder.parse(&filters);
+            true
+        },
+        Err(_) => {
+            builder.filter(None, LevelFilter::Trace);
+            false
+        },
+    };
+    builder.init();
+
+    if !from_env {
+        log::set_max_level(config::DEFAULT_LOG_LEVEL);
+    }
+
+    from_env
+}
+
+fn run() -> Result<()> {
+    let log_from_env = init_logger();
 
     let args = cli::tr1pd::parse();
 
@@ -53,40 +150,535 @@ fn run() -> Result<()> {
         return Ok(());
     }
 
-    let mut config = config::load_config();
+    let (mut config, config_path) = config::load_config_with_path();
 
     config.set_socket(args.socket);
     config.set_datadir(args.data_dir);
 
+    let rules = Rules::from_config(&config.rules)?;
+
+    // hooks are executed by a helper that is forked before the sandbox is active
+    let helper = if rules.has_hooks() {
+        Some(hooks::spawn()?)
+    } else {
+        None
+    };
+
+    sandbox::activate_stage1()
+        .chain_err(|| "sandbox stage1")?;
+
+    // the signals are received by their own thread, this needs to happen
+    // before any other thread is started
+    let signals = Signals::block()?;
+
     let (pk, sk) = load_keypair(&config.pub_key(), &config.sec_key())?;
+    let sealer = load_sealer(&config, None)?;
+
+    // the keys of all ledgers are loaded before we enter the chroot
+    let mut keys = Vec::new();
+    for name in config.ledgers.keys() {
+        ledger::validate_name(name)?;
+        let (pk, sk) = config.ledger_keys(Some(name))
+            .chain_err(|| "invalid ledger keys")?;
+        keys.push((name.clone(), load_keypair(pk, sk)?, load_sealer(&config, Some(name))?));
+    }
+
+    let hooks = match helper {
+        Some(helper) => Some(helper.configure(config.hook_user(), rules.hooks())?),
+        None => None,
+    };
+    let rules = Arc::new(rules);
+
+    // the datadir is rewritten when we enter the chroot
+    let info = DaemonInfo {
+        started: Instant::now(),
+        datadir: config.datadir().to_string(),
+        config_fingerprint: config.fingerprint(),
+    };
 
-    let mut server = Server::bind(config.socket())?;
+    let mut server = bind(&config)?;
+
+    let mut reloader = Reloader::new(config_path.as_ref().map(|p| p.as_path()),
+                                 config.socket(), log_from_env)?;
+    reloader.apply(&config)?;
+
+    // the listeners need to be bound before we enter the chroot
+    let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+    let limiter = Arc::new(Mutex::new(RateLimiter::new(config.daemon.rate_limit.clone())));
+    start_syslog(&config.syslog, &tx, &limiter)?;
+
+    let metrics = Metrics::new();
+    if let Some(ref addr) = config.daemon.metrics {
+        let listener = metrics::bind(addr)?;
+        metrics::serve(listener, metrics.clone());
+    }
 
     sandbox::activate_stage2(&mut config)
         .chain_err(|| "sandbox stage2")?;
 
-    let ring = SignRing::new(pk, sk);
-    let storage = DiskStorage::new(config.datadir()).into_engine();
-    let mut engine = Engine::start(storage, ring)?;
+    let engine = start_engine(&config, None, (pk, sk), sealer, &metrics, &rules, &hooks)?;
+    let mut ledgers = Ledgers::new(engine);
+    for (name, keypair, sealer) in keys {
+        let engine = start_engine(&config, Some(&name), keypair, sealer, &metrics, &rules, &hooks)?;
+        ledgers.insert(name, engine)?;
+    }
+
+    // the engine thread is the only writer, this keeps the chain in order
+    // while the frontend keeps serving other clients
+    let dedup = DedupWindow::new(config.dedup_window());
+    thread::spawn(move || {
+        let code = match commit_loop(ledgers, dedup, info, rx) {
+            Ok(_) => 0,
+            Err(err) => {
+                error!("failed to write final block: {:?}", err);
+                1
+            },
+        };
+        process::exit(code);
+    });
+
+    start_signals(signals, reloader, tx.clone());
+
+    if config.daemon.rate_limit.is_enabled() {
+        start_summaries(limiter.clone(), tx.clone());
+    }
 
     loop {
-        let msg = server.recv()?;
+        let req = server.recv()?;
+        metrics.request();
 
-        let reply = match msg {
-            CtlRequest::Ping => CtlResponse::Pong,
-            CtlRequest::Write(block) => match engine.recipe(block) {
-                Ok(pointer) => CtlResponse::Ack(pointer),
-                Err(err) => {
-                    error!("Write fail: {:?}", err);
-                    CtlResponse::Nack
+        match req.msg {
+            CtlRequest::Write(..) | CtlRequest::WriteBatch(..) => {
+                if let Some(reply) = limit(&limiter, &req) {
+                    count_nack(&metrics, &reply);
+                    server.reply(req.token, &reply)?;
+                    continue;
                 }
             },
+            CtlRequest::Status(_) => (),
+            _ => {
+                let reply = handle(&req.msg);
+                count_nack(&metrics, &reply);
+                server.reply(req.token, &reply)?;
+                continue;
+            },
         };
 
-        server.reply(&reply)?;
+        let token = req.token.clone();
+        match tx.try_send(Job::Rpc(req)) {
+            Ok(_) => (),
+            Err(TrySendError::Full(_)) => {
+                warn!("commit queue is full, rejecting request");
+                let reply = CtlResponse::nack(NackCode::RateLimited, "commit queue is full");
+                count_nack(&metrics, &reply);
+                server.reply(token, &reply)?;
+            },
+            Err(TrySendError::Disconnected(_)) => return Err("engine thread stopped".into()),
+        }
+    }
+}
+
+/// Start a new session on the chain of a ledger.
+fn start_engine(config: &config::Config, ledger: Option<&str>, (pk, sk): (PublicKey, SecretKey), sealer: Option<Sealer>,
+                metrics: &Arc<Metrics>, rules: &Arc<Rules>, hooks: &Option<HookRunner>) -> Result<Engine> {
+    let ring = SignRing::new(pk, sk);
+    let storage = DiskStorage::new(ledger::path(config.datadir(), ledger)?).into_engine();
+
+    let mut engine = Engine::start_with_policy(storage, ring, metrics.clone(), config.daemon.rekey.clone())?;
+    engine.set_rules(rules.clone(), hooks.clone());
+    if config.daemon.compress_threshold.is_some() && sealer.is_some() {
+        warn!("messages of {} are encrypted, they won't be compressed", ledger.unwrap_or("the default ledger"));
+    }
+    engine.set_compressor(config.daemon.compress_threshold.map(Compressor::new));
+    engine.set_sealer(sealer);
+    engine.set_redactable(config.ledger_redactable(ledger));
+
+    Ok(engine)
+}
+
+/// The public keys of the auditors of a ledger, if its payloads are encrypted.
+fn load_sealer(config: &config::Config, ledger: Option<&str>) -> Result<Option<Sealer>> {
+    let paths = config.ledger_auditors(ledger);
+    if paths.is_empty() {
+        return Ok(None);
+    }
+
+    let mut auditors = Vec::new();
+    for path in paths {
+        let key = envelope::load_public_key(path)
+            .chain_err(|| format!("failed to load auditor key {:?}", path))?;
+        auditors.push(key);
+    }
+
+    Ok(Some(Sealer::new(auditors)?))
+}
+
+/// Details about the daemon that are reported by `CtlRequest::Status`.
+struct DaemonInfo {
+    started: Instant,
+    datadir: String,
+    config_fingerprint: String,
+}
+
+/// Work for the engine thread.
+enum Job {
+    Rpc(Request),
+    Syslog(SyslogMessage),
+    Alert(Vec<u8>),
+    /// Commit what is already queued and stop the daemon
+    Shutdown(&'static str),
+}
+
+/// Returns a nack if the client exceeded its limits.
+fn limit(limiter: &Mutex<RateLimiter<ClientId>>, req: &Request) -> Option<CtlResponse> {
+    let mut limiter = limiter.lock().unwrap();
+    if !limiter.is_enabled() {
+        return None;
+    }
+
+    let sizes = match req.msg {
+        CtlRequest::Write(_, ref recipe, _) => recipe.sizes(),
+        CtlRequest::WriteBatch(_, ref recipes, _) => recipes.iter().flat_map(BlockRecipe::sizes).collect(),
+        _ => return None,
+    };
+
+    let client = req.client_id();
+    match limiter.check(&client, &sizes, Instant::now()) {
+        Verdict::Allow => None,
+        Verdict::TooLarge => {
+            debug!("rejecting oversized message from {}", client);
+            Some(CtlResponse::nack(NackCode::TooLarge, "message exceeds max_message_size"))
+        },
+        Verdict::Throttled => {
+            debug!("rate limiting {}", client);
+            Some(CtlResponse::nack(NackCode::RateLimited, "rate limit exceeded"))
+        },
+    }
+}
+
+/// Write an alert for clients that have been rate limited, the rejected data
+/// isn't lost without a trace.
+fn start_summaries(limiter: Arc<Mutex<RateLimiter<ClientId>>>, tx: mpsc::SyncSender<Job>) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(Duration::from_secs(SUMMARY_POLL_SECS));
+
+            let summaries = limiter.lock().unwrap().summaries(Instant::now());
+            for summary in summaries {
+                warn!("{}", summary);
+                if tx.send(Job::Alert(summary.to_string().into_bytes())).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+fn start_signals(signals: Signals, mut reloader: Reloader, tx: mpsc::SyncSender<Job>) {
+    thread::spawn(move || {
+        loop {
+            match signals.wait() {
+                Ok(Signal::Shutdown(name)) => {
+                    info!("received {}, shutting down", name);
+                    // writes that are already queued are committed first
+                    if tx.send(Job::Shutdown(name)).is_err() {
+                        return;
+                    }
+                },
+                Ok(Signal::Reload) => {
+                    info!("received SIGHUP, reloading config");
+                    if let Err(err) = reloader.reload() {
+                        error!("failed to reload config: {}", err);
+                    }
+                },
+                Err(err) => {
+                    error!("failed to wait for signals: {}", err);
+                    return;
+                },
+            }
+        }
+    });
+}
+
+fn start_syslog(config: &config::SyslogConfig, tx: &mpsc::SyncSender<Job>,
+                limiter: &Arc<Mutex<RateLimiter<ClientId>>>) -> Result<()> {
+    if let Some(ref path) = config.unix {
+        syslog::listen_unix(path, enqueue(tx.clone(), limiter.clone()))?;
+    }
+
+    if let Some(ref addr) = config.udp {
+        syslog::listen_udp(addr, enqueue(tx.clone(), limiter.clone()))?;
+    }
+
+    if let Some(ref addr) = config.tcp {
+        syslog::listen_tcp(addr, enqueue(tx.clone(), limiter.clone()))?;
+    }
+
+    Ok(())
+}
+
+/// Syslog messages wait for a free slot in the queue instead of being rejected.
+/// Senders that exceed the rate limit are dropped, they show up in the
+/// summaries like rpc clients.
+fn enqueue(tx: mpsc::SyncSender<Job>, limiter: Arc<Mutex<RateLimiter<ClientId>>>)
+           -> impl Fn(Option<IpAddr>, SyslogMessage) + Send + Clone {
+    move |peer, msg| {
+        {
+            let mut limiter = limiter.lock().unwrap();
+            if limiter.is_enabled() {
+                let client = ClientId::Syslog(peer);
+                if limiter.check(&client, &[msg.bytes.len()], Instant::now()) != Verdict::Allow {
+                    debug!("rate limiting {}", client);
+                    return;
+                }
+            }
+        }
+
+        if tx.send(Job::Syslog(msg)).is_err() {
+            error!("syslog: engine thread stopped");
+        }
+    }
+}
+
+/// Runs until a shutdown is requested.
+fn commit_loop(mut ledgers: Ledgers, mut dedup: DedupWindow, info: DaemonInfo, rx: mpsc::Receiver<Job>) -> engine::Result<()> {
+    loop {
+        match rx.recv_timeout(Duration::from_secs(REKEY_POLL_SECS)) {
+            Ok(Job::Shutdown(signal)) => return shutdown(&mut ledgers, &mut dedup, &info, &rx, signal),
+            Ok(job) => run_job(&mut ledgers, &mut dedup, &info, job),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+
+        // keys also expire while nothing is written
+        for (name, engine) in ledgers.iter_mut() {
+            if let Err(err) = engine.rekey_if_expired() {
+                error!("failed to rekey {}: {:?}", display_ledger(name), err);
+            }
+        }
+    }
+}
+
+fn display_ledger(name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("ledger {:?}", name),
+        None => "default ledger".to_string(),
+    }
+}
+
+/// Requests that arrived after the signal are rejected, the session of
+/// every ledger ends with a signed block that records the shutdown.
+fn shutdown(ledgers: &mut Ledgers, dedup: &mut DedupWindow, info: &DaemonInfo, rx: &mpsc::Receiver<Job>, signal: &str) -> engine::Result<()> {
+    for job in rx.try_iter().take(QUEUE_LEN) {
+        match job {
+            Job::Rpc(req) => {
+                let reply = CtlResponse::nack(NackCode::Unknown, "daemon is shutting down");
+                count_nack(ledgers.default_ledger().metrics(), &reply);
+
+                if let Err(err) = req.token.reply(&reply) {
+                    error!("failed to send reply: {:?}", err);
+                }
+            },
+            Job::Shutdown(_) => (),
+            // syslog senders can't retry, write what we received
+            job => run_job(ledgers, dedup, info, job),
+        }
+    }
+
+    let msg = format!("tr1pd: shutting down after {}", signal);
+    for (name, engine) in ledgers.iter_mut() {
+        engine.alert(msg.clone().into_bytes())?;
+        info!("final block has been written to {}", display_ledger(name));
+    }
+
+    Ok(())
+}
+
+fn run_job(ledgers: &mut Ledgers, dedup: &mut DedupWindow, info: &DaemonInfo, job: Job) {
+    match job {
+        Job::Rpc(req) => {
+            let client = req.client_id();
+            let Request { token, origin, msg } = req;
+
+            // the status is built here so it's consistent with the writes
+            let reply = match ledgers.get_mut(msg.ledger()) {
+                Some(engine) => match msg {
+                    CtlRequest::Status(_) => CtlResponse::Status(status(engine, info)),
+                    msg => commit(engine, dedup, &client, origin, msg),
+                },
+                None => CtlResponse::nack(NackCode::InvalidRequest,
+                                          format!("unknown ledger: {:?}", msg.ledger().unwrap_or_default())),
+            };
+            count_nack(ledgers.default_ledger().metrics(), &reply);
+
+            if let Err(err) = token.reply(&reply) {
+                error!("failed to send reply: {:?}", err);
+            }
+        },
+        Job::Syslog(msg) => {
+            let attributes = vec![Attribute::Syslog(msg.syslog)];
+
+            let engine = ledgers.default_ledger();
+            if let Err(err) = engine.recipe_with_attributes(BlockRecipe::Info(msg.bytes), attributes) {
+                error!("syslog: failed to write message: {:?}", err);
+            }
+        },
+        Job::Alert(bytes) => {
+            if let Err(err) = ledgers.default_ledger().alert(bytes) {
+                error!("failed to write alert: {:?}", err);
+            }
+        },
+        // shutdown is handled by commit_loop
+        Job::Shutdown(_) => (),
     }
 }
 
+fn status(engine: &Engine, info: &DaemonInfo) -> Status {
+    Status {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime: info.started.elapsed().as_secs(),
+        head: engine.head().clone(),
+        session: engine.session().clone(),
+        session_blocks: engine.session_blocks(),
+        datadir: info.datadir.clone(),
+        sandbox: sandbox::stages(),
+        config_fingerprint: info.config_fingerprint.clone(),
+    }
+}
+
+fn count_nack(metrics: &Arc<Metrics>, reply: &CtlResponse) {
+    if let CtlResponse::Nack(code, _) = *reply {
+        metrics.nack(code);
+    }
+}
+
+fn nack(err: &engine::Error) -> CtlResponse {
+    error!("Write fail: {:?}", err);
+
+    let code = match *err.kind() {
+        engine::ErrorKind::Blocks(blocks::ErrorKind::BlockTooLarge) => NackCode::TooLarge,
+        engine::ErrorKind::Envelope(envelope::ErrorKind::Blocks(blocks::ErrorKind::BlockTooLarge)) => NackCode::TooLarge,
+        engine::ErrorKind::Compress(compress::ErrorKind::Blocks(blocks::ErrorKind::BlockTooLarge)) => NackCode::TooLarge,
+        engine::ErrorKind::Storage(_) => NackCode::StorageFailure,
+        engine::ErrorKind::InvalidTransaction(_) => NackCode::InvalidRequest,
+        engine::ErrorKind::InvalidReplay(_) => NackCode::InvalidRequest,
+        engine::ErrorKind::InvalidSource(_) => NackCode::InvalidRequest,
+        engine::ErrorKind::InvalidSignedRecipe => NackCode::InvalidRequest,
+        _ => NackCode::Unknown,
+    };
+
+    CtlResponse::nack(code, err.to_string())
+}
+
+/// Requests that are answered by the frontend without touching the engine.
+fn handle(msg: &CtlRequest) -> CtlResponse {
+    match *msg {
+        CtlRequest::Ping => CtlResponse::Pong,
+        CtlRequest::Hello(ref hello) if hello.version < rpc::MIN_PROTOCOL_VERSION => {
+            CtlResponse::nack(NackCode::UnsupportedVersion,
+                              format!("protocol version {} is not supported", hello.version))
+        },
+        CtlRequest::Hello(_) => CtlResponse::Hello(Hello::current()),
+        CtlRequest::Write(..) | CtlRequest::WriteBatch(..) | CtlRequest::Status(_) => {
+            CtlResponse::nack(NackCode::InvalidRequest, "request goes through the commit queue")
+        },
+    }
+}
+
+fn commit(engine: &mut Engine, dedup: &mut DedupWindow, client: &ClientId, origin: Option<Origin>, msg: CtlRequest) -> CtlResponse {
+    let mut attributes: Vec<_> = origin
+        .map(Attribute::Origin)
+        .into_iter()
+        .collect();
+    if let ClientId::Curve(ref key) = *client {
+        attributes.push(Attribute::ClientKey(key.clone()));
+    }
+
+    match msg {
+        CtlRequest::Write(_, _, Some(ref id)) |
+        CtlRequest::WriteBatch(_, _, Some(ref id)) if !rpc::validate_request_id(id) => {
+            CtlResponse::nack(NackCode::InvalidRequest, "invalid request id")
+        },
+        CtlRequest::WriteBatch(_, ref blocks, _) if blocks.len() > rpc::MAX_BATCH_LEN => {
+            CtlResponse::nack(NackCode::TooLarge,
+                              format!("batch exceeds {} messages", rpc::MAX_BATCH_LEN))
+        },
+        CtlRequest::Write(ledger, block, Some(id)) => {
+            let ledger = ledger.as_ref().map(|name| name.as_str());
+
+            match dedup.check(client, ledger, &id, slice::from_ref(&block)) {
+                Lookup::Duplicate(reply) => {
+                    info!("request {:?} has already been committed", id);
+                    reply
+                },
+                Lookup::Conflict => conflict(&id),
+                Lookup::New => {
+                    attributes.push(Attribute::RequestId(id.clone()));
+                    match engine.recipe_with_attributes(block.clone(), attributes) {
+                        Ok(pointer) => {
+                            let reply = CtlResponse::Ack(pointer);
+                            dedup.insert(client, ledger, &id, slice::from_ref(&block), reply.clone());
+                            reply
+                        },
+                        Err(err) => nack(&err),
+                    }
+                },
+            }
+        },
+        CtlRequest::Write(_, block, None) => {
+            match engine.recipe_with_attributes(block, attributes) {
+                Ok(pointer) => CtlResponse::Ack(pointer),
+                Err(err) => nack(&err),
+            }
+        },
+        CtlRequest::WriteBatch(ledger, blocks, Some(id)) => {
+            let ledger = ledger.as_ref().map(|name| name.as_str());
+
+            match dedup.check(client, ledger, &id, &blocks) {
+                Lookup::Duplicate(reply) => {
+                    info!("request {:?} has already been committed", id);
+                    reply
+                },
+                Lookup::Conflict => conflict(&id),
+                Lookup::New => {
+                    attributes.push(Attribute::RequestId(id.clone()));
+                    let reply = commit_batch(engine, blocks.clone(), &attributes);
+                    // a partial batch is remembered too, the retry gets the same pointers
+                    if let CtlResponse::AckBatch(_) = reply {
+                        dedup.insert(client, ledger, &id, &blocks, reply.clone());
+                    }
+                    reply
+                },
+            }
+        },
+        CtlRequest::WriteBatch(_, blocks, None) => commit_batch(engine, blocks, &attributes),
+        msg => handle(&msg),
+    }
+}
+
+fn commit_batch(engine: &mut Engine, blocks: Vec<BlockRecipe>, attributes: &[Attribute]) -> CtlResponse {
+    let mut pointers = Vec::new();
+    for block in blocks {
+        match engine.recipe_with_attributes(block, attributes.to_vec()) {
+            Ok(pointer) => pointers.push(pointer),
+            Err(err) => {
+                if pointers.is_empty() {
+                    return nack(&err);
+                }
+                error!("Write fail: {:?}", err);
+                break;
+            }
+        }
+    }
+
+    CtlResponse::AckBatch(pointers)
+}
+
+fn conflict(id: &str) -> CtlResponse {
+    CtlResponse::nack(NackCode::InvalidRequest,
+                      format!("request id {:?} has already been used for a different message", id))
+}
+
 fn main() {
     if let Err(ref e) = run() {
         use error_chain::ChainedError; // trait which holds `display_chain`
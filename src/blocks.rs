@@ -1,8 +1,9 @@
 use sha3::{Digest, Sha3_256};
+use sodiumoxide::randombytes;
 
-use crypto::{self, PublicKey, Signable, Signed, Signature};
+use crypto::{self, PublicKey, SecretKey, Signable, Signed, Signature};
 use crypto::ring::SignRing;
-use wire::len_to_u16_vec;
+use wire::{self, len_to_u16_vec, u32_to_vec, u64_to_vec};
 
 use std::fmt;
 
@@ -12,6 +13,9 @@ mod errors {
         errors {
             CorruptedBlock
             InvalidBlockPointer
+            InvalidCommitment {
+                description("payload doesn't match the commitment")
+            }
             BlockTooLarge
             InvalidBlockIdentifier(b: u8) {
                 description("invalid block type identifier")
@@ -205,7 +209,183 @@ impl Block {
         (pointer, bytes)
     }
 
-    /// Return the encoded message of the block, if there's any.
+    /// Return the signed attributes of the block, only info blocks have them.
+    #[inline]
+    pub fn attributes(&self) -> &[Attribute] {
+        match self.inner {
+            InnerBlock::Info(ref block) => block.attributes(),
+            _ => &[],
+        }
+    }
+
+    /// Return the origin of the message, if the daemon recorded it.
+    #[inline]
+    pub fn origin(&self) -> Option<&Origin> {
+        self.attributes().iter()
+            .filter_map(|attr| match *attr {
+                Attribute::Origin(ref origin) => Some(origin),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Return the syslog metadata, if the message was received by the syslog listener.
+    #[inline]
+    pub fn syslog(&self) -> Option<&Syslog> {
+        self.attributes().iter()
+            .filter_map(|attr| match *attr {
+                Attribute::Syslog(ref syslog) => Some(syslog),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Return the rekey policy, if this block announces one.
+    #[inline]
+    pub fn rekey_policy(&self) -> Option<&RekeyPolicy> {
+        self.attributes().iter()
+            .filter_map(|attr| match *attr {
+                Attribute::RekeyPolicy(ref policy) => Some(policy),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Return the position in a transaction, if the block is part of one.
+    #[inline]
+    pub fn transaction(&self) -> Option<&TxPart> {
+        self.attributes().iter()
+            .filter_map(|attr| match *attr {
+                Attribute::Transaction(ref part) => Some(part),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Return the position in a line that has been split, if the message
+    /// was too large for a single block.
+    #[inline]
+    pub fn split(&self) -> Option<&TxPart> {
+        self.attributes().iter()
+            .filter_map(|attr| match *attr {
+                Attribute::Split(ref part) => Some(part),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Whether the block is part of a split line that continues the line of
+    /// a previous request.
+    #[inline]
+    pub fn continued(&self) -> bool {
+        self.attributes().iter()
+            .any(|attr| *attr == Attribute::Continued)
+    }
+
+    /// Return when and in which order the client spooled the message, if
+    /// it couldn't be delivered right away.
+    #[inline]
+    pub fn spooled(&self) -> Option<&Spooled> {
+        self.attributes().iter()
+            .filter_map(|attr| match *attr {
+                Attribute::Spooled(ref spooled) => Some(spooled),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Return the curve key of the zmq client that sent the message, if any.
+    #[inline]
+    pub fn client_key(&self) -> Option<&str> {
+        self.attributes().iter()
+            .filter_map(|attr| match *attr {
+                Attribute::ClientKey(ref key) => Some(key.as_str()),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Return the request ID the client sent with the message, if any.
+    #[inline]
+    pub fn request_id(&self) -> Option<&str> {
+        self.attributes().iter()
+            .filter_map(|attr| match *attr {
+                Attribute::RequestId(ref id) => Some(id.as_str()),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Return the source of the message and its sequence number, if the
+    /// writer numbered its messages.
+    #[inline]
+    pub fn sequence(&self) -> Option<&Sequence> {
+        self.attributes().iter()
+            .filter_map(|attr| match *attr {
+                Attribute::Sequence(ref seq) => Some(seq),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Return the signature of the sensor that wrote the message, if it
+    /// signed it. See [`SensorSignature::verify`].
+    ///
+    /// [`SensorSignature::verify`]: struct.SensorSignature.html#method.verify
+    #[inline]
+    pub fn sensor_signature(&self) -> Option<&SensorSignature> {
+        self.attributes().iter()
+            .filter_map(|attr| match *attr {
+                Attribute::SensorSignature(ref sig) => Some(sig),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Return how the payload has been encrypted, if it's only readable by
+    /// the auditors.
+    #[inline]
+    pub fn envelope(&self) -> Option<&Envelope> {
+        self.attributes().iter()
+            .filter_map(|attr| match *attr {
+                Attribute::Sealed(ref envelope) => Some(envelope),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Return the commitment to the payload, if it's stored next to the
+    /// block and can be redacted.
+    #[inline]
+    pub fn commitment(&self) -> Option<&Commitment> {
+        self.attributes().iter()
+            .filter_map(|attr| match *attr {
+                Attribute::Commitment(ref commitment) => Some(commitment),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Return how the payload has been compressed, if it has been.
+    #[inline]
+    pub fn compressed(&self) -> Option<&Compressed> {
+        self.attributes().iter()
+            .filter_map(|attr| match *attr {
+                Attribute::Compressed(ref compressed) => Some(compressed),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Return the encoded message of the block, if there's any. This is the
+    /// ciphertext if the block has an [`Envelope`], and empty if it has a
+    /// [`Commitment`], see [`BlockStorage::get_msg`]. The plaintext may
+    /// still be [`Compressed`].
+    ///
+    /// [`Envelope`]: struct.Envelope.html
+    /// [`Compressed`]: struct.Compressed.html
+    /// [`Commitment`]: struct.Commitment.html
+    /// [`BlockStorage::get_msg`]: ../storage/trait.BlockStorage.html#method.get_msg
     #[inline]
     pub fn msg(&self) -> Option<&Vec<u8>> {
         match self.inner {
@@ -240,9 +420,16 @@ impl Block {
 
     /// Build a new info block.
     #[inline]
-    pub fn info(prev: BlockPointer, mut keyring: &mut SignRing, bytes: Vec<u8>) -> Result<Block> {
+    pub fn info(prev: BlockPointer, keyring: &mut SignRing, bytes: Vec<u8>) -> Result<Block> {
+        Block::info_with_attributes(prev, keyring, bytes, Vec::new())
+    }
+
+    /// Build a new info block with additional signed attributes.
+    #[inline]
+    pub fn info_with_attributes(prev: BlockPointer, mut keyring: &mut SignRing, bytes: Vec<u8>, attributes: Vec<Attribute>) -> Result<Block> {
         validate_block_size(bytes.len())?;
-        let inner = InfoBlock::new(prev, &mut keyring, bytes);
+        validate_attributes(&attributes)?;
+        let inner = InfoBlock::with_attributes(prev, &mut keyring, bytes, attributes);
         Block::sign(InnerBlock::Info(inner), &keyring)
     }
 
@@ -284,7 +471,7 @@ impl Block {
             InnerBlock::Init(_)  => BlockIdentifier::Init,
             InnerBlock::Rekey(_) => BlockIdentifier::Rekey,
             InnerBlock::Alert(_) => BlockIdentifier::Alert,
-            InnerBlock::Info(_)  => BlockIdentifier::Info,
+            InnerBlock::Info(ref block) => block.identifier(),
         }
     }
 }
@@ -295,6 +482,8 @@ pub enum BlockIdentifier {
     Rekey,
     Alert,
     Info,
+    /// Info block with signed attributes
+    InfoAttrs,
 }
 
 impl BlockIdentifier {
@@ -305,6 +494,7 @@ impl BlockIdentifier {
             0x01 => Ok(BlockIdentifier::Rekey),
             0x02 => Ok(BlockIdentifier::Alert),
             0x03 => Ok(BlockIdentifier::Info),
+            0x04 => Ok(BlockIdentifier::InfoAttrs),
             _ => Err(ErrorKind::InvalidBlockIdentifier(x).into()),
         }
     }
@@ -316,6 +506,7 @@ impl BlockIdentifier {
             BlockIdentifier::Rekey => 0x01,
             BlockIdentifier::Alert => 0x02,
             BlockIdentifier::Info  => 0x03,
+            BlockIdentifier::InfoAttrs => 0x04,
         }
     }
 
@@ -489,13 +680,19 @@ impl Signable for AlertBlock {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct InfoBlock {
     prev: BlockPointer,
+    attributes: Vec<Attribute>,
     bytes: Vec<u8>,
 }
 
 impl InfoBlock {
     pub fn new(prev: BlockPointer, keyring: &mut SignRing, bytes: Vec<u8>) -> Signed<InfoBlock> {
+        InfoBlock::with_attributes(prev, keyring, bytes, Vec::new())
+    }
+
+    pub fn with_attributes(prev: BlockPointer, keyring: &mut SignRing, bytes: Vec<u8>, attributes: Vec<Attribute>) -> Signed<InfoBlock> {
         let block = InfoBlock {
             prev,
+            attributes,
             bytes,
         };
         let mut buf = Vec::new();
@@ -507,8 +704,13 @@ impl InfoBlock {
     }
 
     pub fn from_network(prev: BlockPointer, bytes: Vec<u8>, signature: Signature) -> InnerBlock {
+        InfoBlock::from_network_with_attributes(prev, Vec::new(), bytes, signature)
+    }
+
+    pub fn from_network_with_attributes(prev: BlockPointer, attributes: Vec<Attribute>, bytes: Vec<u8>, signature: Signature) -> InnerBlock {
         InnerBlock::Info(Signed(InfoBlock {
             prev,
+            attributes,
             bytes,
         }, signature))
     }
@@ -518,6 +720,10 @@ impl InfoBlock {
         &self.prev
     }
 
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
+
     pub fn bytes(&self) -> &Vec<u8> {
         &self.bytes
     }
@@ -525,13 +731,684 @@ impl InfoBlock {
     pub fn clone_bytes(&self) -> Vec<u8> {
         self.bytes.clone()
     }
+
+    /// Blocks without attributes use the original info format.
+    #[inline]
+    pub fn identifier(&self) -> BlockIdentifier {
+        if self.attributes.is_empty() {
+            BlockIdentifier::Info
+        } else {
+            BlockIdentifier::InfoAttrs
+        }
+    }
 }
 
 impl Signable for InfoBlock {
     fn encode(&self, buf: &mut Vec<u8>) {
         buf.extend(self.prev.0.iter());
-        buf.extend(BlockIdentifier::Info.to_vec());
+        buf.extend(self.identifier().to_vec());
+        if !self.attributes.is_empty() {
+            buf.push(self.attributes.len() as u8);
+            for attr in &self.attributes {
+                attr.encode(buf);
+            }
+        }
         buf.extend(len_to_u16_vec(self.bytes.len()).expect("block len overflow").iter());
         buf.extend(&self.bytes);
     }
 }
+
+
+/// Validate the attributes fit into the wire format.
+pub fn validate_attributes(attributes: &[Attribute]) -> Result<()> {
+    if attributes.len() > 255 {
+        return Err(ErrorKind::BlockTooLarge.into());
+    }
+
+    for attr in attributes {
+        let mut buf = Vec::new();
+        attr.encode_value(&mut buf);
+        validate_block_size(buf.len())?;
+    }
+
+    Ok(())
+}
+
+/// Signed metadata of an info block.
+///
+/// Each attribute is encoded as its type, a 16 bit length and the value.
+/// Unknown types are preserved, so older versions can still verify the block.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Attribute {
+    Origin(Origin),
+    Syslog(Syslog),
+    RekeyPolicy(RekeyPolicy),
+    Transaction(TxPart),
+    Split(TxPart),
+    Spooled(Spooled),
+    /// Chosen by the client, see `CtlRequest::Write`
+    RequestId(String),
+    Sequence(Sequence),
+    SensorSignature(SensorSignature),
+    Sealed(Envelope),
+    Commitment(Commitment),
+    Compressed(Compressed),
+    /// The parts of this split line continue the line of a previous request
+    Continued,
+    /// The curve key of a zmq client in Z85, unix clients have an `Origin`
+    ClientKey(String),
+    Unknown(u8, Vec<u8>),
+}
+
+impl Attribute {
+    pub fn type_byte(&self) -> u8 {
+        match *self {
+            Attribute::Origin(_) => 0x01,
+            Attribute::Syslog(_) => 0x02,
+            Attribute::RekeyPolicy(_) => 0x03,
+            Attribute::Transaction(_) => 0x04,
+            Attribute::Split(_) => 0x05,
+            Attribute::Spooled(_) => 0x06,
+            Attribute::RequestId(_) => 0x07,
+            Attribute::Sequence(_) => 0x08,
+            Attribute::SensorSignature(_) => 0x09,
+            Attribute::Sealed(_) => 0x0a,
+            Attribute::Commitment(_) => 0x0b,
+            Attribute::Compressed(_) => 0x0c,
+            Attribute::Continued => 0x0d,
+            Attribute::ClientKey(_) => 0x0e,
+            Attribute::Unknown(t, _) => t,
+        }
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let mut value = Vec::new();
+        self.encode_value(&mut value);
+
+        buf.push(self.type_byte());
+        buf.extend(len_to_u16_vec(value.len()).expect("attribute len overflow").iter());
+        buf.extend(&value);
+    }
+
+    fn encode_value(&self, buf: &mut Vec<u8>) {
+        match *self {
+            Attribute::Origin(ref origin) => origin.encode(buf),
+            Attribute::Syslog(ref syslog) => syslog.encode(buf),
+            Attribute::RekeyPolicy(ref policy) => policy.encode(buf),
+            Attribute::Transaction(ref part) => part.encode(buf),
+            Attribute::Split(ref part) => part.encode(buf),
+            Attribute::Spooled(ref spooled) => spooled.encode(buf),
+            Attribute::RequestId(ref id) => buf.extend(id.as_bytes()),
+            Attribute::Sequence(ref seq) => seq.encode(buf),
+            Attribute::SensorSignature(ref sig) => sig.encode(buf),
+            Attribute::Sealed(ref envelope) => envelope.encode(buf),
+            Attribute::Commitment(ref commitment) => buf.extend(&commitment.0),
+            Attribute::Compressed(ref compressed) => compressed.encode(buf),
+            Attribute::Continued => (),
+            Attribute::ClientKey(ref key) => buf.extend(key.as_bytes()),
+            Attribute::Unknown(_, ref bytes) => buf.extend(bytes),
+        }
+    }
+}
+
+/// Credentials of the local process that submitted a message.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Origin {
+    pub uid: u32,
+    pub gid: u32,
+    /// 0 if the platform doesn't report the pid of the peer
+    pub pid: u32,
+}
+
+impl Origin {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend(&u32_to_vec(self.uid));
+        buf.extend(&u32_to_vec(self.gid));
+        buf.extend(&u32_to_vec(self.pid));
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "uid={} gid={} pid={}", self.uid, self.gid, self.pid)
+    }
+}
+
+/// Header fields of a message that has been received over syslog.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Syslog {
+    pub facility: u8,
+    pub severity: u8,
+    pub hostname: Option<String>,
+    pub app_name: Option<String>,
+}
+
+impl Syslog {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.facility);
+        buf.push(self.severity);
+        encode_short_str(buf, self.hostname.as_ref());
+        encode_short_str(buf, self.app_name.as_ref());
+    }
+
+    pub fn facility_name(&self) -> &'static str {
+        match self.facility {
+            0 => "kern",
+            1 => "user",
+            2 => "mail",
+            3 => "daemon",
+            4 => "auth",
+            5 => "syslog",
+            6 => "lpr",
+            7 => "news",
+            8 => "uucp",
+            9 => "cron",
+            10 => "authpriv",
+            11 => "ftp",
+            12 => "ntp",
+            13 => "security",
+            14 => "console",
+            15 => "solaris-cron",
+            16 => "local0",
+            17 => "local1",
+            18 => "local2",
+            19 => "local3",
+            20 => "local4",
+            21 => "local5",
+            22 => "local6",
+            23 => "local7",
+            _ => "unknown",
+        }
+    }
+
+    pub fn severity_name(&self) -> &'static str {
+        match self.severity {
+            0 => "emerg",
+            1 => "alert",
+            2 => "crit",
+            3 => "err",
+            4 => "warning",
+            5 => "notice",
+            6 => "info",
+            7 => "debug",
+            _ => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for Syslog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{} {} {}",
+               self.facility_name(),
+               self.severity_name(),
+               self.hostname.as_ref().map(|x| x.as_str()).unwrap_or("-"),
+               self.app_name.as_ref().map(|x| x.as_str()).unwrap_or("-"))
+    }
+}
+
+/// When the daemon replaces the session key, the first limit that is reached
+/// triggers a rekey. Without limits the key is replaced after every info block.
+///
+/// The policy of a session is announced in an info block after the init
+/// block, sessions without announcement use the default. A limit of 0 is the
+/// same as no limit.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RekeyPolicy {
+    /// Number of info blocks that are signed with the same key
+    pub infos: Option<u64>,
+    /// Maximum age of a key that signed an info block
+    pub seconds: Option<u64>,
+    /// Number of payload bytes that are signed with the same key
+    pub bytes: Option<u64>,
+}
+
+fn limit(x: Option<u64>) -> Option<u64> {
+    x.and_then(|x| if x == 0 { None } else { Some(x) })
+}
+
+impl RekeyPolicy {
+    /// Rekey after every info block.
+    #[inline]
+    pub fn is_default(&self) -> bool {
+        match (limit(self.infos), limit(self.seconds), limit(self.bytes)) {
+            (None, None, None) | (Some(1), None, None) => true,
+            _ => false,
+        }
+    }
+
+    /// The maximum age of a key in seconds. Only the daemon can enforce it,
+    /// blocks don't have timestamps so it can't be verified later.
+    #[inline]
+    pub fn max_age(&self) -> Option<u64> {
+        limit(self.seconds)
+    }
+
+    /// Check if a key with this usage needs to be replaced before it signs
+    /// another info block. The age is only known to the daemon.
+    pub fn is_due(&self, usage: &KeyUsage, age: Option<u64>) -> bool {
+        if usage.infos == 0 {
+            return false;
+        }
+
+        if self.is_default() {
+            return true;
+        }
+
+        limit(self.infos).map(|max| usage.infos >= max).unwrap_or(false) ||
+            limit(self.bytes).map(|max| usage.bytes >= max).unwrap_or(false) ||
+            match (limit(self.seconds), age) {
+                (Some(max), Some(age)) => age >= max,
+                _ => false,
+            }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend(&u64_to_vec(self.infos.unwrap_or(0)));
+        buf.extend(&u64_to_vec(self.seconds.unwrap_or(0)));
+        buf.extend(&u64_to_vec(self.bytes.unwrap_or(0)));
+    }
+}
+
+impl fmt::Display for RekeyPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_default() {
+            return write!(f, "every info block");
+        }
+
+        let limits: Vec<_> = vec![
+            limit(self.infos).map(|x| format!("{} info blocks", x)),
+            limit(self.seconds).map(|x| format!("{} seconds", x)),
+            limit(self.bytes).map(|x| format!("{} bytes", x)),
+        ].into_iter().filter_map(|x| x).collect();
+
+        write!(f, "{}", limits.join(", "))
+    }
+}
+
+/// Position of an info block in a transaction, or in a line that has been
+/// split because it exceeded the maximum block size.
+///
+/// The parts are written in order, only blocks of the daemon itself can be
+/// between them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxPart {
+    /// Starts at 0
+    pub index: u16,
+    pub count: u16,
+}
+
+impl TxPart {
+    #[inline]
+    pub fn is_first(&self) -> bool {
+        self.index == 0
+    }
+
+    #[inline]
+    pub fn is_last(&self) -> bool {
+        self.index >= self.count.saturating_sub(1)
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend(&len_to_u16_vec(self.index as usize).expect("u16 can't overflow"));
+        buf.extend(&len_to_u16_vec(self.count as usize).expect("u16 can't overflow"));
+    }
+}
+
+impl fmt::Display for TxPart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", u32::from(self.index) + 1, self.count)
+    }
+}
+
+/// A message that has been replayed from the spool of a client.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Spooled {
+    /// Sequence number in the spool of the client
+    pub seq: u64,
+    /// Unix time the client spooled the message
+    pub time: u64,
+}
+
+impl Spooled {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend(&u64_to_vec(self.seq));
+        buf.extend(&u64_to_vec(self.time));
+    }
+}
+
+impl fmt::Display for Spooled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} at {}", self.seq, self.time)
+    }
+}
+
+/// The maximum length of the name of a source.
+pub const MAX_SOURCE_LEN: usize = 64;
+
+/// Source names are printable ASCII without spaces.
+pub fn validate_source(source: &str) -> bool {
+    !source.is_empty() &&
+        source.len() <= MAX_SOURCE_LEN &&
+        source.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Position of a message in the stream of its source. Each source numbers
+/// its messages without gaps, across restarts of the writer and the daemon.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sequence {
+    pub source: String,
+    /// Starts at 1
+    pub seq: u64,
+}
+
+impl Sequence {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend(&u64_to_vec(self.seq));
+        buf.extend(self.source.as_bytes());
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}#{}", self.source, self.seq)
+    }
+}
+
+/// Identifies the key of a sensor, the first 8 bytes of the sha3 of the
+/// public key.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct KeyId(pub [u8; 8]);
+
+impl KeyId {
+    #[inline]
+    pub fn of(pubkey: &PublicKey) -> KeyId {
+        KeyId::of_bytes(&pubkey.0)
+    }
+
+    /// Works for all kinds of public keys, eg. the keys of auditors.
+    pub fn of_bytes(pubkey: &[u8]) -> KeyId {
+        let sha3 = Sha3_256::digest(pubkey);
+        let mut id = [0; 8];
+        id.copy_from_slice(&sha3.as_slice()[..8]);
+        KeyId(id)
+    }
+}
+
+impl fmt::Display for KeyId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for x in &self.0 {
+            write!(f, "{:02x}", x)?
+        }
+        Ok(())
+    }
+}
+
+/// Prefix of the bytes a sensor signs, they can't be mistaken for a block.
+const SENSOR_CONTEXT: &[u8] = b"tr1pd sensor message\x00";
+
+/// Signature of the sensor that wrote the message, it's created before the
+/// message is sent to the daemon. The daemon can't forge messages of a
+/// sensor, but it could still drop or repeat them. The sequence number is
+/// covered as well if the message has one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SensorSignature {
+    pub key_id: KeyId,
+    pub signature: Signature,
+}
+
+impl SensorSignature {
+    pub fn sign(seq: Option<&Sequence>, msg: &[u8], pubkey: &PublicKey, seckey: &SecretKey) -> SensorSignature {
+        SensorSignature {
+            key_id: KeyId::of(pubkey),
+            signature: crypto::sign(&sensor_payload(seq, msg), seckey),
+        }
+    }
+
+    /// Verify the signature with the public key of the sensor.
+    pub fn verify(&self, seq: Option<&Sequence>, msg: &[u8], pubkey: &PublicKey) -> Result<()> {
+        if self.key_id != KeyId::of(pubkey) {
+            bail!(ErrorKind::Crypto(crypto::ErrorKind::InvalidSignature));
+        }
+        crypto::verify(&self.signature, &sensor_payload(seq, msg), pubkey)?;
+        Ok(())
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend(&self.key_id.0);
+        buf.extend(self.signature.0.iter());
+    }
+}
+
+fn sensor_payload(seq: Option<&Sequence>, msg: &[u8]) -> Vec<u8> {
+    let mut buf = SENSOR_CONTEXT.to_vec();
+    match seq {
+        Some(seq) => {
+            let mut encoded = Vec::new();
+            seq.encode(&mut encoded);
+            buf.extend(&len_to_u16_vec(encoded.len()).expect("sequence len overflow"));
+            buf.extend(&encoded);
+        },
+        None => buf.extend(&[0x00, 0x00]),
+    }
+    buf.extend(msg);
+    buf
+}
+
+/// The payload of the block is encrypted, see the `envelope` module. Only
+/// the auditors can decrypt it, the signatures cover the ciphertext.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub nonce: Vec<u8>,
+    pub recipients: Vec<Recipient>,
+}
+
+/// The payload key, sealed for one auditor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recipient {
+    pub key_id: KeyId,
+    pub sealed_key: Vec<u8>,
+}
+
+impl Envelope {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend(&self.nonce);
+        buf.push(self.recipients.len() as u8);
+        for recipient in &self.recipients {
+            buf.extend(&recipient.key_id.0);
+            buf.extend(&recipient.sealed_key);
+        }
+    }
+}
+
+impl fmt::Display for Envelope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ids: Vec<_> = self.recipients.iter()
+            .map(|recipient| recipient.key_id.to_string())
+            .collect();
+        write!(f, "{}", ids.join(","))
+    }
+}
+
+pub const SALT_LEN: usize = 32;
+
+/// Salted sha3 of a payload that is stored next to the block, the
+/// signatures and the pointer only cover the commitment. The payload can be
+/// removed with `tr1pctl redact` without breaking the chain, the salt keeps
+/// short messages from being guessed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Commitment(pub [u8; 32]);
+
+impl Commitment {
+    /// Returns the commitment and the bytes that need to be stored, the
+    /// salt followed by the payload.
+    pub fn commit(msg: &[u8]) -> (Commitment, Vec<u8>) {
+        let mut stored = randombytes::randombytes(SALT_LEN);
+        stored.extend(msg);
+        (Commitment::of(&stored), stored)
+    }
+
+    fn of(stored: &[u8]) -> Commitment {
+        let sha3 = Sha3_256::digest(stored);
+        let mut digest = [0; 32];
+        digest.copy_from_slice(sha3.as_slice());
+        Commitment(digest)
+    }
+
+    /// Verify the stored bytes and return the payload.
+    pub fn open<'a>(&self, stored: &'a [u8]) -> Result<&'a [u8]> {
+        if stored.len() < SALT_LEN || Commitment::of(stored) != *self {
+            bail!(ErrorKind::InvalidCommitment);
+        }
+        Ok(&stored[SALT_LEN..])
+    }
+}
+
+impl fmt::Display for Commitment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for x in &self.0 {
+            write!(f, "{:02x}", x)?
+        }
+        Ok(())
+    }
+}
+
+/// Algorithm of a compressed payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    Deflate,
+    /// Blocks of newer versions can still be verified
+    Unknown(u8),
+}
+
+impl Codec {
+    pub fn from_byte(x: u8) -> Codec {
+        match x {
+            0x01 => Codec::Deflate,
+            x => Codec::Unknown(x),
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        match *self {
+            Codec::Deflate => 0x01,
+            Codec::Unknown(x) => x,
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Codec::Deflate => write!(f, "deflate"),
+            Codec::Unknown(x) => write!(f, "unknown codec {:02x}", x),
+        }
+    }
+}
+
+/// The payload is compressed, see the `compress` module. The signatures
+/// cover the compressed bytes, an encrypted payload is compressed before it
+/// is encrypted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Compressed {
+    pub codec: Codec,
+    /// Length of the uncompressed payload
+    pub len: u32,
+}
+
+impl Compressed {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.codec.to_byte());
+        buf.extend(&u32_to_vec(self.len));
+    }
+}
+
+impl fmt::Display for Compressed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, {} bytes", self.codec, self.len)
+    }
+}
+
+/// Alerts that start with this close a transaction that couldn't be written
+/// completely.
+pub const TX_ABORTED: &str = "tr1pd: transaction aborted";
+
+/// Follows the transactions in a range of blocks, see `tr1pctl fsck`.
+#[derive(Debug, Default)]
+pub struct TxTracker {
+    open: Option<TxPart>,
+    /// The range may start in the middle of a transaction
+    seen_info: bool,
+}
+
+impl TxTracker {
+    /// Returns `false` if the info block breaks the transaction that is
+    /// open, or if it's part of a transaction that never started.
+    pub fn info(&mut self, part: Option<&TxPart>) -> bool {
+        let ok = match (self.open.as_ref(), part) {
+            (Some(open), Some(part)) => open.index.checked_add(1) == Some(part.index) && part.count == open.count,
+            (Some(_), None) => false,
+            (None, Some(part)) => part.index < part.count && (part.is_first() || !self.seen_info),
+            (None, None) => true,
+        };
+
+        self.seen_info = true;
+        self.open = match part {
+            Some(part) if !part.is_last() => Some(part.clone()),
+            _ => None,
+        };
+
+        ok
+    }
+
+    /// An alert of the daemon, a transaction that failed half way is closed
+    /// by an alert that starts with [`TX_ABORTED`]. Returns `true` if it
+    /// aborted the open transaction.
+    ///
+    /// [`TX_ABORTED`]: constant.TX_ABORTED.html
+    pub fn alert(&mut self, bytes: &[u8]) -> bool {
+        if self.open.is_some() && bytes.starts_with(TX_ABORTED.as_bytes()) {
+            self.open = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// A new session started, returns `false` if a transaction was open.
+    pub fn init(&mut self) -> bool {
+        let ok = self.open.is_none();
+        self.open = None;
+        self.seen_info = true;
+        ok
+    }
+
+    #[inline]
+    pub fn is_open(&self) -> bool {
+        self.open.is_some()
+    }
+}
+
+/// What has been signed with the current session key.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct KeyUsage {
+    pub infos: u64,
+    pub bytes: u64,
+}
+
+impl KeyUsage {
+    #[inline]
+    pub fn add(&mut self, bytes: usize) {
+        self.infos += 1;
+        self.bytes += bytes as u64;
+    }
+
+    #[inline]
+    pub fn reset(&mut self) {
+        *self = KeyUsage::default();
+    }
+}
+
+/// Strings with an 8 bit length, missing values are encoded as empty string.
+fn encode_short_str(buf: &mut Vec<u8>, value: Option<&String>) {
+    wire::encode_str(buf, value.map(|x| x.as_str()).unwrap_or(""), 255);
+}
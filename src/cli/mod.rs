@@ -6,7 +6,10 @@ use structopt::clap::Shell;
 
 use std::io;
 
+#[cfg(feature="zmq")]
 pub const TR1PD_SOCKET: &str = "ipc:///run/tr1pd/tr1pd.sock";
+#[cfg(not(feature="zmq"))]
+pub const TR1PD_SOCKET: &str = "unix:///run/tr1pd/tr1pd.sock";
 pub const TR1PD_DATADIR: &str = "/var/lib/tr1pd";
 
 #[inline]
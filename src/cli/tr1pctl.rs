@@ -17,6 +17,32 @@ pub struct Args {
                 long = "data-dir",
                 env = "TR1PD_DATADIR")]
     pub data_dir: Option<String>,
+    #[structopt(short = "L",
+                long = "ledger",
+                env = "TR1PD_LEDGER",
+                help = "Use a named ledger instead of the default ledger")]
+    pub ledger: Option<String>,
+    #[structopt(long = "curve-pk",
+                env = "TR1PD_CURVE_PK",
+                help = "Client public key for curve encrypted sockets")]
+    pub curve_pk: Option<String>,
+    #[structopt(long = "curve-sk",
+                env = "TR1PD_CURVE_SK",
+                help = "Client secret key for curve encrypted sockets")]
+    pub curve_sk: Option<String>,
+    #[structopt(long = "curve-server",
+                env = "TR1PD_CURVE_SERVER",
+                help = "Public curve key of the daemon")]
+    pub curve_server: Option<String>,
+    #[structopt(long = "timeout",
+                env = "TR1PD_TIMEOUT",
+                help = "Seconds to wait for the daemon, 0 waits forever")]
+    pub timeout: Option<u64>,
+    #[structopt(long = "retries",
+                env = "TR1PD_RETRIES",
+                default_value = "0",
+                help = "Retry failed requests on a new connection")]
+    pub retries: usize,
     #[structopt(subcommand)]
     pub subcommand: SubCommand,
 }
@@ -56,6 +82,10 @@ pub enum SubCommand {
                 name = "from",
                 about = "Write command output to ledger")]
     From(FromCmd),
+    #[structopt(author = "",
+                name = "journal",
+                about = "Write journal entries in the export format from stdin to the ledger")]
+    Journal(JournalCmd),
     #[structopt(author = "",
                 name = "rekey",
                 about = "Explicitly write a rekey block")]
@@ -64,10 +94,30 @@ pub enum SubCommand {
                 name = "fsck",
                 about = "Verify ledger")]
     Fsck(FsckCmd),
+    #[structopt(author = "",
+                name = "redact",
+                about = "Remove the payload of a block, its commitment stays in the ledger")]
+    Redact(RedactCmd),
     #[structopt(author = "",
                 name = "ping",
                 about = "Ping the daemon process")]
     Ping(PingCmd),
+    #[structopt(author = "",
+                name = "status",
+                about = "Show the state of the daemon process")]
+    Status(StatusCmd),
+    #[structopt(author = "",
+                name = "curve-keygen",
+                about = "Generate a keypair for curve encrypted sockets")]
+    CurveKeygen(CurveKeygenCmd),
+    #[structopt(author = "",
+                name = "sensor-keygen",
+                about = "Generate a keypair for signing messages of a sensor")]
+    SensorKeygen(SensorKeygenCmd),
+    #[structopt(author = "",
+                name = "auditor-keygen",
+                about = "Generate a keypair for reading encrypted messages")]
+    AuditorKeygen(AuditorKeygenCmd),
     #[structopt(author = "",
                 name = "bash-completion",
                 about = "Generate bash completion script for the tr1pd command.")]
@@ -91,6 +141,9 @@ pub struct GetCmd {
                 long = "parent",
                 help = "Print the pointer to the parent")]
     pub parent: bool,
+    #[structopt(long = "decrypt-key",
+                help = "Decrypt the message with the secret key of an auditor")]
+    pub decrypt_key: Option<String>,
     #[structopt(parse(try_from_str = "SpecPointer::parse"),
                 help = "The block to select")]
     pub block: SpecPointer,
@@ -98,6 +151,12 @@ pub struct GetCmd {
 
 #[derive(StructOpt, Debug)]
 pub struct LsCmd {
+    #[structopt(long = "show-origin",
+                help = "Show the credentials of the process that wrote the block")]
+    pub show_origin: bool,
+    #[structopt(long = "decrypt-key",
+                help = "Decrypt messages with the secret key of an auditor")]
+    pub decrypt_key: Option<String>,
     #[structopt(default_value = "..",
                 parse(try_from_str = "Spec::parse_range"),
                 help = "Specify range to verify")]
@@ -111,6 +170,31 @@ pub struct WriteCmd {
                 parse(try_from_str = "recipe::parse_size"),
                 help = "Use buffer size instead of lines")]
     pub size: Option<usize>,
+    #[structopt(long = "batch-size",
+                default_value = "64",
+                parse(try_from_str = "recipe::parse_batch_size"),
+                help = "Maximum number of messages sent to the daemon at once")]
+    pub batch_size: usize,
+    #[structopt(long = "batch-delay",
+                default_value = "100",
+                help = "Milliseconds to wait for more messages before a batch is sent")]
+    pub batch_delay: u64,
+    #[structopt(long = "transaction",
+                help = "Write all messages as one transaction after stdin is closed")]
+    pub transaction: bool,
+    #[structopt(long = "spool",
+                env = "TR1PD_SPOOL",
+                help = "Spool messages in this directory while the daemon is unreachable")]
+    pub spool: Option<String>,
+    #[structopt(long = "source",
+                help = "Number the messages of this source, requires --seq-file")]
+    pub source: Option<String>,
+    #[structopt(long = "seq-file",
+                help = "Store the next sequence number of the source in this file")]
+    pub seq_file: Option<String>,
+    #[structopt(long = "sign-key",
+                help = "Sign each message with the secret key of the sensor")]
+    pub sign_key: Option<String>,
 }
 
 #[derive(StructOpt, Debug)]
@@ -120,12 +204,66 @@ pub struct FromCmd {
                 parse(try_from_str = "recipe::parse_size"),
                 help = "Use buffer size instead of lines")]
     pub size: Option<usize>,
+    #[structopt(long = "batch-size",
+                default_value = "64",
+                parse(try_from_str = "recipe::parse_batch_size"),
+                help = "Maximum number of messages sent to the daemon at once")]
+    pub batch_size: usize,
+    #[structopt(long = "batch-delay",
+                default_value = "100",
+                help = "Milliseconds to wait for more messages before a batch is sent")]
+    pub batch_delay: u64,
+    #[structopt(long = "transaction",
+                help = "Write all messages as one transaction after the program exited")]
+    pub transaction: bool,
+    #[structopt(long = "spool",
+                env = "TR1PD_SPOOL",
+                help = "Spool messages in this directory while the daemon is unreachable")]
+    pub spool: Option<String>,
+    #[structopt(long = "source",
+                help = "Number the messages of this source, requires --seq-file")]
+    pub source: Option<String>,
+    #[structopt(long = "seq-file",
+                help = "Store the next sequence number of the source in this file")]
+    pub seq_file: Option<String>,
+    #[structopt(long = "sign-key",
+                help = "Sign each message with the secret key of the sensor")]
+    pub sign_key: Option<String>,
     #[structopt(help = "Program to execute")]
     pub prog: String,
     #[structopt(help = "Program arguments")]
     pub args: Vec<String>,
 }
 
+#[derive(StructOpt, Debug)]
+pub struct JournalCmd {
+    #[structopt(long = "cursor-file",
+                help = "Store the cursor of the last written entry and resume from it")]
+    pub cursor_file: Option<String>,
+    #[structopt(long = "spool",
+                env = "TR1PD_SPOOL",
+                help = "Spool messages in this directory while the daemon is unreachable")]
+    pub spool: Option<String>,
+    #[structopt(long = "source",
+                help = "Number the messages of this source, requires --seq-file")]
+    pub source: Option<String>,
+    #[structopt(long = "seq-file",
+                help = "Store the next sequence number of the source in this file")]
+    pub seq_file: Option<String>,
+    #[structopt(long = "sign-key",
+                help = "Sign each message with the secret key of the sensor")]
+    pub sign_key: Option<String>,
+    #[structopt(long = "batch-size",
+                default_value = "64",
+                parse(try_from_str = "recipe::parse_batch_size"),
+                help = "Maximum number of messages sent to the daemon at once")]
+    pub batch_size: usize,
+    #[structopt(long = "batch-delay",
+                default_value = "100",
+                help = "Milliseconds to wait for more messages before a batch is sent")]
+    pub batch_delay: u64,
+}
+
 #[derive(StructOpt, Debug)]
 pub struct FsckCmd {
     #[structopt(default_value = "..",
@@ -142,6 +280,16 @@ pub struct FsckCmd {
                 long = "paranoid",
                 help = "Consider 2nd init block within range fatal")]
     pub paranoid: bool,
+    #[structopt(long = "sources",
+                help = "Verify the sequence numbers of each source")]
+    pub sources: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct RedactCmd {
+    #[structopt(parse(try_from_str = "SpecPointer::parse"),
+                help = "The block to redact")]
+    pub block: SpecPointer,
 }
 
 #[derive(StructOpt, Debug)]
@@ -151,6 +299,46 @@ pub struct PingCmd {
     pub quiet: bool,
 }
 
+#[derive(StructOpt, Debug)]
+pub struct StatusCmd {
+    #[structopt(long = "json",
+                help = "Print the status as json")]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct SensorKeygenCmd {
+    #[structopt(long = "force",
+                help = "Overwrite existing keypair")]
+    pub force: bool,
+    #[structopt(help = "Path to the public key")]
+    pub pub_key: String,
+    #[structopt(help = "Path to the secret key")]
+    pub sec_key: String,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct AuditorKeygenCmd {
+    #[structopt(long = "force",
+                help = "Overwrite existing keypair")]
+    pub force: bool,
+    #[structopt(help = "Path to the public key")]
+    pub pub_key: String,
+    #[structopt(help = "Path to the secret key")]
+    pub sec_key: String,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct CurveKeygenCmd {
+    #[structopt(long = "force",
+                help = "Overwrite existing keypair")]
+    pub force: bool,
+    #[structopt(help = "Path to the public key")]
+    pub pub_key: String,
+    #[structopt(help = "Path to the secret key")]
+    pub sec_key: String,
+}
+
 pub fn parse() -> Args {
     Args::from_args()
 }
@@ -0,0 +1,99 @@
+//! Compression of payloads, log lines and binary blocks often compress well.
+//!
+//! The daemon compresses messages of clients that reach a threshold, the
+//! signatures cover the compressed bytes. Readers decompress the payload
+//! after it has been decrypted.
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use blocks::{self, Codec, Compressed};
+
+use std::io::{Read, Write};
+
+mod errors {
+    use std::io;
+    use blocks::Codec;
+
+    error_chain! {
+        errors {
+            UnsupportedCodec(codec: Codec) {
+                description("unsupported compression")
+                display("unsupported compression: {}", codec)
+            }
+            InvalidLength(expected: u32, actual: usize) {
+                description("decompressed payload has the wrong length")
+                display("decompressed payload has {} bytes instead of {}", actual, expected)
+            }
+        }
+        links {
+            Blocks(::blocks::Error, ::blocks::ErrorKind);
+        }
+        foreign_links {
+            Io(io::Error);
+        }
+    }
+}
+pub use self::errors::{Result, Error, ErrorKind};
+
+
+/// Compresses messages that are at least `threshold` bytes long.
+#[derive(Debug, Clone)]
+pub struct Compressor {
+    threshold: usize,
+}
+
+impl Compressor {
+    #[inline]
+    pub fn new(threshold: usize) -> Compressor {
+        Compressor {
+            threshold,
+        }
+    }
+
+    /// Returns `None` if the message is too short or doesn't get any
+    /// smaller, it's stored as it is then.
+    pub fn compress(&self, msg: &[u8]) -> Result<Option<(Compressed, Vec<u8>)>> {
+        if msg.len() < self.threshold {
+            return Ok(None);
+        }
+        blocks::validate_block_size(msg.len())?;
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(msg)?;
+        let bytes = encoder.finish()?;
+
+        if bytes.len() >= msg.len() {
+            return Ok(None);
+        }
+
+        let compressed = Compressed {
+            codec: Codec::Deflate,
+            len: msg.len() as u32,
+        };
+        Ok(Some((compressed, bytes)))
+    }
+}
+
+/// Decompress a payload, the result has to match the length in the
+/// attribute. Payloads never exceed the size of a block when they're
+/// decompressed.
+pub fn decompress(compressed: &Compressed, bytes: &[u8]) -> Result<Vec<u8>> {
+    let len = compressed.len as usize;
+    blocks::validate_block_size(len)?;
+
+    let mut msg = Vec::with_capacity(len);
+    match compressed.codec {
+        Codec::Deflate => {
+            DeflateDecoder::new(bytes)
+                .take(len as u64 + 1)
+                .read_to_end(&mut msg)?;
+        },
+        codec => bail!(ErrorKind::UnsupportedCodec(codec)),
+    }
+
+    if msg.len() != len {
+        bail!(ErrorKind::InvalidLength(compressed.len, msg.len()));
+    }
+    Ok(msg)
+}
@@ -1,7 +1,13 @@
+use log::LevelFilter;
+use sha3::{Digest, Sha3_256};
 use toml;
 
+use blocks::RekeyPolicy;
 use cli;
+use dedup;
+use hooks;
 
+use std::collections::BTreeMap;
 use std::env;
 use std::io::Read;
 use std::fs::File;
@@ -12,6 +18,12 @@ mod errors {
     use std::io;
 
     error_chain! {
+        errors {
+            InvalidValue(key: &'static str, value: String) {
+                description("invalid config value")
+                display("invalid value for {}: {:?}", key, value)
+            }
+        }
         foreign_links {
             Toml(toml::de::Error);
             Io(io::Error);
@@ -20,23 +32,30 @@ mod errors {
 }
 pub use self::errors::{Result, Error, ErrorKind};
 
+/// The log verbosity if neither `RUST_LOG` nor `log_level` is set.
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Error;
 
 #[inline]
 pub fn load_config() -> Config {
+    load_config_with_path().0
+}
+
+/// Load the config and return the path it was loaded from, if any.
+pub fn load_config_with_path() -> (Config, Option<PathBuf>) {
     let mut userpath = env::home_dir().unwrap();
     userpath.push(".config/tr1pd.toml");
 
     let globalpath = PathBuf::from("/etc/tr1pd/tr1pd.toml");
 
-    for path in &[userpath, globalpath] {
+    for path in vec![userpath, globalpath] {
         if let Ok(config) = load_configfile(&path) {
             info!("using config from {:?}", path);
-            return config;
+            return (config, Some(path));
         }
     }
 
     info!("using default config");
-    Config::default()
+    (Config::default(), None)
 }
 
 #[inline]
@@ -57,6 +76,15 @@ pub struct Config {
     pub daemon: DaemonConfig,
     #[serde(default)]
     pub security: SecurityConfig,
+    #[serde(default)]
+    pub syslog: SyslogConfig,
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+    #[serde(default)]
+    pub ledgers: BTreeMap<String, LedgerConfig>,
+    /// Paths to the public keys of sensors that sign their messages, by name
+    #[serde(default)]
+    pub sensors: BTreeMap<String, String>,
 }
 
 impl Config {
@@ -111,6 +139,91 @@ impl Config {
             None => "/etc/tr1pd/lt.sk",
         }
     }
+
+    /// Paths to the long-term keypair of a ledger. Ledgers without their own
+    /// keypair use the one of the daemon.
+    pub fn ledger_keys(&self, ledger: Option<&str>) -> Result<(&str, &str)> {
+        let config = match ledger.and_then(|name| self.ledgers.get(name)) {
+            Some(config) => config,
+            None => return Ok((self.pub_key(), self.sec_key())),
+        };
+
+        match (config.pub_key.as_ref(), config.sec_key.as_ref()) {
+            (Some(pk), Some(sk)) => Ok((pk, sk)),
+            (None, None) => Ok((self.pub_key(), self.sec_key())),
+            _ => Err(ErrorKind::InvalidValue("ledgers", ledger.unwrap_or_default().to_string()).into()),
+        }
+    }
+
+    /// Paths to the public keys of the auditors of a ledger, payloads are
+    /// stored in cleartext if there are none.
+    pub fn ledger_auditors(&self, ledger: Option<&str>) -> &[String] {
+        match ledger.and_then(|name| self.ledgers.get(name)) {
+            Some(&LedgerConfig { auditors: Some(ref auditors), .. }) => auditors,
+            _ => &self.daemon.auditors,
+        }
+    }
+
+    /// Whether the payloads of a ledger are stored next to their blocks, so
+    /// they can be redacted.
+    pub fn ledger_redactable(&self, ledger: Option<&str>) -> bool {
+        match ledger.and_then(|name| self.ledgers.get(name)) {
+            Some(&LedgerConfig { redactable: Some(redactable), .. }) => redactable,
+            _ => self.daemon.redactable,
+        }
+    }
+
+    /// Hash of the effective configuration, to tell if two daemons use the
+    /// same settings.
+    pub fn fingerprint(&self) -> String {
+        // going through a value puts the tables after the plain values
+        let value = toml::Value::try_from(self).expect("failed to serialize config");
+        let toml = value.to_string();
+        let hash = Sha3_256::digest(toml.as_bytes());
+        hash.as_slice().iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// The configured log verbosity, `RUST_LOG` takes precedence.
+    pub fn log_level(&self) -> Result<Option<LevelFilter>> {
+        match self.daemon.log_level {
+            Some(ref level) => level.parse()
+                .map(Some)
+                .map_err(|_| ErrorKind::InvalidValue("log_level", level.clone()).into()),
+            None => Ok(None),
+        }
+    }
+
+    /// The configured permissions of the socket, as octal string.
+    pub fn socket_mode(&self) -> Result<Option<u32>> {
+        match self.daemon.socket_mode {
+            Some(ref mode) => match u32::from_str_radix(mode, 8) {
+                Ok(bits) if bits <= 0o777 => Ok(Some(bits)),
+                _ => Err(ErrorKind::InvalidValue("socket_mode", mode.clone()).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    #[inline]
+    pub fn hook_user(&self) -> &str {
+        self.daemon.hook_user.as_ref().map(|x| x.as_str()).unwrap_or(hooks::DEFAULT_USER)
+    }
+
+    #[inline]
+    pub fn dedup_window(&self) -> usize {
+        self.daemon.dedup_window.unwrap_or(dedup::DEFAULT_WINDOW)
+    }
+
+    /// Returns the paths to the curve keypair if curve is enabled.
+    #[inline]
+    pub fn curve_keypair(&self) -> Option<(&str, &str)> {
+        match (self.daemon.curve_pub_key.as_ref(), self.daemon.curve_sec_key.as_ref()) {
+            (Some(pk), Some(sk)) => Some((pk, sk)),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -120,6 +233,64 @@ pub struct DaemonConfig {
 
     pub pub_key: Option<String>,
     pub sec_key: Option<String>,
+
+    pub curve_pub_key: Option<String>,
+    pub curve_sec_key: Option<String>,
+    /// z85 encoded public keys of clients that are allowed to connect
+    #[serde(default)]
+    pub curve_clients: Vec<String>,
+
+    /// Address of the prometheus endpoint, eg. `127.0.0.1:9163`
+    pub metrics: Option<String>,
+
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// When the session key is replaced, defaults to every info block
+    #[serde(default)]
+    pub rekey: RekeyPolicy,
+    /// Number of request IDs that are remembered to detect retries, 0
+    /// disables this
+    pub dedup_window: Option<usize>,
+    /// Paths to the public keys of auditors, payloads are only readable by them
+    #[serde(default)]
+    pub auditors: Vec<String>,
+    /// Blocks only contain a commitment to the payload, see `tr1pctl redact`
+    #[serde(default)]
+    pub redactable: bool,
+    /// Compress messages of at least this many bytes, disabled by default
+    pub compress_threshold: Option<usize>,
+    /// Hooks run as this user if tr1pd has been started as root, defaults to `nobody`
+    pub hook_user: Option<String>,
+
+    /// `error`, `warn`, `info`, `debug` or `trace`, reloaded on SIGHUP
+    pub log_level: Option<String>,
+    /// Permissions of the socket as octal string, eg. `0770`, reloaded on SIGHUP
+    pub socket_mode: Option<String>,
+}
+
+/// Limits for each client, unix sockets are limited by uid and zmq sockets
+/// by connection. Everything is unlimited by default.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Sustained number of messages per second
+    pub messages_per_second: Option<u64>,
+    /// Number of messages that can be sent at once, defaults to one second
+    pub messages_burst: Option<u64>,
+    /// Sustained number of payload bytes per second
+    pub bytes_per_second: Option<u64>,
+    /// Number of bytes that can be sent at once, defaults to one second
+    pub bytes_burst: Option<u64>,
+    /// Reject messages that are larger than this
+    pub max_message_size: Option<usize>,
+}
+
+impl RateLimitConfig {
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.messages_per_second.is_some() ||
+            self.bytes_per_second.is_some() ||
+            self.max_message_size.is_some()
+    }
 }
 
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -127,3 +298,40 @@ pub struct SecurityConfig {
     #[serde(default)]
     pub strict_chroot: bool,
 }
+
+/// Write an alert if an info block matches, either `contains` or `regex` is
+/// required.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleConfig {
+    pub name: String,
+    /// Match messages that contain this string
+    pub contains: Option<String>,
+    /// Match messages with a regular expression
+    pub regex: Option<String>,
+    /// Program and arguments that are executed if the rule matches
+    pub hook: Option<Vec<String>>,
+}
+
+/// A ledger with its own chain in the datadir, see `tr1pctl -L`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LedgerConfig {
+    /// Long-term keypair of this ledger, both keys need to be set
+    pub pub_key: Option<String>,
+    pub sec_key: Option<String>,
+    /// Overrides the auditors of the daemon, an empty list disables
+    /// encryption for this ledger
+    pub auditors: Option<Vec<String>>,
+    /// Overrides `redactable` of the daemon
+    pub redactable: Option<bool>,
+}
+
+/// Listeners for syslog messages, all of them are disabled by default.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyslogConfig {
+    /// Path of a unix datagram socket
+    pub unix: Option<String>,
+    /// Address to receive udp messages on, eg. `127.0.0.1:514`
+    pub udp: Option<String>,
+    /// Address to accept tcp connections on, eg. `127.0.0.1:514`
+    pub tcp: Option<String>,
+}
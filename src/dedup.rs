@@ -0,0 +1,96 @@
+//! Writes that are repeated with the same request ID are only committed once.
+//!
+//! A client can't tell if a request that timed out has been committed, the
+//! daemon remembers the replies of the most recent request IDs so a retry
+//! gets the original pointers instead of a second copy of the blocks.
+use sha3::{Digest, Sha3_256};
+
+use recipe::BlockRecipe;
+use rpc::{ClientId, CtlResponse};
+
+use std::collections::{HashMap, VecDeque};
+
+/// Default number of request IDs that are remembered.
+pub const DEFAULT_WINDOW: usize = 1024;
+
+
+/// Request IDs are scoped to a client and a ledger, `None` is the default
+/// ledger. A client can't replay or guess the IDs of somebody else.
+type Key = (ClientId, Option<String>, String);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lookup {
+    /// The ID hasn't been seen, or it fell out of the window
+    New,
+    /// The request has already been committed, this is the original reply
+    Duplicate(CtlResponse),
+    /// The ID has already been used for a different message
+    Conflict,
+}
+
+/// The last `capacity` request IDs and the replies they have been acked with.
+#[derive(Debug)]
+pub struct DedupWindow {
+    capacity: usize,
+    entries: HashMap<Key, (Vec<u8>, CtlResponse)>,
+    order: VecDeque<Key>,
+}
+
+impl DedupWindow {
+    /// A capacity of 0 disables deduplication.
+    pub fn new(capacity: usize) -> DedupWindow {
+        DedupWindow {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn check(&self, client: &ClientId, ledger: Option<&str>, id: &str, recipes: &[BlockRecipe]) -> Lookup {
+        let key = (client.clone(), ledger.map(|x| x.to_string()), id.to_string());
+
+        match self.entries.get(&key) {
+            Some(&(ref digest, ref reply)) if *digest == fingerprint(recipes) => Lookup::Duplicate(reply.clone()),
+            Some(_) => Lookup::Conflict,
+            None => Lookup::New,
+        }
+    }
+
+    /// Remember a committed request, the oldest one is forgotten if the
+    /// window is full.
+    pub fn insert(&mut self, client: &ClientId, ledger: Option<&str>, id: &str, recipes: &[BlockRecipe], reply: CtlResponse) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let key = (client.clone(), ledger.map(|x| x.to_string()), id.to_string());
+        if self.entries.insert(key.clone(), (fingerprint(recipes), reply)).is_none() {
+            self.order.push_back(key);
+        }
+
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// The hash of the encoded recipes, a retry sends the exact same bytes.
+fn fingerprint(recipes: &[BlockRecipe]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for recipe in recipes {
+        recipe.encode(&mut buf);
+    }
+    Sha3_256::digest(&buf).as_slice().to_vec()
+}
@@ -1,13 +1,41 @@
-use blocks::{Block, BlockPointer};
+use blocks::{self, Block, BlockPointer, Attribute, Commitment, KeyUsage, RekeyPolicy, Sequence, Spooled, TxPart};
+use compress::Compressor;
 use crypto::SignRing;
-use recipe::BlockRecipe;
+use envelope::{self, Sealer};
+use hooks::{Event, HookRunner};
+use metrics::Metrics;
+use recipe::{self, BlockRecipe};
+use rules::Rules;
+use spool::SpoolEntry;
 use storage::{StorageEngine, BlockStorage};
 
+use std::sync::Arc;
+use std::time::Instant;
+
 
 mod errors {
     error_chain! {
+        errors {
+            InvalidTransaction(reason: &'static str) {
+                description("invalid transaction")
+                display("invalid transaction: {}", reason)
+            }
+            InvalidReplay(reason: &'static str) {
+                description("invalid spool replay")
+                display("invalid spool replay: {}", reason)
+            }
+            InvalidSource(source: String) {
+                description("invalid source")
+                display("invalid source: {:?}", source)
+            }
+            InvalidSignedRecipe {
+                description("only single messages can be signed by a sensor")
+            }
+        }
         links {
             Blocks(::blocks::Error, ::blocks::ErrorKind);
+            Compress(::compress::Error, ::compress::ErrorKind);
+            Envelope(::envelope::Error, ::envelope::ErrorKind);
             Storage(::storage::Error, ::storage::ErrorKind);
         }
     }
@@ -18,10 +46,43 @@ pub struct Engine {
     storage: StorageEngine,
     ring: SignRing,
     head: BlockPointer,
+    /// The init block of the current session.
+    session: BlockPointer,
+    session_blocks: u64,
+    policy: RekeyPolicy,
+    /// What has been signed with the current session key.
+    usage: KeyUsage,
+    key_created: Instant,
+    /// Shared by the engines of all ledgers
+    rules: Arc<Rules>,
+    hooks: Option<HookRunner>,
+    /// Payloads of clients are compressed if they're large enough
+    compressor: Option<Compressor>,
+    /// Payloads of clients are only readable by the auditors
+    sealer: Option<Sealer>,
+    /// Payloads of clients are stored next to their blocks
+    redactable: bool,
+    metrics: Arc<Metrics>,
 }
 
 impl Engine {
+    #[inline]
     pub fn start(storage: StorageEngine, ring: SignRing) -> Result<Engine> {
+        Engine::start_with_metrics(storage, ring, Metrics::new())
+    }
+
+    /// Same as [`Engine::start`], but the counters are shared with the caller.
+    ///
+    /// [`Engine::start`]: #method.start
+    pub fn start_with_metrics(storage: StorageEngine, ring: SignRing, metrics: Arc<Metrics>) -> Result<Engine> {
+        Engine::start_with_policy(storage, ring, metrics, RekeyPolicy::default())
+    }
+
+    /// Same as [`Engine::start_with_metrics`], but the session key is
+    /// replaced according to `policy` instead of after every info block.
+    ///
+    /// [`Engine::start_with_metrics`]: #method.start_with_metrics
+    pub fn start_with_policy(storage: StorageEngine, ring: SignRing, metrics: Arc<Metrics>, policy: RekeyPolicy) -> Result<Engine> {
         // TODO: check if this is the first block
         // TODO: write genesis block if yes
         // TODO: build an init+alert otherwise
@@ -42,6 +103,17 @@ impl Engine {
             storage,
             ring,
             head,
+            session: BlockPointer::empty(),
+            session_blocks: 0,
+            policy,
+            usage: KeyUsage::default(),
+            key_created: Instant::now(),
+            rules: Arc::new(Rules::default()),
+            hooks: None,
+            compressor: None,
+            sealer: None,
+            redactable: false,
+            metrics,
         };
 
         engine.init()?;
@@ -49,51 +121,498 @@ impl Engine {
         Ok(engine)
     }
 
+    /// Messages that match one of the rules are followed by an alert.
+    pub fn set_rules(&mut self, rules: Arc<Rules>, hooks: Option<HookRunner>) {
+        self.rules = rules;
+        self.hooks = hooks;
+    }
+
+    /// Compress the messages of clients, the rules still match the
+    /// uncompressed messages. Messages that are encrypted aren't compressed,
+    /// their length would leak how much they have in common with the
+    /// plaintext an attacker got into the same message.
+    pub fn set_compressor(&mut self, compressor: Option<Compressor>) {
+        self.compressor = compressor;
+    }
+
+    /// Encrypt the messages of clients for the auditors, the rules still
+    /// match the plaintext. Messages of the daemon itself aren't encrypted.
+    pub fn set_sealer(&mut self, sealer: Option<Sealer>) {
+        self.sealer = sealer;
+    }
+
+    /// Store the messages of clients next to their blocks, the blocks only
+    /// contain a commitment so the messages can be redacted later. Encrypted
+    /// messages are committed to as ciphertext.
+    pub fn set_redactable(&mut self, redactable: bool) {
+        self.redactable = redactable;
+    }
+
     /*
     pub fn get(&self, pointer: &BlockPointer) -> Result<Block, storage::Error> {
         self.db.get(pointer)
     }
     */
 
+    fn push(&mut self, block: &Block) -> Result<()> {
+        match self.storage.push(block) {
+            Ok(pointer) => {
+                self.head = pointer;
+                self.session_blocks += 1;
+                Ok(())
+            },
+            Err(err) => {
+                self.metrics.storage_error();
+                Err(err.into())
+            },
+        }
+    }
+
+    fn new_key(&mut self) {
+        self.usage.reset();
+        self.key_created = Instant::now();
+    }
+
+    /// Start a new session, a policy that isn't the default is announced
+    /// right after the init block. The announcement counts against the
+    /// policy like any other info block.
     pub fn init(&mut self) -> Result<Block> {
         let block = Block::init(self.head.clone(), &mut self.ring)?;
-        self.head = self.storage.push(&block)?;
+        self.session_blocks = 0;
+        self.push(&block)?;
+        self.session = self.head.clone();
+        self.new_key();
+        self.metrics.init_block();
+
+        if !self.policy.is_default() {
+            let msg = format!("rekey policy: {}", self.policy);
+            let attributes = vec![Attribute::RekeyPolicy(self.policy.clone())];
+            self.info_with_attributes(msg.into_bytes(), attributes)?;
+
+            let age = self.key_age();
+            if self.policy.is_due(&self.usage, Some(age)) {
+                self.rekey()?;
+            }
+        }
+
         Ok(block)
     }
 
     pub fn rekey(&mut self) -> Result<Block> {
         let block = Block::rekey(self.head.clone(), &mut self.ring)?;
-        self.head = self.storage.push(&block)?;
+        self.push(&block)?;
+        self.new_key();
+        self.metrics.rekey_block();
         Ok(block)
     }
 
     pub fn alert(&mut self, bytes: Vec<u8>) -> Result<Block> {
         let block = Block::alert(self.head.clone(), &mut self.ring, bytes)?;
-        self.head = self.storage.push(&block)?;
+        self.push(&block)?;
+        self.new_key();
+        self.metrics.alert_block();
         Ok(block)
     }
 
+    fn key_age(&self) -> u64 {
+        self.key_created.elapsed().as_secs()
+    }
+
+    /// Replace a session key that signed info blocks and is older than the
+    /// policy allows. This should be called periodically, a key doesn't
+    /// expire while the engine is idle otherwise.
+    pub fn rekey_if_expired(&mut self) -> Result<Option<Block>> {
+        let expired = match self.policy.seconds {
+            Some(max) if max > 0 => self.usage.infos > 0 && self.key_age() >= max,
+            _ => false,
+        };
+
+        if expired {
+            self.rekey().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
     pub fn info(&mut self, bytes: Vec<u8>) -> Result<Block> {
-        let block = Block::info(self.head.clone(), &mut self.ring, bytes)?;
-        self.head = self.storage.push(&block)?;
+        self.info_with_attributes(bytes, Vec::new())
+    }
+
+    pub fn info_with_attributes(&mut self, bytes: Vec<u8>, attributes: Vec<Attribute>) -> Result<Block> {
+        let len = bytes.len();
+        let block = Block::info_with_attributes(self.head.clone(), &mut self.ring, bytes, attributes)?;
+        self.push(&block)?;
+        self.usage.add(len);
+        self.metrics.info_block(len);
+        Ok(block)
+    }
+
+    /// Same as [`Engine::info_with_attributes`], but the block only contains
+    /// a commitment to the message. The payload is written first, a block
+    /// without its payload has always been redacted.
+    ///
+    /// [`Engine::info_with_attributes`]: #method.info_with_attributes
+    pub fn info_committed(&mut self, bytes: Vec<u8>, attributes: Vec<Attribute>) -> Result<Block> {
+        let len = bytes.len();
+        blocks::validate_block_size(len)?;
+
+        let (commitment, stored) = Commitment::commit(&bytes);
+        let mut attributes = attributes;
+        attributes.push(Attribute::Commitment(commitment));
+
+        let block = Block::info_with_attributes(self.head.clone(), &mut self.ring, Vec::new(), attributes)?;
+        if let Err(err) = self.storage.write_payload(&block.sha3(), stored) {
+            self.metrics.storage_error();
+            return Err(err.into());
+        }
+        self.push(&block)?;
+        self.usage.add(len);
+        self.metrics.info_block(len);
         Ok(block)
     }
 
     pub fn recipe(&mut self, recipe: BlockRecipe) -> Result<BlockPointer> {
+        self.recipe_with_attributes(recipe, Vec::new())
+    }
+
+    /// Same as [`Engine::recipe`], but info blocks also get the attributes
+    /// the daemon collected about the request. Returns the rekey block if the
+    /// policy required one, the info block otherwise.
+    ///
+    /// [`Engine::recipe`]: #method.recipe
+    pub fn recipe_with_attributes(&mut self, recipe: BlockRecipe, attributes: Vec<Attribute>) -> Result<BlockPointer> {
+        let start = Instant::now();
         let block = match recipe {
             BlockRecipe::Rekey => {
                 self.rekey()?
             },
             BlockRecipe::Info(info) => {
-                self.info(info)?;
-                self.rekey()?
+                self.commit_info(info, attributes)?
+            },
+            BlockRecipe::Transaction(parts) => {
+                self.commit_parts(parts, attributes, Attribute::Transaction)?
+            },
+            BlockRecipe::SplitLine(parts) => {
+                self.commit_split_line(None, parts, attributes, None)?
+            },
+            BlockRecipe::ContinuedLine(context, parts) => {
+                self.validate_payload(context.len())?;
+                self.commit_split_line(Some(context), parts, attributes, None)?
+            },
+            BlockRecipe::SequencedLine(seq, parts) => {
+                if !blocks::validate_source(&seq.source) {
+                    bail!(ErrorKind::InvalidSource(seq.source));
+                }
+                self.commit_split_line(None, parts, attributes, Some(seq))?
+            },
+            BlockRecipe::Replay(entries) => {
+                self.commit_replay(entries, attributes)?
+            },
+            BlockRecipe::Sequenced(seq, info) => {
+                if !blocks::validate_source(&seq.source) {
+                    bail!(ErrorKind::InvalidSource(seq.source));
+                }
+                let mut attributes = attributes;
+                attributes.push(Attribute::Sequence(seq));
+                self.commit_info(info, attributes)?
+            },
+            BlockRecipe::Signed(sig, inner) => {
+                // the daemon can't verify the signature, fsck does
+                if inner.sensor_payload().is_none() {
+                    bail!(ErrorKind::InvalidSignedRecipe);
+                }
+                let mut attributes = attributes;
+                attributes.push(Attribute::SensorSignature(sig));
+                return self.recipe_with_attributes(*inner, attributes);
             },
         };
+        self.metrics.write_latency(start.elapsed());
 
         Ok(block.sha3())
     }
 
+    /// Encrypted payloads are a bit larger, they still need to fit into a
+    /// block.
+    fn validate_payload(&self, len: usize) -> Result<()> {
+        let overhead = if self.sealer.is_some() { envelope::OVERHEAD } else { 0 };
+        blocks::validate_block_size(len + overhead)?;
+        Ok(())
+    }
+
+    /// Write an info block and apply the rules and the rekey policy.
+    fn commit_info(&mut self, info: Vec<u8>, attributes: Vec<Attribute>) -> Result<Block> {
+        let matches = self.rules.matches(&info);
+        let payload = if matches.is_empty() { None } else { Some(info.clone()) };
+        self.commit_matched(info, attributes, &matches, payload)
+    }
+
+    /// Write an info block, `matches` are the rules that matched `payload`.
+    fn commit_matched(&mut self, info: Vec<u8>, attributes: Vec<Attribute>, matches: &[usize], payload: Option<Vec<u8>>) -> Result<Block> {
+        let compressor = match self.sealer {
+            Some(_) => None,
+            None => self.compressor.as_ref(),
+        };
+        let (info, attributes) = match compressor {
+            Some(compressor) => match compressor.compress(&info)? {
+                Some((compressed, bytes)) => {
+                    let mut attributes = attributes;
+                    attributes.push(Attribute::Compressed(compressed));
+                    (bytes, attributes)
+                },
+                None => (info, attributes),
+            },
+            None => (info, attributes),
+        };
+
+        let (info, attributes) = match self.sealer {
+            Some(ref sealer) => {
+                let (envelope, ciphertext) = sealer.seal(&info)?;
+                let mut attributes = attributes;
+                attributes.push(Attribute::Sealed(envelope));
+                (ciphertext, attributes)
+            },
+            None => (info, attributes),
+        };
+
+        let block = if self.redactable {
+            self.info_committed(info, attributes)?
+        } else {
+            self.info_with_attributes(info, attributes)?
+        };
+        let age = self.key_age();
+        if let Some(payload) = payload {
+            self.alert_rules(&block, matches, payload)
+        } else if self.policy.is_due(&self.usage, Some(age)) {
+            self.rekey()
+        } else {
+            Ok(block)
+        }
+    }
+
+    /// Each part is written like a regular info block, the whole transaction
+    /// is validated before the first part is written. The engine is the only
+    /// writer, parts can't be interleaved with other messages. If a part
+    /// can't be written anyway, an alert marks the transaction as aborted.
+    fn commit_parts(&mut self, parts: Vec<Vec<u8>>, attributes: Vec<Attribute>, mark: fn(TxPart) -> Attribute) -> Result<Block> {
+        self.validate_parts(&parts)?;
+
+        let count = parts.len() as u16;
+        let mut checked = attributes.clone();
+        checked.push(mark(TxPart {
+            index: count - 1,
+            count,
+        }));
+        blocks::validate_attributes(&checked)?;
+
+        let mut last = None;
+        for (index, part) in parts.into_iter().enumerate() {
+            let mut attributes = attributes.clone();
+            attributes.push(mark(TxPart {
+                index: index as u16,
+                count,
+            }));
+
+            match self.commit_info(part, attributes) {
+                Ok(block) => last = Some(block),
+                Err(err) => {
+                    if index > 0 {
+                        let msg = format!("{} after {} of {} parts: {}", blocks::TX_ABORTED, index, count, err);
+                        if let Err(err) = self.alert(msg.into_bytes()) {
+                            error!("failed to abort transaction: {:?}", err);
+                        }
+                    }
+                    return Err(err);
+                },
+            }
+        }
+
+        Ok(last.expect("transaction has parts"))
+    }
+
+    fn validate_parts(&self, parts: &[Vec<u8>]) -> Result<()> {
+        if parts.is_empty() {
+            bail!(ErrorKind::InvalidTransaction("transaction has no parts"));
+        }
+        if parts.len() > recipe::MAX_TRANSACTION_PARTS {
+            bail!(ErrorKind::InvalidTransaction("transaction has too many parts"));
+        }
+        for part in parts {
+            self.validate_payload(part.len())?;
+        }
+        Ok(())
+    }
+
+    /// The client had to split the line, this is recorded with an alert.
+    /// Rules are matched against each part together with the part before
+    /// it, `context` if the line continues a previous request. A rule that
+    /// already matched the part before isn't reported again. The sequence
+    /// number of a line is stored in its first part.
+    fn commit_split_line(&mut self, context: Option<Vec<u8>>, parts: Vec<Vec<u8>>, attributes: Vec<Attribute>, seq: Option<Sequence>) -> Result<Block> {
+        self.validate_parts(&parts)?;
+
+        let len = parts.iter().map(|part| part.len()).sum::<usize>();
+        let count = parts.len();
+        let origin = describe_origin(&attributes);
+
+        let continued = context.is_some();
+        let mut attributes = attributes;
+        if continued {
+            attributes.push(Attribute::Continued);
+        }
+
+        let mut prev = context.unwrap_or_default();
+        let mut prev_matches = self.rules.matches(&prev);
+        for (index, part) in parts.into_iter().enumerate() {
+            let own_matches = self.rules.matches(&part);
+
+            let mut window = prev;
+            window.extend(&part);
+            let matches = self.rules.matches(&window).into_iter()
+                .filter(|idx| !prev_matches.contains(idx))
+                .collect::<Vec<_>>();
+            let payload = if matches.is_empty() { None } else { Some(window) };
+
+            let mut attributes = attributes.clone();
+            attributes.push(Attribute::Split(TxPart {
+                index: index as u16,
+                count: count as u16,
+            }));
+            if index == 0 {
+                if let Some(ref seq) = seq {
+                    attributes.push(Attribute::Sequence(seq.clone()));
+                }
+            }
+
+            prev = part.clone();
+            prev_matches = own_matches;
+            self.commit_matched(part, attributes, &matches, payload)?;
+        }
+
+        let msg = if continued {
+            format!("tr1pd: line{} continues with {} bytes in {} blocks", origin, len, count)
+        } else {
+            format!("tr1pd: line of {} bytes{} has been split into {} blocks", len, origin, count)
+        };
+        self.alert(msg.into_bytes())
+    }
+
+    /// The messages couldn't be delivered when they have been written, each
+    /// one records when it has been spooled. The replay and the time window
+    /// it covers are recorded with an alert.
+    fn commit_replay(&mut self, entries: Vec<SpoolEntry>, attributes: Vec<Attribute>) -> Result<Block> {
+        if entries.is_empty() {
+            bail!(ErrorKind::InvalidReplay("replay has no messages"));
+        }
+        if entries.len() > recipe::MAX_TRANSACTION_PARTS {
+            bail!(ErrorKind::InvalidReplay("replay has too many messages"));
+        }
+        for (prev, entry) in entries.iter().zip(entries.iter().skip(1)) {
+            if entry.seq <= prev.seq {
+                bail!(ErrorKind::InvalidReplay("messages are out of order"));
+            }
+        }
+        for entry in &entries {
+            self.validate_payload(entry.msg.len())?;
+        }
+
+        let count = entries.len();
+        let first = entries[0].seq;
+        let last = entries[count - 1].seq;
+        let since = entries.iter().map(|entry| entry.time).min().unwrap_or(0);
+        let until = entries.iter().map(|entry| entry.time).max().unwrap_or(0);
+        let origin = describe_origin(&attributes);
+
+        for entry in entries {
+            let mut attributes = attributes.clone();
+            attributes.push(Attribute::Spooled(Spooled {
+                seq: entry.seq,
+                time: entry.time,
+            }));
+            self.commit_info(entry.msg, attributes)?;
+        }
+
+        let msg = format!("tr1pd: replayed {} spooled messages{} (#{} to #{}), spooled between {} and {}",
+                          count, origin, first, last, since, until);
+        self.alert(msg.into_bytes())
+    }
+
+    /// Write an alert for each rule that matched the info block, this also
+    /// replaces the session key. Returns the last alert.
+    fn alert_rules(&mut self, info: &Block, matches: &[usize], payload: Vec<u8>) -> Result<Block> {
+        let pointer = info.sha3();
+
+        let mut last = None;
+        for &idx in matches {
+            let (name, has_hook) = match self.rules.get(idx) {
+                Some(rule) => (rule.name.clone(), rule.hook.is_some()),
+                None => continue,
+            };
+
+            let msg = format!("rule {:?} matched {:x}", name, pointer);
+            let alert = self.alert(msg.into_bytes())?;
+
+            if let (true, Some(hooks)) = (has_hook, self.hooks.as_ref()) {
+                let event = Event {
+                    rule: idx,
+                    name,
+                    block: format!("{:x}", pointer),
+                    alert: format!("{:x}", alert.sha3()),
+                    payload: payload.clone(),
+                };
+
+                if let Err(err) = hooks.run(event) {
+                    error!("failed to run hook: {}", err);
+                }
+            }
+
+            last = Some(alert);
+        }
+
+        match last {
+            Some(alert) => Ok(alert),
+            None => self.rekey(),
+        }
+    }
+
     pub fn storage(&self) -> &StorageEngine {
         &self.storage
     }
+
+    #[inline]
+    pub fn head(&self) -> &BlockPointer {
+        &self.head
+    }
+
+    /// Pointer to the init block of the current session.
+    #[inline]
+    pub fn session(&self) -> &BlockPointer {
+        &self.session
+    }
+
+    /// Number of blocks written in this session, including the init block.
+    #[inline]
+    pub fn session_blocks(&self) -> u64 {
+        self.session_blocks
+    }
+
+    #[inline]
+    pub fn policy(&self) -> &RekeyPolicy {
+        &self.policy
+    }
+
+    #[inline]
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+}
+
+/// ` from <origin>` if the daemon recorded the origin of the request.
+fn describe_origin(attributes: &[Attribute]) -> String {
+    attributes.iter()
+        .filter_map(|attr| match *attr {
+            Attribute::Origin(ref origin) => Some(format!(" from {}", origin)),
+            _ => None,
+        })
+        .next()
+        .unwrap_or_default()
 }
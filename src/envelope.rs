@@ -0,0 +1,168 @@
+//! Payloads that are only readable by auditors.
+//!
+//! The payload is encrypted with a random key, the key is sealed for each
+//! auditor with a sealed box. The daemon encrypts before the block is
+//! signed, so the chain can be verified without any secret key.
+use sodiumoxide::crypto::box_;
+use sodiumoxide::crypto::scalarmult::curve25519;
+use sodiumoxide::crypto::sealedbox;
+use sodiumoxide::crypto::secretbox;
+
+use blocks::{self, Envelope, KeyId, Recipient};
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+pub use sodiumoxide::crypto::box_::{PublicKey, SecretKey};
+
+mod errors {
+    use std::io;
+
+    error_chain! {
+        errors {
+            CorruptedKey {
+                description("corrupted auditor key")
+            }
+            InvalidRecipients(count: usize) {
+                description("invalid number of auditors")
+                display("payloads can be encrypted for 1 to 255 auditors, not {}", count)
+            }
+            DecryptionFailed {
+                description("payload couldn't be decrypted")
+            }
+        }
+        links {
+            Blocks(::blocks::Error, ::blocks::ErrorKind);
+        }
+        foreign_links {
+            Io(io::Error);
+        }
+    }
+}
+pub use self::errors::{Result, Error, ErrorKind};
+
+pub const NONCE_LEN: usize = secretbox::NONCEBYTES;
+/// A payload key in a sealed box.
+pub const SEALED_KEY_LEN: usize = secretbox::KEYBYTES + sealedbox::SEALBYTES;
+/// The ciphertext is this much larger than the payload.
+pub const OVERHEAD: usize = secretbox::MACBYTES;
+/// The largest payload that still fits into a block once it's sealed.
+/// Clients don't send anything larger, they can't tell if the ledger is
+/// encrypted.
+pub const MAX_PAYLOAD: usize = 65535 - OVERHEAD;
+pub const MAX_RECIPIENTS: usize = 255;
+
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    File::open(path)?.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+#[inline]
+pub fn gen_keypair() -> (PublicKey, SecretKey) {
+    box_::gen_keypair()
+}
+
+#[inline]
+pub fn key_id(pubkey: &PublicKey) -> KeyId {
+    KeyId::of_bytes(&pubkey.0)
+}
+
+pub fn load_public_key<P: AsRef<Path>>(path: P) -> Result<PublicKey> {
+    PublicKey::from_slice(&read_file(path.as_ref())?)
+        .ok_or_else(|| ErrorKind::CorruptedKey.into())
+}
+
+/// Encrypts payloads for a fixed set of auditors.
+#[derive(Debug, Clone)]
+pub struct Sealer {
+    recipients: Vec<(KeyId, PublicKey)>,
+}
+
+impl Sealer {
+    pub fn new(auditors: Vec<PublicKey>) -> Result<Sealer> {
+        if auditors.is_empty() || auditors.len() > MAX_RECIPIENTS {
+            bail!(ErrorKind::InvalidRecipients(auditors.len()));
+        }
+
+        let recipients = auditors.into_iter()
+            .map(|pubkey| (key_id(&pubkey), pubkey))
+            .collect();
+
+        Ok(Sealer {
+            recipients,
+        })
+    }
+
+    /// Returns the envelope and the ciphertext, which still needs to fit
+    /// into a block.
+    pub fn seal(&self, msg: &[u8]) -> Result<(Envelope, Vec<u8>)> {
+        blocks::validate_block_size(msg.len() + OVERHEAD)?;
+
+        let key = secretbox::gen_key();
+        let nonce = secretbox::gen_nonce();
+        let ciphertext = secretbox::seal(msg, &nonce, &key);
+
+        let recipients = self.recipients.iter()
+            .map(|&(ref key_id, ref pubkey)| Recipient {
+                key_id: key_id.clone(),
+                sealed_key: sealedbox::seal(&key.0, pubkey),
+            })
+            .collect();
+
+        let envelope = Envelope {
+            nonce: nonce.0.to_vec(),
+            recipients,
+        };
+        Ok((envelope, ciphertext))
+    }
+}
+
+/// The secret key of an auditor, see `tr1pctl auditor-keygen`.
+#[derive(Debug)]
+pub struct AuditorKey {
+    pubkey: PublicKey,
+    seckey: SecretKey,
+}
+
+impl AuditorKey {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<AuditorKey> {
+        let seckey = SecretKey::from_slice(&read_file(path.as_ref())?)
+            .ok_or(ErrorKind::CorruptedKey)?;
+        let pubkey = curve25519::scalarmult_base(&curve25519::Scalar(seckey.0));
+        let pubkey = PublicKey(pubkey.0);
+
+        Ok(AuditorKey {
+            pubkey,
+            seckey,
+        })
+    }
+
+    #[inline]
+    pub fn key_id(&self) -> KeyId {
+        key_id(&self.pubkey)
+    }
+
+    /// Decrypt the payload, returns `None` if it hasn't been encrypted for
+    /// this auditor.
+    pub fn open(&self, envelope: &Envelope, ciphertext: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key_id = self.key_id();
+        let recipient = match envelope.recipients.iter().find(|recipient| recipient.key_id == key_id) {
+            Some(recipient) => recipient,
+            None => return Ok(None),
+        };
+
+        let key = sealedbox::open(&recipient.sealed_key, &self.pubkey, &self.seckey)
+            .ok()
+            .and_then(|key| secretbox::Key::from_slice(&key))
+            .ok_or(ErrorKind::DecryptionFailed)?;
+        let nonce = secretbox::Nonce::from_slice(&envelope.nonce)
+            .ok_or(ErrorKind::DecryptionFailed)?;
+
+        let msg = secretbox::open(ciphertext, &nonce, &key)
+            .map_err(|_| ErrorKind::DecryptionFailed)?;
+        Ok(Some(msg))
+    }
+}
@@ -0,0 +1,281 @@
+//! Commands that are executed when a rule matches.
+//!
+//! The daemon can't execute programs once the sandbox is active, the hooks
+//! are started by a helper process that is forked before that. The helper
+//! receives the commands once, afterwards the daemon can only refer to them
+//! by index.
+use libc;
+use serde_json;
+use users;
+
+use rpc::unix::{read_frame, write_frame};
+use signals::Signals;
+
+use std::io::{self, Write};
+use std::fs::File;
+use std::os::unix::io::FromRawFd;
+use std::process::{self, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+
+mod errors {
+    use serde_json;
+    use std::io;
+
+    error_chain! {
+        errors {
+            UnknownUser(name: String) {
+                description("unknown user")
+                display("unknown user: {:?}", name)
+            }
+            QueueFull
+            HelperStopped
+        }
+        links {
+            Caps(::sandbox::capabilities::Error, ::sandbox::capabilities::ErrorKind) #[cfg(target_os="linux")];
+            Rpc(::rpc::Error, ::rpc::ErrorKind);
+            Signals(::signals::Error, ::signals::ErrorKind);
+        }
+        foreign_links {
+            Io(io::Error);
+            Json(serde_json::Error);
+        }
+    }
+}
+pub use self::errors::{Result, Error, ErrorKind};
+
+/// Hooks run as this user if tr1pd has been started as root.
+pub const DEFAULT_USER: &str = "nobody";
+/// Events that haven't been sent to the helper yet, more are dropped.
+const QUEUE_LEN: usize = 64;
+/// Hooks that run at the same time, more are dropped.
+const MAX_RUNNING: usize = 16;
+
+
+/// A rule matched the message in `block`, the alert has been written to `alert`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub rule: usize,
+    pub name: String,
+    pub block: String,
+    pub alert: String,
+    pub payload: Vec<u8>,
+}
+
+/// Sent to the helper once, before any event.
+#[derive(Debug, Serialize, Deserialize)]
+struct Setup {
+    user: String,
+    hooks: Vec<Option<Vec<String>>>,
+}
+
+/// The daemon side of the helper process, before it has been configured.
+#[derive(Debug)]
+pub struct HookHelper {
+    pipe: File,
+}
+
+/// Queues events for the helper, this never blocks the engine.
+#[derive(Debug, Clone)]
+pub struct HookRunner {
+    queue: SyncSender<Event>,
+}
+
+/// Fork the helper, this needs to happen before the sandbox is activated
+/// and before any thread is started.
+pub fn spawn() -> Result<HookHelper> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error().into()),
+        0 => {
+            unsafe { libc::close(fds[1]) };
+
+            // fork again so the daemon doesn't need to reap the helper
+            match unsafe { libc::fork() } {
+                0 => (),
+                -1 => process::exit(1),
+                _ => process::exit(0),
+            }
+
+            let pipe = unsafe { File::from_raw_fd(fds[0]) };
+            let code = match serve(pipe) {
+                Ok(_) => 0,
+                Err(err) => {
+                    error!("hooks: {}", err);
+                    1
+                },
+            };
+            process::exit(code);
+        },
+        pid => {
+            unsafe { libc::close(fds[0]) };
+            let pipe = unsafe { File::from_raw_fd(fds[1]) };
+
+            let mut status = 0;
+            if unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+                return Err(io::Error::last_os_error().into());
+            }
+            // the intermediate process exits with 0 after the helper has been forked
+            if status != 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "failed to fork hook helper").into());
+            }
+
+            Ok(HookHelper {
+                pipe,
+            })
+        },
+    }
+}
+
+impl HookHelper {
+    /// Send the commands to the helper and start the thread that writes the
+    /// events to it. The helper exits if there are no commands.
+    pub fn configure(mut self, user: &str, hooks: Vec<Option<Vec<String>>>) -> Result<HookRunner> {
+        let setup = Setup {
+            user: user.to_string(),
+            hooks,
+        };
+        let buf = serde_json::to_vec(&setup)?;
+        write_frame(&mut self.pipe, &buf)?;
+
+        let (runner, rx) = HookRunner::queue(QUEUE_LEN);
+        let mut pipe = self.pipe;
+        thread::spawn(move || {
+            for event in rx {
+                let buf = match serde_json::to_vec(&event) {
+                    Ok(buf) => buf,
+                    Err(err) => {
+                        error!("hooks: failed to encode event: {}", err);
+                        continue;
+                    },
+                };
+
+                if let Err(err) = write_frame(&mut pipe, &buf) {
+                    error!("hooks: helper stopped: {}", err);
+                    return;
+                }
+            }
+        });
+
+        Ok(runner)
+    }
+}
+
+impl HookRunner {
+    /// A runner that queues up to `len` events, they are received by `Receiver`.
+    pub fn queue(len: usize) -> (HookRunner, Receiver<Event>) {
+        let (queue, rx) = mpsc::sync_channel(len);
+        (HookRunner { queue }, rx)
+    }
+
+    /// Queue the event, it's dropped if the helper falls behind.
+    pub fn run(&self, event: Event) -> Result<()> {
+        match self.queue.try_send(event) {
+            Ok(_) => Ok(()),
+            Err(TrySendError::Full(_)) => bail!(ErrorKind::QueueFull),
+            Err(TrySendError::Disconnected(_)) => bail!(ErrorKind::HelperStopped),
+        }
+    }
+}
+
+/// Give up root and all capabilities before any hook is executed.
+fn drop_privileges(user: &str) -> Result<()> {
+    if unsafe { libc::geteuid() } == 0 {
+        let user = match users::get_user_by_name(user) {
+            Some(user) => user,
+            None => bail!(ErrorKind::UnknownUser(user.to_string())),
+        };
+
+        unsafe {
+            if libc::setgroups(0, [].as_ptr()) != 0 ||
+               libc::setgid(user.primary_group_id()) != 0 ||
+               libc::setuid(user.uid()) != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+    }
+
+    // the binary might have capabilities without running as root
+    #[cfg(target_os="linux")]
+    ::sandbox::capabilities::drop()?;
+
+    Ok(())
+}
+
+/// The helper runs until the daemon exits.
+fn serve(mut pipe: File) -> Result<()> {
+    // the daemon decides when to shut down, the pipe is closed after that
+    Signals::block()?;
+
+    let setup: Setup = match read_frame(&mut pipe)? {
+        Some(buf) => serde_json::from_slice(&buf)?,
+        None => return Ok(()),
+    };
+
+    if setup.hooks.iter().all(|hook| hook.is_none()) {
+        return Ok(());
+    }
+
+    drop_privileges(&setup.user)?;
+
+    let running = Arc::new(AtomicUsize::new(0));
+    while let Some(buf) = read_frame(&mut pipe)? {
+        let event: Event = serde_json::from_slice(&buf)?;
+
+        match setup.hooks.get(event.rule) {
+            Some(&Some(ref hook)) => execute(hook, event, &running),
+            _ => warn!("hooks: rule {} has no hook", event.rule),
+        }
+    }
+
+    Ok(())
+}
+
+/// The message is written to stdin of the hook, the details of the match
+/// are set as environment variables.
+fn execute(hook: &[String], event: Event, running: &Arc<AtomicUsize>) {
+    if running.load(Ordering::SeqCst) >= MAX_RUNNING {
+        warn!("hooks: {} hooks are still running, skipping hook for {:?}", MAX_RUNNING, event.name);
+        return;
+    }
+
+    let child = Command::new(&hook[0])
+        .args(&hook[1..])
+        .env("TR1PD_RULE", &event.name)
+        .env("TR1PD_BLOCK", &event.block)
+        .env("TR1PD_ALERT", &event.alert)
+        .stdin(Stdio::piped())
+        .spawn();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            error!("hooks: failed to execute {:?}: {}", hook, err);
+            return;
+        },
+    };
+
+    // a slow hook must not delay the other ones
+    running.fetch_add(1, Ordering::SeqCst);
+    let running = running.clone();
+    thread::spawn(move || {
+        if let Some(mut stdin) = child.stdin.take() {
+            if let Err(err) = stdin.write_all(&event.payload) {
+                debug!("hooks: failed to write to hook: {}", err);
+            }
+        }
+
+        match child.wait() {
+            Ok(status) if !status.success() => warn!("hooks: hook for {:?} exited with {}", event.name, status),
+            Ok(_) => (),
+            Err(err) => error!("hooks: failed to wait for hook: {}", err),
+        }
+        running.fetch_sub(1, Ordering::SeqCst);
+    });
+}
@@ -0,0 +1,322 @@
+//! Reader for the journal export format, see `journalctl -o export`.
+use atomic;
+use recipe;
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+use std::str;
+
+mod errors {
+    use std::io;
+
+    error_chain! {
+        errors {
+            InvalidEntry(reason: &'static str) {
+                description("invalid journal entry")
+                display("invalid journal entry: {}", reason)
+            }
+        }
+        foreign_links {
+            Io(io::Error);
+        }
+    }
+}
+pub use self::errors::{Result, Error, ErrorKind};
+
+
+/// The fields that are kept, in the order they are written into the block.
+pub const FIELDS: &[&str] = &[
+    "__CURSOR",
+    "__REALTIME_TIMESTAMP",
+    "_PID",
+    "_UID",
+    "_SYSTEMD_UNIT",
+    "MESSAGE",
+];
+
+/// Entries that don't fit into a block are split like long lines, larger
+/// entries are truncated so they're still written with one request.
+pub const MAX_ENTRY_SIZE: usize = recipe::MAX_SPLIT_PARTS * recipe::MAX_LINE_PART;
+
+/// Added to entries that have been truncated, with the number of bytes that
+/// have been cut off. Entries that are read can't contain it.
+pub const TRUNCATED_FIELD: &str = "TR1PD_TRUNCATED";
+
+/// Refuse fields larger than this, journald uses 64M.
+const MAX_FIELD_SIZE: u64 = 64 * 1024 * 1024;
+
+
+/// A journal entry with the selected fields.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Entry {
+    pub fields: Vec<(String, Vec<u8>)>,
+}
+
+impl Entry {
+    #[inline]
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.fields.iter()
+            .find(|&&(ref key, _)| key == name)
+            .map(|&(_, ref value)| value.as_slice())
+    }
+
+    #[inline]
+    pub fn cursor(&self) -> Option<&str> {
+        self.get("__CURSOR")
+            .and_then(|cursor| str::from_utf8(cursor).ok())
+    }
+
+    /// Bring the fields into the order of `FIELDS`.
+    fn sorted(mut self) -> Entry {
+        self.fields.sort_by_key(|&(ref key, _)| FIELDS.iter().position(|f| f == key));
+        self
+    }
+
+    /// Encode the entry in the export format. Values that contain a newline
+    /// use the binary form. If the entry is too large, `MESSAGE` is cut off
+    /// first, then the other fields in reverse order. The cursor is never
+    /// cut, the number of bytes that are missing is recorded in
+    /// `TRUNCATED_FIELD`.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for &(ref key, ref value) in &self.fields {
+            encode_field(&mut buf, key, value);
+        }
+
+        if buf.len() <= MAX_ENTRY_SIZE {
+            return buf;
+        }
+
+        // leave room for the marker
+        let marker = TRUNCATED_FIELD.len() + "=18446744073709551615\n".len();
+        let mut overflow = buf.len() + marker - MAX_ENTRY_SIZE;
+        warn!("journal entry is {} bytes too large, truncating fields", buf.len() - MAX_ENTRY_SIZE);
+
+        let mut values: Vec<&[u8]> = self.fields.iter()
+            .map(|&(_, ref value)| value.as_slice())
+            .collect();
+
+        let mut order: Vec<usize> = (0..self.fields.len())
+            .rev()
+            .filter(|&idx| self.fields[idx].0 != "__CURSOR")
+            .collect();
+        order.sort_by_key(|&idx| self.fields[idx].0 != "MESSAGE");
+
+        let mut truncated = 0;
+        for idx in order {
+            if overflow == 0 {
+                break;
+            }
+            let cut = cmp::min(overflow, values[idx].len());
+            values[idx] = &values[idx][..values[idx].len() - cut];
+            overflow -= cut;
+            truncated += cut;
+        }
+
+        // a value without a newline is encoded shorter, never longer
+        buf.clear();
+        for (&(ref key, _), value) in self.fields.iter().zip(values) {
+            encode_field(&mut buf, key, value);
+        }
+        encode_field(&mut buf, TRUNCATED_FIELD, truncated.to_string().as_bytes());
+
+        buf
+    }
+
+    /// The encoded entry in parts that fit into a block each.
+    pub fn parts(&self) -> Vec<Vec<u8>> {
+        self.encode()
+            .chunks(recipe::MAX_LINE_PART)
+            .map(|part| part.to_vec())
+            .collect()
+    }
+}
+
+fn encode_field(buf: &mut Vec<u8>, key: &str, value: &[u8]) {
+    buf.extend(key.as_bytes());
+    if value.contains(&b'\n') {
+        let len = value.len() as u64;
+        buf.push(b'\n');
+        for i in 0..8 {
+            buf.push((len >> (i * 8)) as u8);
+        }
+        buf.extend(value);
+    } else {
+        buf.push(b'=');
+        buf.extend(value);
+    }
+    buf.push(b'\n');
+}
+
+/// Read the next entry and discard all fields that aren't in `FIELDS`.
+/// Returns `None` at the end of the stream.
+pub fn read_entry<R: BufRead>(stream: &mut R) -> Result<Option<Entry>> {
+    let mut entry = Entry::default();
+    let mut empty = true;
+
+    loop {
+        let mut line = Vec::new();
+        stream.by_ref()
+            .take(MAX_FIELD_SIZE + 1)
+            .read_until(b'\n', &mut line)?;
+
+        if line.len() as u64 > MAX_FIELD_SIZE {
+            return Err(ErrorKind::InvalidEntry("field too large").into());
+        }
+
+        if line.is_empty() {
+            // a missing separator after the last entry is fine
+            return Ok(if empty { None } else { Some(entry.sorted()) });
+        }
+
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        } else {
+            return Err(ErrorKind::InvalidEntry("unexpected end of stream").into());
+        }
+
+        if line.is_empty() {
+            if empty {
+                // skip additional separators
+                continue;
+            }
+            return Ok(Some(entry.sorted()));
+        }
+        empty = false;
+
+        let (key, value) = match line.iter().position(|&b| b == b'=') {
+            Some(idx) => {
+                let value = line[idx + 1..].to_vec();
+                line.truncate(idx);
+                (line, value)
+            },
+            None => (line, read_binary(stream)?),
+        };
+
+        let key = String::from_utf8(key)
+            .map_err(|_| ErrorKind::InvalidEntry("field name is not utf8"))?;
+
+        if FIELDS.contains(&key.as_str()) {
+            entry.fields.push((key, value));
+        }
+    }
+}
+
+/// Binary fields are followed by a little endian u64 length, the data and
+/// a newline.
+fn read_binary<R: BufRead>(stream: &mut R) -> Result<Vec<u8>> {
+    let mut len = [0; 8];
+    stream.read_exact(&mut len)?;
+    let len = len.iter().rev().fold(0u64, |acc, &b| (acc << 8) | u64::from(b));
+
+    if len > MAX_FIELD_SIZE {
+        return Err(ErrorKind::InvalidEntry("binary field too large").into());
+    }
+
+    let mut value = vec![0; len as usize];
+    stream.read_exact(&mut value)?;
+
+    let mut newline = [0; 1];
+    stream.read_exact(&mut newline)?;
+    if newline[0] != b'\n' {
+        return Err(ErrorKind::InvalidEntry("missing newline after binary field").into());
+    }
+
+    Ok(value)
+}
+
+
+/// The parts of a cursor that are needed to order entries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    /// Id of the sequence number space, `s=`
+    pub seqnum_id: String,
+    /// Sequence number of the entry, `i=`
+    pub seqnum: u64,
+    /// Wallclock time in microseconds, `t=`
+    pub realtime: u64,
+}
+
+impl Cursor {
+    /// Parse a cursor like `s=...;i=...;b=...;m=...;t=...;x=...`.
+    pub fn parse(cursor: &str) -> Option<Cursor> {
+        let mut seqnum_id = None;
+        let mut seqnum = None;
+        let mut realtime = None;
+
+        for part in cursor.split(';') {
+            let mut kv = part.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some("s"), Some(v)) => seqnum_id = Some(v.to_string()),
+                (Some("i"), Some(v)) => seqnum = u64::from_str_radix(v, 16).ok(),
+                (Some("t"), Some(v)) => realtime = u64::from_str_radix(v, 16).ok(),
+                _ => (),
+            }
+        }
+
+        Some(Cursor {
+            seqnum_id: seqnum_id?,
+            seqnum: seqnum?,
+            realtime: realtime?,
+        })
+    }
+
+    /// Check if this entry has been written after `other`. Sequence numbers
+    /// are only comparable within the same journal, otherwise the wallclock
+    /// time is used.
+    pub fn is_after(&self, other: &Cursor) -> bool {
+        if self.seqnum_id == other.seqnum_id {
+            self.seqnum > other.seqnum
+        } else {
+            self.realtime > other.realtime
+        }
+    }
+}
+
+
+/// Remembers the cursor of the last entry that has been committed.
+#[derive(Debug, Clone)]
+pub struct CursorFile {
+    path: PathBuf,
+}
+
+impl CursorFile {
+    #[inline]
+    pub fn new<P: Into<PathBuf>>(path: P) -> CursorFile {
+        CursorFile {
+            path: path.into(),
+        }
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns `None` if no cursor has been stored yet.
+    pub fn load(&self) -> Result<Option<String>> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut cursor = String::new();
+        file.read_to_string(&mut cursor)?;
+
+        let cursor = cursor.trim();
+        if cursor.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(cursor.to_string()))
+        }
+    }
+
+    /// Replace the cursor atomically, a crash never leaves a partial cursor.
+    pub fn store(&self, cursor: &str) -> Result<()> {
+        atomic::replace(&self.path, format!("{}\n", cursor).as_bytes())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+//! Independent chains that are written by the same daemon.
+//!
+//! The default ledger lives in the root of the datadir, named ledgers have
+//! their own folder in `ledgers/` with their own blocks and HEAD.
+use engine::Engine;
+
+use std::collections::BTreeMap;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+mod errors {
+    error_chain! {
+        errors {
+            InvalidName(name: String) {
+                description("invalid ledger name")
+                display("invalid ledger name: {:?}", name)
+            }
+        }
+    }
+}
+pub use self::errors::{Result, Error, ErrorKind};
+
+/// Folder in the datadir that contains the named ledgers.
+pub const LEDGERS_DIR: &str = "ledgers";
+pub const MAX_NAME_LEN: usize = 64;
+
+
+/// Names are used as folder names, they are limited to `[a-zA-Z0-9_-]`.
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty() &&
+        name.len() <= MAX_NAME_LEN &&
+        name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+
+    if valid {
+        Ok(())
+    } else {
+        bail!(ErrorKind::InvalidName(name.to_string()))
+    }
+}
+
+/// The folder of a ledger, `None` is the default ledger.
+pub fn path<P: AsRef<Path>>(datadir: P, name: Option<&str>) -> Result<PathBuf> {
+    let mut path = datadir.as_ref().to_path_buf();
+
+    if let Some(name) = name {
+        validate_name(name)?;
+        path.push(LEDGERS_DIR);
+        path.push(name);
+    }
+
+    Ok(path)
+}
+
+/// One engine for each ledger, requests for ledgers that aren't configured
+/// are rejected.
+pub struct Ledgers {
+    default: Engine,
+    named: BTreeMap<String, Engine>,
+}
+
+impl Ledgers {
+    pub fn new(default: Engine) -> Ledgers {
+        Ledgers {
+            default,
+            named: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, name: String, engine: Engine) -> Result<()> {
+        validate_name(&name)?;
+        self.named.insert(name, engine);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn default_ledger(&mut self) -> &mut Engine {
+        &mut self.default
+    }
+
+    pub fn get_mut(&mut self, name: Option<&str>) -> Option<&mut Engine> {
+        match name {
+            Some(name) => self.named.get_mut(name),
+            None => Some(&mut self.default),
+        }
+    }
+
+    /// The default ledger first, followed by the named ledgers.
+    pub fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item=(Option<&'a str>, &'a mut Engine)> + 'a {
+        let named = self.named.iter_mut()
+            .map(|(name, engine)| (Some(name.as_str()), engine));
+
+        iter::once((None, &mut self.default))
+            .chain(named)
+    }
+}
@@ -5,11 +5,14 @@
 extern crate sodiumoxide;
 extern crate sha3;
 #[macro_use] extern crate structopt;
+#[cfg(feature="zmq")]
 extern crate zmq;
 extern crate toml;
+extern crate flate2;
 extern crate human_size;
 extern crate libc;
-#[cfg(not(target_os="linux"))]
+extern crate regex;
+extern crate serde_json;
 extern crate users;
 #[cfg(target_os="linux")]
 extern crate seccomp_sys;
@@ -30,11 +33,23 @@ mod errors {
     error_chain! {
         links {
             Blocks(::blocks::Error, ::blocks::ErrorKind);
+            Compress(::compress::Error, ::compress::ErrorKind);
             Crypto(::crypto::Error, ::crypto::ErrorKind);
             Engine(::engine::Error, ::engine::ErrorKind);
+            Envelope(::envelope::Error, ::envelope::ErrorKind);
+            Hooks(::hooks::Error, ::hooks::ErrorKind);
+            Ledger(::ledger::Error, ::ledger::ErrorKind);
+            Metrics(::metrics::Error, ::metrics::ErrorKind);
             Sandbox(::sandbox::Error, ::sandbox::ErrorKind);
             Storage(::storage::Error, ::storage::ErrorKind);
+            Reload(::reload::Error, ::reload::ErrorKind);
             Rpc(::rpc::Error, ::rpc::ErrorKind);
+            Rules(::rules::Error, ::rules::ErrorKind);
+            Sensors(::sensors::Error, ::sensors::ErrorKind);
+            Sequence(::sequence::Error, ::sequence::ErrorKind);
+            Signals(::signals::Error, ::signals::ErrorKind);
+            Spool(::spool::Error, ::spool::ErrorKind);
+            Syslog(::syslog::Error, ::syslog::ErrorKind);
         }
         foreign_links {
             Io(::std::io::Error);
@@ -43,16 +58,32 @@ mod errors {
 }
 pub use self::errors::{Result, ResultExt, Error, ErrorKind};
 
+pub mod atomic;
 pub mod blocks;
 pub mod cli;
+pub mod compress;
 pub mod config;
 pub mod crypto;
+pub mod dedup;
 pub mod engine;
+pub mod envelope;
+pub mod hooks;
+pub mod journal;
+pub mod ledger;
+pub mod metrics;
+pub mod ratelimit;
 pub mod recipe;
+pub mod reload;
 pub mod rpc;
+pub mod rules;
 pub mod sandbox;
+pub mod sensors;
+pub mod sequence;
+pub mod signals;
 pub mod spec;
+pub mod spool;
 pub mod storage;
+pub mod syslog;
 #[allow(unused_variables)]
 pub mod wire;
 
@@ -0,0 +1,266 @@
+//! Counters for monitoring, exported in the prometheus text format.
+use rpc::NackCode;
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+mod errors {
+    use std::io;
+
+    error_chain! {
+        foreign_links {
+            Io(io::Error);
+        }
+    }
+}
+pub use self::errors::{Result, Error, ErrorKind};
+
+
+/// Upper bounds of the write latency histogram, in microseconds.
+const LATENCY_BUCKETS: [usize; 10] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 100_000, 1_000_000,
+];
+
+const NACK_CODES: usize = 7;
+
+/// Requests larger than this are dropped.
+const MAX_REQUEST_SIZE: u64 = 8192;
+
+/// Slow clients can't hold up the listener for longer than this.
+const CLIENT_TIMEOUT_SECS: u64 = 5;
+
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    init_blocks: AtomicUsize,
+    rekey_blocks: AtomicUsize,
+    alert_blocks: AtomicUsize,
+    info_blocks: AtomicUsize,
+    info_bytes: AtomicUsize,
+    storage_errors: AtomicUsize,
+    requests: AtomicUsize,
+    nacks: [AtomicUsize; NACK_CODES],
+    latency_buckets: [AtomicUsize; 10],
+    latency_sum_us: AtomicUsize,
+    latency_count: AtomicUsize,
+    /// Unix time of the last init block, 0 if there is no session yet.
+    session_start: AtomicUsize,
+}
+
+#[inline]
+fn inc(counter: &AtomicUsize) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+#[inline]
+fn get(counter: &AtomicUsize) -> usize {
+    counter.load(Ordering::Relaxed)
+}
+
+fn unix_time() -> usize {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as usize)
+        .unwrap_or(0)
+}
+
+fn as_micros(duration: Duration) -> usize {
+    (duration.as_secs() * 1_000_000 + u64::from(duration.subsec_nanos() / 1_000)) as usize
+}
+
+fn nack_label(code: NackCode) -> &'static str {
+    match code {
+        NackCode::Unknown            => "unknown",
+        NackCode::TooLarge           => "too_large",
+        NackCode::StorageFailure     => "storage_failure",
+        NackCode::RateLimited        => "rate_limited",
+        NackCode::Forbidden          => "forbidden",
+        NackCode::InvalidRequest     => "invalid_request",
+        NackCode::UnsupportedVersion => "unsupported_version",
+    }
+}
+
+impl Metrics {
+    #[inline]
+    pub fn new() -> Arc<Metrics> {
+        Arc::new(Metrics::default())
+    }
+
+    /// A new session has been started with an init block.
+    pub fn init_block(&self) {
+        inc(&self.init_blocks);
+        self.session_start.store(unix_time(), Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn rekey_block(&self) {
+        inc(&self.rekey_blocks);
+    }
+
+    #[inline]
+    pub fn alert_block(&self) {
+        inc(&self.alert_blocks);
+    }
+
+    #[inline]
+    pub fn info_block(&self, len: usize) {
+        inc(&self.info_blocks);
+        self.info_bytes.fetch_add(len, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn storage_error(&self) {
+        inc(&self.storage_errors);
+    }
+
+    #[inline]
+    pub fn request(&self) {
+        inc(&self.requests);
+    }
+
+    #[inline]
+    pub fn nack(&self, code: NackCode) {
+        inc(&self.nacks[code.to_byte() as usize]);
+    }
+
+    /// Time it took to commit a recipe, including the rekey.
+    pub fn write_latency(&self, duration: Duration) {
+        let us = as_micros(duration);
+
+        if let Some(idx) = LATENCY_BUCKETS.iter().position(|&le| us <= le) {
+            inc(&self.latency_buckets[idx]);
+        }
+        self.latency_sum_us.fetch_add(us, Ordering::Relaxed);
+        inc(&self.latency_count);
+    }
+
+    /// Encode all metrics in the prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "tr1pd_blocks_written_total", "counter", "Blocks written to the ledger");
+        for &(kind, ref counter) in &[
+            ("init", &self.init_blocks),
+            ("rekey", &self.rekey_blocks),
+            ("alert", &self.alert_blocks),
+            ("info", &self.info_blocks),
+        ] {
+            writeln!(out, "tr1pd_blocks_written_total{{type=\"{}\"}} {}", kind, get(counter)).unwrap();
+        }
+
+        header(&mut out, "tr1pd_info_bytes_written_total", "counter", "Payload bytes written in info blocks");
+        writeln!(out, "tr1pd_info_bytes_written_total {}", get(&self.info_bytes)).unwrap();
+
+        header(&mut out, "tr1pd_storage_errors_total", "counter", "Blocks that couldn't be written to disk");
+        writeln!(out, "tr1pd_storage_errors_total {}", get(&self.storage_errors)).unwrap();
+
+        header(&mut out, "tr1pd_requests_total", "counter", "Requests received by the daemon");
+        writeln!(out, "tr1pd_requests_total {}", get(&self.requests)).unwrap();
+
+        header(&mut out, "tr1pd_nacks_total", "counter", "Requests that have been rejected");
+        for (i, counter) in self.nacks.iter().enumerate() {
+            let code = NackCode::from_byte(i as u8);
+            writeln!(out, "tr1pd_nacks_total{{code=\"{}\"}} {}", nack_label(code), get(counter)).unwrap();
+        }
+
+        header(&mut out, "tr1pd_write_duration_seconds", "histogram", "Time to commit a write");
+        let mut cumulative = 0;
+        for (le, counter) in LATENCY_BUCKETS.iter().zip(self.latency_buckets.iter()) {
+            cumulative += get(counter);
+            writeln!(out, "tr1pd_write_duration_seconds_bucket{{le=\"{}\"}} {}",
+                     *le as f64 / 1_000_000.0, cumulative).unwrap();
+        }
+        let count = get(&self.latency_count);
+        writeln!(out, "tr1pd_write_duration_seconds_bucket{{le=\"+Inf\"}} {}", count).unwrap();
+        writeln!(out, "tr1pd_write_duration_seconds_sum {}",
+                 get(&self.latency_sum_us) as f64 / 1_000_000.0).unwrap();
+        writeln!(out, "tr1pd_write_duration_seconds_count {}", count).unwrap();
+
+        let session_start = get(&self.session_start);
+        if session_start > 0 {
+            header(&mut out, "tr1pd_session_age_seconds", "gauge", "Time since the current session has been started");
+            writeln!(out, "tr1pd_session_age_seconds {}", unix_time().saturating_sub(session_start)).unwrap();
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}.", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+
+/// Bind the metrics endpoint, this needs to happen before the sandbox is
+/// activated.
+#[inline]
+pub fn bind(addr: &str) -> Result<TcpListener> {
+    let listener = TcpListener::bind(addr)?;
+    info!("metrics: listening on http://{}/metrics", addr);
+    Ok(listener)
+}
+
+/// Serve `GET /metrics` in a background thread. Requests are handled one
+/// after another, scrapes are rare.
+pub fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| respond(stream, &metrics));
+
+            if let Err(err) = result {
+                debug!("metrics: failed to serve request: {:?}", err);
+            }
+        }
+    });
+}
+
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    let timeout = Duration::from_secs(CLIENT_TIMEOUT_SECS);
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let request = read_request(&mut stream)?;
+    let (status, body) = route(&request, metrics);
+
+    write!(stream, "HTTP/1.0 {}\r\n\
+                    Content-Type: text/plain; version=0.0.4\r\n\
+                    Content-Length: {}\r\n\
+                    Connection: close\r\n\
+                    \r\n", status, body.len())?;
+    stream.write_all(body.as_bytes())?;
+    Ok(())
+}
+
+/// Read until the end of the request header, the body is ignored.
+fn read_request<R: Read>(stream: R) -> io::Result<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut stream = stream.take(MAX_REQUEST_SIZE);
+    let mut buf = [0; 1024];
+
+    while !request.ends_with(b"\r\n\r\n") && !request.ends_with(b"\n\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend(&buf[..n]);
+    }
+
+    Ok(request)
+}
+
+/// Returns the status line and the body for a request.
+pub fn route(request: &[u8], metrics: &Metrics) -> (&'static str, String) {
+    let line = request.split(|&b| b == b'\n').next().unwrap_or(&[]);
+    let mut parts = line.split(|&b| b == b' ');
+
+    match (parts.next(), parts.next()) {
+        (Some(b"GET"), Some(b"/metrics")) => ("200 OK", metrics.render()),
+        (Some(b"GET"), Some(_)) => ("404 Not Found", "not found\n".into()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".into()),
+    }
+}
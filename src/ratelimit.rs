@@ -0,0 +1,273 @@
+//! Token buckets that limit how much a single client can write.
+use config::RateLimitConfig;
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+
+/// Summaries are written at least this often while a client is throttled.
+pub const SUMMARY_INTERVAL_SECS: u64 = 60;
+/// A client is considered well-behaved again after this long.
+pub const QUIET_PERIOD_SECS: u64 = 5;
+/// Clients with full buckets are forgotten once there are more than this.
+const MAX_IDLE_CLIENTS: usize = 1024;
+/// New clients are throttled while this many clients are tracked, this keeps
+/// spoofed syslog senders from growing the map without bounds.
+pub const MAX_CLIENTS: usize = 16384;
+
+
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+fn as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+impl TokenBucket {
+    /// A full bucket that refills `rate` tokens per second, up to `capacity`.
+    #[inline]
+    pub fn new(rate: u64, capacity: u64, now: Instant) -> TokenBucket {
+        TokenBucket {
+            capacity: capacity as f64,
+            rate: rate as f64,
+            tokens: capacity as f64,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now > self.last {
+            let elapsed = as_secs_f64(now - self.last);
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+            self.last = now;
+        }
+    }
+
+    #[inline]
+    pub fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity
+    }
+
+    /// Check if `n` tokens are available without taking them. Requests
+    /// larger than the capacity are allowed if the bucket is full.
+    #[inline]
+    pub fn has(&mut self, n: u64, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= (n as f64).min(self.capacity)
+    }
+
+    /// Take `n` tokens, this can put the bucket into debt.
+    #[inline]
+    pub fn take(&mut self, n: u64) {
+        self.tokens -= n as f64;
+    }
+}
+
+
+/// The result of checking a request against the limits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Allow,
+    /// A message exceeds `max_message_size`
+    TooLarge,
+    /// The client ran out of tokens
+    Throttled,
+}
+
+/// The requests of a client that have been rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary<K> {
+    pub client: K,
+    pub duration: Duration,
+    pub requests: u64,
+    pub messages: u64,
+    pub bytes: u64,
+    /// `true` if the client is still being throttled
+    pub ongoing: bool,
+}
+
+impl<K: fmt::Display> fmt::Display for Summary<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rate limit: rejected {} requests ({} messages, {} bytes) from {} in {}s{}",
+               self.requests, self.messages, self.bytes, self.client,
+               self.duration.as_secs(),
+               if self.ongoing { ", still throttled" } else { "" })
+    }
+}
+
+#[derive(Debug)]
+struct Rejected {
+    since: Instant,
+    last: Instant,
+    requests: u64,
+    messages: u64,
+    bytes: u64,
+}
+
+#[derive(Debug)]
+struct Client {
+    messages: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    rejected: Option<Rejected>,
+}
+
+impl Client {
+    fn is_idle(&mut self, now: Instant) -> bool {
+        self.rejected.is_none() &&
+            self.messages.as_mut().map(|b| b.is_full(now)).unwrap_or(true) &&
+            self.bytes.as_mut().map(|b| b.is_full(now)).unwrap_or(true)
+    }
+}
+
+/// Limits for each client, identified by `K`.
+#[derive(Debug)]
+pub struct RateLimiter<K: Hash + Eq> {
+    config: RateLimitConfig,
+    clients: HashMap<K, Client>,
+    /// Idle clients are removed once the map reaches this size
+    cleanup_at: usize,
+    last_cleanup: Option<Instant>,
+}
+
+impl<K: Hash + Eq + Clone> RateLimiter<K> {
+    #[inline]
+    pub fn new(config: RateLimitConfig) -> RateLimiter<K> {
+        RateLimiter {
+            config,
+            clients: HashMap::new(),
+            cleanup_at: MAX_IDLE_CLIENTS,
+            last_cleanup: None,
+        }
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_enabled()
+    }
+
+    /// Returns `None` if the client is new and there are too many clients.
+    fn client(&mut self, key: &K, now: Instant) -> Option<&mut Client> {
+        if !self.clients.contains_key(key) {
+            // a full map is scanned at most once per second
+            let full = self.clients.len() >= MAX_CLIENTS && self.last_cleanup
+                .map(|last| now >= last + Duration::from_secs(1))
+                .unwrap_or(true);
+
+            if self.clients.len() >= self.cleanup_at || full {
+                self.clients.retain(|_, client| !client.is_idle(now));
+                // the remaining clients are busy, don't scan them again for every new one
+                self.cleanup_at = cmp::max(self.clients.len() * 2, MAX_IDLE_CLIENTS);
+                self.last_cleanup = Some(now);
+            }
+
+            if self.clients.len() >= MAX_CLIENTS {
+                return None;
+            }
+
+            let config = &self.config;
+            let bucket = |rate: Option<u64>, burst: Option<u64>| {
+                rate.map(|rate| TokenBucket::new(rate, cmp::max(burst.unwrap_or(rate), 1), now))
+            };
+
+            self.clients.insert(key.clone(), Client {
+                messages: bucket(config.messages_per_second, config.messages_burst),
+                bytes: bucket(config.bytes_per_second, config.bytes_burst),
+                rejected: None,
+            });
+        }
+
+        self.clients.get_mut(key)
+    }
+
+    /// Check a request with the given message sizes, tokens are only taken
+    /// if the request is allowed.
+    pub fn check(&mut self, key: &K, sizes: &[usize], now: Instant) -> Verdict {
+        let max_size = self.config.max_message_size;
+        let messages = sizes.len() as u64;
+        let bytes = sizes.iter().sum::<usize>() as u64;
+
+        let client = match self.client(key, now) {
+            Some(client) => client,
+            None => {
+                debug!("rate limit: tracking too many clients, rejecting new ones");
+                return Verdict::Throttled;
+            },
+        };
+
+        let verdict = if max_size.map(|max| sizes.iter().any(|&s| s > max)).unwrap_or(false) {
+            Verdict::TooLarge
+        } else {
+            let enough_messages = client.messages.as_mut().map(|b| b.has(messages, now)).unwrap_or(true);
+            let enough_bytes = client.bytes.as_mut().map(|b| b.has(bytes, now)).unwrap_or(true);
+
+            if enough_messages && enough_bytes {
+                if let Some(ref mut bucket) = client.messages {
+                    bucket.take(messages);
+                }
+                if let Some(ref mut bucket) = client.bytes {
+                    bucket.take(bytes);
+                }
+                Verdict::Allow
+            } else {
+                Verdict::Throttled
+            }
+        };
+
+        if verdict != Verdict::Allow {
+            let rejected = client.rejected.get_or_insert_with(|| Rejected {
+                since: now,
+                last: now,
+                requests: 0,
+                messages: 0,
+                bytes: 0,
+            });
+            rejected.last = now;
+            rejected.requests += 1;
+            rejected.messages += messages;
+            rejected.bytes += bytes;
+        }
+
+        verdict
+    }
+
+    /// Collect the clients that stopped exceeding the limits, and the ones
+    /// that have been throttled for longer than `SUMMARY_INTERVAL_SECS`.
+    pub fn summaries(&mut self, now: Instant) -> Vec<Summary<K>> {
+        let quiet = Duration::from_secs(QUIET_PERIOD_SECS);
+        let interval = Duration::from_secs(SUMMARY_INTERVAL_SECS);
+
+        let mut summaries = Vec::new();
+        for (key, client) in &mut self.clients {
+            let (ended, overdue) = match client.rejected {
+                Some(ref rejected) => (now >= rejected.last + quiet,
+                                       now >= rejected.since + interval),
+                None => continue,
+            };
+
+            if !ended && !overdue {
+                continue;
+            }
+
+            let rejected = client.rejected.take().unwrap();
+            summaries.push(Summary {
+                client: key.clone(),
+                duration: rejected.last - rejected.since,
+                requests: rejected.requests,
+                messages: rejected.messages,
+                bytes: rejected.bytes,
+                ongoing: !ended,
+            });
+        }
+
+        summaries
+    }
+}
@@ -1,15 +1,55 @@
-use blocks;
-use rpc::Client;
+use blocks::{self, SensorSignature, Sequence};
+use envelope;
+use journal::{self, Cursor, CursorFile};
+use rpc::{self, capabilities, Client, ErrorKind};
+use sensors::SensorKey;
+use sequence::SourceCounter;
+use spool::{Spool, SpoolEntry};
 
-use std::io::{Read, BufReader, BufRead};
+use std::cmp;
+use std::io::{self, Read, BufReader, BufRead};
+use std::mem;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use human_size::Size;
 
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The maximum number of parts of a transaction.
+pub const MAX_TRANSACTION_PARTS: usize = 1024;
+/// Lines are split after this many bytes, the maximum size of a block
+/// that may still be encrypted.
+pub const MAX_LINE_PART: usize = envelope::MAX_PAYLOAD;
+/// Parts of a split line that are sent in one request. Together with the
+/// context of a continued line they still fit into a frame.
+pub const MAX_SPLIT_PARTS: usize = 14;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BlockRecipe {
     Rekey,
     Info(Vec<u8>),
+    /// Messages that are written as one group, each part is an info block
+    Transaction(Vec<Vec<u8>>),
+    /// A line that exceeded the maximum block size, the parts are written
+    /// like a transaction and followed by an alert
+    SplitLine(Vec<Vec<u8>>),
+    /// More parts of a line that didn't fit into one request, written like
+    /// `SplitLine`. The first field is the last part that has already been
+    /// written, it's only used to match rules across the boundary
+    ContinuedLine(Vec<u8>, Vec<Vec<u8>>),
+    /// A `SplitLine` that has been numbered by its source, the number is
+    /// stored in the first part
+    SequencedLine(Sequence, Vec<Vec<u8>>),
+    /// Messages from the spool of the client, each one is written into an
+    /// info block that records when it has been spooled, followed by an
+    /// alert
+    Replay(Vec<SpoolEntry>),
+    /// A message that has been numbered by its source
+    Sequenced(Sequence, Vec<u8>),
+    /// An `Info` or `Sequenced` message that has been signed by the sensor,
+    /// the signature is stored in the block
+    Signed(SensorSignature, Box<BlockRecipe>),
 }
 
 impl BlockRecipe {
@@ -17,8 +57,174 @@ impl BlockRecipe {
         blocks::validate_block_size(buf.len())?;
         Ok(BlockRecipe::Info(buf))
     }
+
+    pub fn transaction(parts: Vec<Vec<u8>>) -> Result<BlockRecipe, blocks::Error> {
+        validate_parts(&parts)?;
+        Ok(BlockRecipe::Transaction(parts))
+    }
+
+    pub fn split_line(parts: Vec<Vec<u8>>) -> Result<BlockRecipe, blocks::Error> {
+        validate_parts(&parts)?;
+        Ok(BlockRecipe::SplitLine(parts))
+    }
+
+    pub fn continued_line(context: Vec<u8>, parts: Vec<Vec<u8>>) -> Result<BlockRecipe, blocks::Error> {
+        blocks::validate_block_size(context.len())?;
+        validate_parts(&parts)?;
+        Ok(BlockRecipe::ContinuedLine(context, parts))
+    }
+
+    pub fn sequenced_line(seq: Sequence, parts: Vec<Vec<u8>>) -> Result<BlockRecipe, blocks::Error> {
+        if !blocks::validate_source(&seq.source) {
+            bail!(format!("invalid source: {:?}", seq.source));
+        }
+        validate_parts(&parts)?;
+        Ok(BlockRecipe::SequencedLine(seq, parts))
+    }
+
+    pub fn replay(entries: Vec<SpoolEntry>) -> Result<BlockRecipe, blocks::Error> {
+        if entries.len() > MAX_TRANSACTION_PARTS {
+            bail!(blocks::ErrorKind::BlockTooLarge);
+        }
+        for entry in &entries {
+            blocks::validate_block_size(entry.msg.len())?;
+        }
+        Ok(BlockRecipe::Replay(entries))
+    }
+
+    pub fn sequenced(seq: Sequence, buf: Vec<u8>) -> Result<BlockRecipe, blocks::Error> {
+        if !blocks::validate_source(&seq.source) {
+            bail!(format!("invalid source: {:?}", seq.source));
+        }
+        blocks::validate_block_size(buf.len())?;
+        Ok(BlockRecipe::Sequenced(seq, buf))
+    }
+
+    pub fn signed(sig: SensorSignature, inner: BlockRecipe) -> Result<BlockRecipe, blocks::Error> {
+        if inner.sensor_payload().is_none() {
+            bail!("only single messages can be signed by a sensor");
+        }
+        Ok(BlockRecipe::Signed(sig, Box::new(inner)))
+    }
+
+    /// The sequence number and the message a sensor signs, only single
+    /// messages can be signed.
+    pub fn sensor_payload(&self) -> Option<(Option<&Sequence>, &[u8])> {
+        match *self {
+            BlockRecipe::Info(ref bytes) => Some((None, bytes)),
+            BlockRecipe::Sequenced(ref seq, ref bytes) => Some((Some(seq), bytes)),
+            _ => None,
+        }
+    }
+
+    /// The payload sizes of the info blocks this recipe is going to write.
+    pub fn sizes(&self) -> Vec<usize> {
+        match *self {
+            BlockRecipe::Rekey => vec![0],
+            BlockRecipe::Info(ref bytes) |
+            BlockRecipe::Sequenced(_, ref bytes) => vec![bytes.len()],
+            BlockRecipe::Transaction(ref parts) |
+            BlockRecipe::SplitLine(ref parts) |
+            BlockRecipe::ContinuedLine(_, ref parts) |
+            BlockRecipe::SequencedLine(_, ref parts) => parts.iter().map(|part| part.len()).collect(),
+            BlockRecipe::Replay(ref entries) => entries.iter().map(|entry| entry.msg.len()).collect(),
+            BlockRecipe::Signed(_, ref inner) => inner.sizes(),
+        }
+    }
+}
+
+fn validate_parts(parts: &[Vec<u8>]) -> Result<(), blocks::Error> {
+    if parts.len() > MAX_TRANSACTION_PARTS {
+        bail!(blocks::ErrorKind::BlockTooLarge);
+    }
+    for part in parts {
+        blocks::validate_block_size(part.len())?;
+    }
+    Ok(())
+}
+
+/// A message that has been read from a sensor.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    Msg(Vec<u8>),
+    /// A line that exceeded the maximum block size
+    Split(Vec<Vec<u8>>),
+    /// More parts of the line of the previous record
+    Continued(Vec<Vec<u8>>),
+}
+
+impl Record {
+    /// The line doesn't end in this record, unless the source ended.
+    pub fn is_unfinished(&self) -> bool {
+        let last = match *self {
+            Record::Msg(ref msg) => Some(msg),
+            Record::Split(ref parts) |
+            Record::Continued(ref parts) => parts.last(),
+        };
+        last.and_then(|part| part.last()) != Some(&b'\n')
+    }
+}
+
+/// Read the next line including the newline, the bytes are passed on as
+/// they are. Lines that are longer than [`MAX_LINE_PART`] are split, a line
+/// that doesn't even fit into [`MAX_TRANSACTION_PARTS`] parts is continued
+/// in the next record. `continued` is set if the previous record was
+/// unfinished, the rest of its line is returned as `Record::Continued`.
+///
+/// [`MAX_LINE_PART`]: constant.MAX_LINE_PART.html
+/// [`MAX_TRANSACTION_PARTS`]: constant.MAX_TRANSACTION_PARTS.html
+pub fn read_line<B: BufRead>(src: &mut B, continued: bool) -> io::Result<Option<Record>> {
+    let mut parts = Vec::new();
+
+    loop {
+        let mut part = Vec::new();
+        src.by_ref()
+            .take(MAX_LINE_PART as u64)
+            .read_until(b'\n', &mut part)?;
+
+        if part.is_empty() {
+            break;
+        }
+
+        let done = part.last() == Some(&b'\n');
+        parts.push(part);
+
+        if done || parts.len() >= MAX_TRANSACTION_PARTS {
+            break;
+        }
+    }
+
+    Ok(match parts.len() {
+        0 => None,
+        _ if continued => Some(Record::Continued(parts)),
+        1 => parts.pop().map(Record::Msg),
+        _ => Some(Record::Split(parts)),
+    })
 }
 
+/// Split the spool into recipes that don't exceed the limits of a batch.
+fn replay_chunks(entries: Vec<SpoolEntry>, batch_size: usize) -> Vec<Vec<SpoolEntry>> {
+    let max_len = cmp::min(cmp::max(batch_size, 1), MAX_TRANSACTION_PARTS);
+
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_bytes = 0;
+
+    for entry in entries {
+        if !chunk.is_empty() && (chunk.len() >= max_len || chunk_bytes + entry.msg.len() > MAX_BATCH_BYTES) {
+            chunks.push(mem::replace(&mut chunk, Vec::new()));
+            chunk_bytes = 0;
+        }
+        chunk_bytes += entry.msg.len();
+        chunk.push(entry);
+    }
+
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
 
 pub fn parse_size(size: &str) -> Result<usize, String> {
 
@@ -31,35 +237,143 @@ pub fn parse_size(size: &str) -> Result<usize, String> {
         },
     };
 
-    if size >= 65536 {
-        eprintln!("WARN: --size exceeds maximum block size, caping to 65535");
-        size = 65535;
+    if size > envelope::MAX_PAYLOAD {
+        eprintln!("WARN: --size exceeds maximum block size, caping to {}", envelope::MAX_PAYLOAD);
+        size = envelope::MAX_PAYLOAD;
     }
 
     Ok(size)
 }
 
+/// Default number of lines that are sent to the daemon at once.
+pub const DEFAULT_BATCH_SIZE: usize = 64;
+
+/// The daemon rejects batches that are larger than `rpc::MAX_BATCH_LEN`.
+pub fn parse_batch_size(size: &str) -> Result<usize, String> {
+    match size.parse::<usize>() {
+        Ok(0) => Err("batch size can't be 0".to_string()),
+        Ok(size) if size > rpc::MAX_BATCH_LEN => {
+            Err(format!("batch size can't exceed {}", rpc::MAX_BATCH_LEN))
+        },
+        Ok(size) => Ok(size),
+        Err(_) => Err("failed to parse batch size".to_string()),
+    }
+}
+/// Default time to wait for more lines before the batch is sent.
+pub const DEFAULT_BATCH_DELAY_MS: u64 = 100;
+/// Flush the batch before it gets larger than this.
+const MAX_BATCH_BYTES: usize = 512 * 1024;
+/// Seconds to wait before trying to reach the daemon again, messages are
+/// spooled in the meantime.
+const SPOOL_RETRY_SECS: u64 = 5;
+
 pub struct InfoBlockPipe<R: Read> {
     pub quiet: bool,
+    /// Maximum number of messages per batch, 1 disables batching. This is
+    /// capped to `rpc::MAX_BATCH_LEN`.
+    pub batch_size: usize,
+    /// Maximum time a message waits for more messages before it's sent.
+    pub batch_delay: Duration,
+    /// Write everything as one transaction after the source is closed.
+    pub transaction: bool,
+    /// Messages that can't be delivered are stored here and replayed later.
+    pub spool: Option<Spool>,
+    /// Number the messages of this source, see [`BlockRecipe::Sequenced`].
+    ///
+    /// [`BlockRecipe::Sequenced`]: enum.BlockRecipe.html#variant.Sequenced
+    pub source: Option<SourceCounter>,
+    /// Sign each message with the key of the sensor.
+    pub sign_key: Option<SensorKey>,
     client: Client,
     src: Option<R>,
+    /// Don't try to reach the daemon before this.
+    retry_at: Option<Instant>,
+    /// The last part of the previous split line, if the next record continues it.
+    split_context: Option<Vec<u8>>,
 }
 
-impl<R: Read> InfoBlockPipe<R> {
+impl<R: Read + Send + 'static> InfoBlockPipe<R> {
     #[inline]
     pub fn new(client: Client, src: R) -> InfoBlockPipe<R> {
         InfoBlockPipe {
             quiet: false,
+            batch_size: DEFAULT_BATCH_SIZE,
+            batch_delay: Duration::from_millis(DEFAULT_BATCH_DELAY_MS),
+            transaction: false,
+            spool: None,
+            source: None,
+            sign_key: None,
             client,
             src: Some(src),
+            retry_at: None,
+            split_context: None,
         }
     }
 
     #[inline]
-    pub fn write(&mut self, buf: Vec<u8>) -> Result<(), ()> {
-        // TODO: panics
-        let block = BlockRecipe::info(buf).expect("couldn't build block recipe");
-        let pointer = self.client.write_block(block).expect("write block");
+    pub fn write(&mut self, buf: Vec<u8>) -> rpc::Result<()> {
+        self.write_batch(vec![buf])
+    }
+
+    /// Write the messages in one round trip, each message is acknowledged
+    /// with its own pointer.
+    pub fn write_batch(&mut self, bufs: Vec<Vec<u8>>) -> rpc::Result<()> {
+        let blocks = match self.source {
+            Some(ref mut source) => {
+                if !self.client.server().has(capabilities::SEQUENCES) {
+                    return Err("daemon doesn't support sequence numbers".into());
+                }
+
+                // numbers of messages that fail are lost, fsck reports them as a gap
+                let seqs = source.assign(bufs.len())?;
+                seqs.into_iter()
+                    .zip(bufs)
+                    .map(|(seq, buf)| BlockRecipe::sequenced(seq, buf))
+                    .collect::<Result<Vec<_>, _>>()?
+            },
+            None => bufs.into_iter()
+                .map(BlockRecipe::info)
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+
+        let blocks = match self.sign_key {
+            Some(ref key) => {
+                if !self.client.server().has(capabilities::SENSOR_SIGNATURES) {
+                    return Err("daemon doesn't support sensor signatures".into());
+                }
+                blocks.into_iter()
+                    .map(|block| key.sign(block))
+                    .collect::<Result<Vec<_>, _>>()?
+            },
+            None => blocks,
+        };
+        let len = blocks.len();
+
+        let pointers = if len == 1 {
+            let block = blocks.into_iter().next().unwrap();
+            vec![self.client.write_block(block)?]
+        } else {
+            self.client.write_batch(blocks)?
+        };
+
+        if !self.quiet {
+            for pointer in &pointers {
+                println!("{:x}", pointer);
+            }
+        }
+
+        if pointers.len() != len {
+            return Err(ErrorKind::PartialBatch(pointers.len(), len).into());
+        }
+
+        Ok(())
+    }
+
+    /// Write the messages as one transaction, see [`BlockRecipe::Transaction`].
+    ///
+    /// [`BlockRecipe::Transaction`]: enum.BlockRecipe.html#variant.Transaction
+    pub fn write_transaction(&mut self, bufs: Vec<Vec<u8>>) -> rpc::Result<()> {
+        let pointer = self.client.write_transaction(bufs)?;
 
         if !self.quiet {
             println!("{:x}", pointer);
@@ -68,29 +382,408 @@ impl<R: Read> InfoBlockPipe<R> {
         Ok(())
     }
 
-    #[inline]
-    pub fn start_lines(&mut self) {
-        let src = BufReader::new(self.src.take().unwrap());
-        for line in src.lines() {
-            // discard invalid lines
-            if let Ok(mut line) = line {
-                line.push('\n');
-                self.write(line.as_bytes().to_vec()).expect("write failed");
+    /// Write the parts of a line that exceeded the maximum block size, see
+    /// [`BlockRecipe::SplitLine`]. The parts continue the previous line if
+    /// there's a split context.
+    ///
+    /// [`BlockRecipe::SplitLine`]: enum.BlockRecipe.html#variant.SplitLine
+    pub fn write_split_line(&mut self, parts: Vec<Vec<u8>>) -> rpc::Result<()> {
+        let len = parts.iter().map(|part| part.len()).sum::<usize>();
+        info!("line of {} bytes is split into {} parts", len, parts.len());
+        if self.sign_key.is_some() {
+            warn!("the parts of a line that has been split aren't signed");
+        }
+
+        // a line that continues has been numbered with its first parts
+        let context = self.split_context.clone();
+        let seq = match self.source {
+            Some(ref mut source) if context.is_none() => {
+                if self.client.server().has(capabilities::SEQUENCED_LINES) {
+                    source.assign(1)?.pop()
+                } else {
+                    warn!("daemon doesn't support numbered split lines, the line isn't numbered");
+                    None
+                }
+            },
+            _ => None,
+        };
+        let pointer = self.client.write_split_line(parts, context, seq)?;
+
+        if !self.quiet {
+            println!("{:x}", pointer);
+        }
+
+        Ok(())
+    }
+
+    /// Wait for the daemon and disconnect. If the daemon is unreachable,
+    /// the messages stay in the spool.
+    pub fn close(mut self) -> rpc::Result<()> {
+        if self.spool.is_some() {
+            self.retry_at = None;
+            if !self.replay_spool()? {
+                let len = self.spool.as_ref().map(Spool::len).unwrap_or(0);
+                warn!("daemon is unreachable, {} messages remain in the spool", len);
+                return Ok(());
             }
         }
+
+        self.client.close()
+    }
+
+    /// Replay the spool in order. Returns `false` if the daemon is still
+    /// unreachable, the remaining messages stay in the spool.
+    pub fn replay_spool(&mut self) -> rpc::Result<bool> {
+        if let Some(retry_at) = self.retry_at {
+            if Instant::now() < retry_at {
+                return Ok(false);
+            }
+        }
+
+        match self.try_replay() {
+            Ok(_) => {
+                self.retry_at = None;
+                Ok(true)
+            },
+            Err(ref err) if err.is_unreachable() || err.is_temporary() => {
+                self.unreachable(err);
+                Ok(false)
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    fn try_replay(&mut self) -> rpc::Result<()> {
+        // the daemon might have been down when the client has been created
+        if self.client.server().version == 0 {
+            self.client.hello()?;
+        }
+
+        let spool = match self.spool {
+            Some(ref mut spool) if !spool.is_empty() => spool,
+            _ => return Ok(()),
+        };
+
+        let entries = spool.pending()?;
+        info!("replaying {} messages from {:?}", entries.len(), spool.path());
+
+        for chunk in replay_chunks(entries, self.batch_size) {
+            let last = chunk.last().map(|entry| entry.seq).expect("chunks aren't empty");
+            let pointer = self.client.write_replay(chunk)?;
+            spool.replayed(last)?;
+
+            if !self.quiet {
+                println!("{:x}", pointer);
+            }
+        }
+
+        Ok(())
     }
 
+    fn unreachable(&mut self, err: &rpc::Error) {
+        warn!("daemon is unreachable, spooling messages: {}", err);
+        self.retry_at = Some(Instant::now() + Duration::from_secs(SPOOL_RETRY_SECS));
+    }
+
+    /// Write the messages with `write`. If there's a spool, it's replayed
+    /// first to keep the order and the messages are spooled if the daemon
+    /// is unreachable or rejected them for now.
+    fn deliver<F>(&mut self, msgs: Vec<Vec<u8>>, write: F) -> rpc::Result<()>
+        where F: FnOnce(&mut Self, Vec<Vec<u8>>) -> rpc::Result<()>
+    {
+        if self.spool.is_none() {
+            return write(self, msgs);
+        }
+
+        if self.replay_spool()? {
+            match write(self, msgs.clone()) {
+                Ok(_) => return Ok(()),
+                // the daemon might have written the messages, they would
+                // be written twice if they are replayed
+                Err(ref err) if err.is_timeout() => {
+                    error!("daemon didn't reply, {} messages might not have been written", msgs.len());
+                    return Err(ErrorKind::Timeout.into());
+                },
+                Err(ref err) if err.is_unreachable() || err.is_temporary() => self.unreachable(err),
+                Err(err) => return Err(err),
+            }
+        }
+
+        if let Some(ref mut spool) = self.spool {
+            for msg in &msgs {
+                spool.push(msg)?;
+            }
+            debug!("spooled {} messages, {} messages in the spool", msgs.len(), spool.len());
+        }
+
+        Ok(())
+    }
+
+    /// Read from the source in a background thread so we can send a partial
+    /// batch after `batch_delay` even if the source blocks.
     #[inline]
-    pub fn start_bytes(&mut self, size: usize) {
-        let mut src = self.src.take().unwrap();
+    fn pipe<F>(&mut self, reader: F) -> rpc::Result<()>
+        where F: FnOnce(R, mpsc::Sender<io::Result<Record>>) + Send + 'static
+    {
+        self.pipe_with(reader, |_| Ok(()))
+    }
+
+    /// Like `pipe`, `committed` is called with each message after the daemon
+    /// acknowledged it.
+    fn pipe_with<F, C>(&mut self, reader: F, mut committed: C) -> rpc::Result<()>
+        where F: FnOnce(R, mpsc::Sender<io::Result<Record>>) + Send + 'static,
+              C: FnMut(&[u8]) -> rpc::Result<()>
+    {
+        if self.transaction && self.spool.is_some() {
+            // the spool can't keep the messages together
+            return Err("transactions can't be spooled".into());
+        }
+        if self.source.is_some() && (self.transaction || self.spool.is_some()) {
+            return Err("sequence numbers can't be used with transactions or a spool".into());
+        }
+        if self.sign_key.is_some() && (self.transaction || self.spool.is_some()) {
+            return Err("sensor signatures can't be used with transactions or a spool".into());
+        }
+
+        let src = self.src.take().unwrap();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || reader(src, tx));
+
+        if self.transaction {
+            let mut parts: Vec<Vec<u8>> = Vec::new();
+            let mut parts_bytes = 0;
+            for record in rx {
+                let msg = match record? {
+                    Record::Msg(msg) => msg,
+                    // the parts of the line couldn't be told apart from the other messages
+                    Record::Split(_) |
+                    Record::Continued(_) => {
+                        if !parts.is_empty() {
+                            self.commit_transaction(parts, &mut committed)?;
+                        }
+                        return Err("line exceeds the maximum block size, it can't be part of a transaction".into());
+                    },
+                };
+
+                // a transaction has to fit into one request, the messages
+                // are kept in a new one instead of being dropped
+                if !parts.is_empty() && (parts.len() >= MAX_TRANSACTION_PARTS || parts_bytes + msg.len() > MAX_BATCH_BYTES) {
+                    warn!("transaction exceeds {} messages or {} bytes, continuing in a new transaction",
+                          MAX_TRANSACTION_PARTS, MAX_BATCH_BYTES);
+                    let full = mem::replace(&mut parts, Vec::new());
+                    self.commit_transaction(full, &mut committed)?;
+                    parts_bytes = 0;
+                }
+                parts_bytes += msg.len();
+                parts.push(msg);
+            }
+
+            if !parts.is_empty() {
+                self.commit_transaction(parts, &mut committed)?;
+            }
+            return Ok(());
+        }
+
+        let mut batch = Vec::new();
+        let mut batch_bytes = 0;
+        let mut deadline = None;
 
-        let mut buf = vec![0; size];
         loop {
-            let i = src.read(&mut buf).unwrap();
-            if i == 0 {
-                break;
+            let msg = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    let timeout = if deadline > now { deadline - now } else { Duration::from_secs(0) };
+                    match rx.recv_timeout(timeout) {
+                        Ok(msg) => Some(msg?),
+                        Err(mpsc::RecvTimeoutError::Timeout) => None,
+                        Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                    }
+                },
+                None => match rx.recv() {
+                    Ok(msg) => Some(msg?),
+                    Err(_) => break,
+                },
+            };
+
+            let msg = match msg {
+                Some(Record::Split(parts)) => {
+                    self.split_context = None;
+                    Err(parts)
+                },
+                Some(Record::Continued(parts)) => Err(parts),
+                Some(Record::Msg(msg)) => Ok(Some(msg)),
+                None => Ok(None),
+            };
+
+            let msg = match msg {
+                Err(parts) => {
+                    // messages that have been read before the line are written first
+                    if !batch.is_empty() {
+                        let msgs = mem::replace(&mut batch, Vec::new());
+                        self.commit_batch(msgs, &mut committed)?;
+                        batch_bytes = 0;
+                        deadline = None;
+                    }
+
+                    // a spooled line is replayed as separate messages
+                    let line = parts.concat();
+                    let last = parts.last().cloned().unwrap_or_default();
+                    self.deliver(parts, Self::write_split_line)?;
+                    self.split_context = Some(last);
+                    committed(&line)?;
+                    continue;
+                },
+                Ok(msg) => msg,
+            };
+
+            if let Some(msg) = msg {
+                if batch.is_empty() {
+                    deadline = Some(Instant::now() + self.batch_delay);
+                }
+                batch_bytes += msg.len();
+                batch.push(msg);
             }
-            self.write(buf[..i].to_vec()).expect("write failed");
+
+            let full = batch.len() >= cmp::min(self.batch_size, rpc::MAX_BATCH_LEN) ||
+                batch_bytes >= MAX_BATCH_BYTES;
+            let expired = deadline.map(|d| Instant::now() >= d).unwrap_or(false);
+
+            if !batch.is_empty() && (full || expired) {
+                let msgs = mem::replace(&mut batch, Vec::new());
+                self.commit_batch(msgs, &mut committed)?;
+                batch_bytes = 0;
+                deadline = None;
+            }
+        }
+
+        if !batch.is_empty() {
+            self.commit_batch(batch, &mut committed)?;
+        }
+
+        Ok(())
+    }
+
+    fn commit_transaction<C>(&mut self, parts: Vec<Vec<u8>>, committed: &mut C) -> rpc::Result<()>
+        where C: FnMut(&[u8]) -> rpc::Result<()>
+    {
+        self.write_transaction(parts.clone())?;
+        for part in &parts {
+            committed(part)?;
         }
+        Ok(())
+    }
+
+    fn commit_batch<C>(&mut self, msgs: Vec<Vec<u8>>, committed: &mut C) -> rpc::Result<()>
+        where C: FnMut(&[u8]) -> rpc::Result<()>
+    {
+        self.deliver(msgs.clone(), Self::write_batch)?;
+        for msg in &msgs {
+            committed(msg)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn start_lines(&mut self) -> rpc::Result<()> {
+        self.pipe(|src, tx| {
+            let mut src = BufReader::new(src);
+            let mut continued = false;
+            loop {
+                let record = match read_line(&mut src, continued) {
+                    Ok(Some(record)) => {
+                        continued = record.is_unfinished();
+                        Ok(record)
+                    },
+                    Ok(None) => break,
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => Err(err),
+                };
+                let failed = record.is_err();
+                if tx.send(record).is_err() || failed {
+                    break;
+                }
+            }
+        })
+    }
+
+    #[inline]
+    pub fn start_bytes(&mut self, size: usize) -> rpc::Result<()> {
+        self.pipe(move |mut src, tx| {
+            let mut buf = vec![0; size];
+            loop {
+                let msg = match src.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(i) => Ok(Record::Msg(buf[..i].to_vec())),
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => Err(err),
+                };
+                let failed = msg.is_err();
+                if tx.send(msg).is_err() || failed {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Read the journal export format, each entry is written into its own
+    /// block. If a cursor file is given, the cursor of each committed entry
+    /// is stored in it and entries up to that cursor are skipped.
+    pub fn start_journal(&mut self, cursor_file: Option<CursorFile>) -> rpc::Result<()> {
+        let last = match cursor_file {
+            Some(ref file) => match file.load()? {
+                Some(cursor) => {
+                    let parsed = Cursor::parse(&cursor);
+                    if parsed.is_none() {
+                        warn!("ignoring invalid cursor in {:?}: {:?}", file.path(), cursor);
+                    }
+                    parsed
+                },
+                None => None,
+            },
+            None => None,
+        };
+
+        self.pipe_with(move |src, tx| {
+            let mut src = BufReader::new(src);
+            loop {
+                let entry = match journal::read_entry(&mut src) {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => break,
+                    Err(err) => {
+                        let err = io::Error::new(io::ErrorKind::InvalidData, err.to_string());
+                        let _ = tx.send(Err(err));
+                        break;
+                    },
+                };
+
+                if let (Some(last), Some(cursor)) = (last.as_ref(), entry.cursor().and_then(Cursor::parse)) {
+                    if !cursor.is_after(last) {
+                        debug!("skipping entry that has already been written: {:?}", entry.cursor());
+                        continue;
+                    }
+                }
+
+                // large entries are written like a long line
+                let mut parts = entry.parts();
+                let record = if parts.len() > 1 {
+                    Record::Split(parts)
+                } else {
+                    Record::Msg(parts.pop().unwrap_or_default())
+                };
+
+                if tx.send(Ok(record)).is_err() {
+                    break;
+                }
+            }
+        }, |msg| {
+            if let Some(ref file) = cursor_file {
+                if let Some(entry) = journal::read_entry(&mut &msg[..])? {
+                    if let Some(cursor) = entry.cursor() {
+                        file.store(cursor)?;
+                    }
+                }
+            }
+            Ok(())
+        })
     }
 }
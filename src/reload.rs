@@ -0,0 +1,159 @@
+//! Reloading parts of the config while the daemon is running.
+//!
+//! The config file and the socket are usually outside of the datadir. The
+//! config file is opened before the daemon enters the chroot, a handle to
+//! its directory would allow to leave the chroot again. The socket can only
+//! be reached while the daemon isn't in the chroot.
+use log;
+
+use config::{self, Config};
+use sandbox;
+
+use std::fs::{self, File, Permissions};
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+mod errors {
+    use std::io;
+
+    error_chain! {
+        links {
+            Config(::config::Error, ::config::ErrorKind);
+        }
+        foreign_links {
+            Io(io::Error);
+        }
+    }
+}
+pub use self::errors::{Result, Error, ErrorKind};
+
+
+/// A file that stays readable after the daemon entered the chroot.
+#[derive(Debug)]
+pub struct Anchor {
+    file: File,
+    path: PathBuf,
+}
+
+impl Anchor {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Anchor> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+
+        Ok(Anchor {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Outside of the chroot the file is opened again, editors usually
+    /// replace the file instead of writing to it. Inside the chroot only the
+    /// file that has been opened at startup can be read.
+    pub fn read(&self) -> Result<Vec<u8>> {
+        if sandbox::chrooted() {
+            self.read_opened()
+        } else {
+            let mut buf = Vec::new();
+            File::open(&self.path)?.read_to_end(&mut buf)?;
+            Ok(buf)
+        }
+    }
+
+    /// Read the file that has been opened at startup.
+    pub fn read_opened(&self) -> Result<Vec<u8>> {
+        let mut file = self.file.try_clone()?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// The filesystem path of a `unix://` or `ipc://` socket.
+pub fn socket_path(url: &str) -> Option<&str> {
+    if url.starts_with("unix://") || url.starts_with("ipc://") {
+        url.splitn(2, "://").nth(1)
+    } else {
+        None
+    }
+}
+
+/// Applies the settings that can be changed without a restart.
+#[derive(Debug)]
+pub struct Reloader {
+    config: Option<Anchor>,
+    socket: Option<PathBuf>,
+    /// The permissions that have been applied to the socket
+    socket_mode: Option<u32>,
+    /// `RUST_LOG` was set, the log level of the config is ignored
+    log_from_env: bool,
+}
+
+impl Reloader {
+    /// Needs to be called before the daemon enters the chroot.
+    pub fn new(config: Option<&Path>, socket: &str, log_from_env: bool) -> Result<Reloader> {
+        let config = match config {
+            Some(path) => Some(Anchor::open(path)?),
+            None => None,
+        };
+
+        let socket = socket_path(socket).map(PathBuf::from);
+
+        Ok(Reloader {
+            config,
+            socket,
+            socket_mode: None,
+            log_from_env,
+        })
+    }
+
+    /// Read the config file again and apply it. A daemon without config
+    /// file keeps its settings.
+    pub fn reload(&mut self) -> Result<()> {
+        let anchor = match self.config {
+            Some(ref anchor) => anchor,
+            None => {
+                warn!("reload: daemon was started without config file");
+                return Ok(());
+            },
+        };
+
+        let buf = anchor.read()?;
+        let config = Config::parse(&String::from_utf8_lossy(&buf))?;
+        info!("reload: using config from {:?}", anchor.path());
+
+        self.apply(&config)
+    }
+
+    /// Settings are validated before anything is changed.
+    pub fn apply(&mut self, config: &Config) -> Result<()> {
+        let level = config.log_level()?;
+        let mode = config.socket_mode()?;
+
+        if !self.log_from_env {
+            log::set_max_level(level.unwrap_or(config::DEFAULT_LOG_LEVEL));
+        }
+
+        match (mode, self.socket.as_ref()) {
+            (Some(mode), Some(socket)) if self.socket_mode != Some(mode) => {
+                if sandbox::chrooted() {
+                    warn!("reload: {:?} is outside of the chroot, socket_mode requires a restart", socket);
+                } else {
+                    debug!("setting permissions of {:?} to {:o}", socket, mode);
+                    fs::set_permissions(socket, Permissions::from_mode(mode))?;
+                    self.socket_mode = Some(mode);
+                }
+            },
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use zmq;
+use sodiumoxide::crypto::box_;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::thread;
+
+use rpc::errors::{Result, ErrorKind};
+
+/// The well-known endpoint libzmq sends authentication requests to.
+const ZAP_ENDPOINT: &str = "inproc://zeromq.zap.01";
+pub const ZAP_DOMAIN: &str = "tr1pd";
+
+
+/// A CURVE key, either public or secret.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurveKey(pub [u8; 32]);
+
+impl CurveKey {
+    pub fn from_slice(bytes: &[u8]) -> Result<CurveKey> {
+        if bytes.len() == 32 {
+            let mut key = [0; 32];
+            key.copy_from_slice(bytes);
+            Ok(CurveKey(key))
+        } else {
+            Err(ErrorKind::InvalidCurveKey.into())
+        }
+    }
+
+    /// Decode a key in the Z85 text format used by zmq.
+    pub fn from_z85(text: &str) -> Result<CurveKey> {
+        match zmq::z85_decode(text) {
+            Ok(bytes) => CurveKey::from_slice(&bytes),
+            Err(_) => Err(ErrorKind::InvalidCurveKey.into()),
+        }
+    }
+
+    /// Read a raw 32 byte key from a file.
+    pub fn load(path: &str) -> Result<CurveKey> {
+        let mut file = File::open(path)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        CurveKey::from_slice(&buf)
+    }
+
+    pub fn to_z85(&self) -> String {
+        zmq::z85_encode(&self.0).expect("32 bytes are always z85 encodable")
+    }
+
+    #[inline]
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Generate a new CURVE keypair, returns (public, secret).
+pub fn gen_keypair() -> (CurveKey, CurveKey) {
+    let (pk, sk) = box_::gen_keypair();
+    (CurveKey(pk.0), CurveKey(sk.0))
+}
+
+/// CURVE settings of the daemon socket.
+#[derive(Debug, Clone)]
+pub struct CurveServer {
+    pub public_key: CurveKey,
+    pub secret_key: CurveKey,
+    /// Client keys that are allowed to connect. If this is empty, every
+    /// client is rejected.
+    pub clients: Vec<CurveKey>,
+}
+
+impl CurveServer {
+    pub fn apply(&self, socket: &zmq::Socket) -> Result<()> {
+        socket.set_curve_server(true)?;
+        socket.set_curve_publickey(self.public_key.bytes())?;
+        socket.set_curve_secretkey(self.secret_key.bytes())?;
+        socket.set_zap_domain(ZAP_DOMAIN)?;
+        Ok(())
+    }
+}
+
+/// CURVE settings of a client socket.
+#[derive(Debug, Clone)]
+pub struct CurveClient {
+    pub public_key: CurveKey,
+    pub secret_key: CurveKey,
+    pub server_key: CurveKey,
+}
+
+impl CurveClient {
+    pub fn apply(&self, socket: &zmq::Socket) -> Result<()> {
+        socket.set_curve_publickey(self.public_key.bytes())?;
+        socket.set_curve_secretkey(self.secret_key.bytes())?;
+        socket.set_curve_serverkey(self.server_key.bytes())?;
+        Ok(())
+    }
+}
+
+/// Start a ZAP handler that only accepts CURVE clients in the allowlist.
+///
+/// This has to be called before the server socket is bound, otherwise the
+/// first connections could be handled before the handler is in place.
+pub fn start_authenticator(ctx: &zmq::Context, clients: Vec<CurveKey>) -> Result<()> {
+    let socket = ctx.socket(zmq::REP)?;
+    socket.bind(ZAP_ENDPOINT)?;
+
+    thread::spawn(move || {
+        loop {
+            if let Err(err) = zap_handle(&socket, &clients) {
+                error!("zap: {:?}", err);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Whether a ZAP request comes from a CURVE client in the allowlist. An
+/// empty allowlist rejects everybody.
+pub fn is_allowed(clients: &[CurveKey], mechanism: &[u8], credentials: &[Vec<u8>]) -> bool {
+    mechanism == b"CURVE" && credentials.len() == 1 && {
+        let key = &credentials[0];
+        clients.iter().any(|c| c.bytes() == &key[..])
+    }
+}
+
+/// Every request needs a reply, the handler is stuck otherwise.
+fn zap_handle(socket: &zmq::Socket, clients: &[CurveKey]) -> Result<()> {
+    let request = socket.recv_multipart(0)?;
+
+    // version, request_id, domain, address, identity, mechanism, credentials
+    let valid = request.len() >= 6 && request[0] == b"1.0";
+    let request_id = request.get(1).map(|x| &x[..]).unwrap_or(b"");
+
+    // the user id is the client key, it's attached to every message
+    let (status, text, user_id) = if !valid {
+        warn!("zap: invalid request: {:?}", request);
+        (&b"400"[..], &b"invalid request"[..], String::new())
+    } else if is_allowed(clients, &request[5], &request[6..]) {
+        let key = CurveKey::from_slice(&request[6])?.to_z85();
+        debug!("zap: accepted client {}", key);
+        (&b"200"[..], &b"OK"[..], key)
+    } else {
+        warn!("zap: rejected client {:?}", request.get(6));
+        (&b"400"[..], &b"client key not allowed"[..], String::new())
+    };
+
+    socket.send(b"1.0", zmq::SNDMORE)?;
+    socket.send(request_id, zmq::SNDMORE)?;
+    socket.send(status, zmq::SNDMORE)?;
+    socket.send(text, zmq::SNDMORE)?;
+    socket.send(user_id.as_bytes(), zmq::SNDMORE)?;
+    socket.send(b"", 0)?;
+
+    Ok(())
+}
@@ -1,19 +1,35 @@
-use zmq;
-
-use std::fs::{self, Permissions};
-use std::os::unix::fs::PermissionsExt;
-
-use blocks::BlockPointer;
-use recipe::BlockRecipe;
+use blocks::{BlockPointer, Sequence};
+use recipe::{BlockRecipe, MAX_SPLIT_PARTS};
+use spool::SpoolEntry;
 
+#[cfg(feature="zmq")]
+pub mod curve;
+pub mod unix;
 #[allow(unused_variables)]
 mod wire;
+#[cfg(feature="zmq")]
+mod zeromq;
+
+#[cfg(feature="zmq")]
+pub use self::curve::{CurveKey, CurveServer, CurveClient};
+use self::unix::{Peer, UnixServer, UnixClient};
+#[cfg(feature="zmq")]
+use self::zeromq::{ZmqServer, ZmqPeer, ZmqClient};
+use blocks::Origin;
+
+use sodiumoxide::randombytes;
+
+use std::fmt;
+use std::net::IpAddr;
+use std::thread;
+use std::time::Duration;
 
 mod errors {
     use std;
+    #[cfg(feature="zmq")]
     use zmq;
 
-    use rpc::CtlResponse;
+    use rpc::{CtlResponse, NackCode};
 
     error_chain! {
         errors {
@@ -30,141 +46,900 @@ mod errors {
                 display("invalid response: {:?}", resp)
 
             }
+            InvalidCurveKey {
+                description("invalid curve key")
+            }
+            FrameTooLarge(len: usize) {
+                description("frame too large")
+                display("frame too large: {} bytes", len)
+            }
 
             UnexpectedResponse(reply: CtlResponse) {
                 description("unexpected response")
                 display("unexpected response: {:?}", reply)
             }
+            Nack(code: NackCode, msg: String) {
+                description("daemon rejected request")
+                display("daemon rejected request: {}: {}", code, msg)
+            }
+            UnsupportedVersion(version: u16) {
+                description("unsupported protocol version")
+                display("unsupported protocol version: {}", version)
+            }
+            UnsupportedTransport(url: String) {
+                description("unsupported transport")
+                display("unsupported transport, tr1pd was built without zmq: {:?}", url)
+            }
+            Timeout {
+                description("timeout while waiting for the daemon")
+            }
+            PartialBatch(committed: usize, total: usize) {
+                description("daemon only committed part of the batch")
+                display("daemon only committed {}/{} messages of the batch", committed, total)
+            }
+        }
+
+        links {
+            Blocks(::blocks::Error, ::blocks::ErrorKind);
+            Journal(::journal::Error, ::journal::ErrorKind);
+            Sensors(::sensors::Error, ::sensors::ErrorKind);
+            Sequence(::sequence::Error, ::sequence::ErrorKind);
+            Spool(::spool::Error, ::spool::ErrorKind);
         }
 
         foreign_links {
             Io(std::io::Error);
-            Zmq(zmq::Error);
+            Zmq(zmq::Error) #[cfg(feature="zmq")];
         }
     }
 }
 pub use self::errors::{Result, Error, ErrorKind};
 
+impl Error {
+    /// The daemon couldn't be reached or didn't reply, as opposed to a
+    /// request that has been rejected.
+    pub fn is_unreachable(&self) -> bool {
+        match *self.kind() {
+            ErrorKind::Io(_) | ErrorKind::Timeout => true,
+            #[cfg(feature="zmq")]
+            ErrorKind::Zmq(_) => true,
+            _ => false,
+        }
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+    /// The request might have been written, but the daemon didn't reply.
+    pub fn is_timeout(&self) -> bool {
+        match *self.kind() {
+            ErrorKind::Timeout => true,
+            _ => false,
+        }
+    }
+
+    /// The daemon rejected the request without writing anything, it might
+    /// be accepted later.
+    pub fn is_temporary(&self) -> bool {
+        match *self.kind() {
+            ErrorKind::Nack(NackCode::RateLimited, _) => true,
+            ErrorKind::Nack(NackCode::StorageFailure, _) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The maximum number of recipes in a `WriteBatch`.
+pub const MAX_BATCH_LEN: usize = 1024;
+/// The maximum length of a request ID.
+pub const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Version of the rpc protocol, clients that don't send a `Hello` are version 0.
+pub const PROTOCOL_VERSION: u16 = 1;
+/// The oldest protocol version the daemon and the client accept.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Optional features of the protocol, exchanged in the `Hello` handshake.
+pub mod capabilities {
+    pub const BATCH: u32     = 1 << 0;
+    pub const ORIGIN: u32    = 1 << 1;
+    pub const STATUS: u32    = 1 << 2;
+    pub const LEDGERS: u32   = 1 << 3;
+    pub const TRANSACTIONS: u32 = 1 << 4;
+    pub const SPLIT_LINES: u32  = 1 << 5;
+    pub const SPOOL: u32        = 1 << 6;
+    pub const REQUEST_IDS: u32  = 1 << 7;
+    pub const SEQUENCES: u32    = 1 << 8;
+    pub const SENSOR_SIGNATURES: u32 = 1 << 9;
+    pub const CONTINUED_LINES: u32 = 1 << 10;
+    pub const SEQUENCED_LINES: u32 = 1 << 11;
+
+    /// Everything that is implemented by this version.
+    pub const ALL: u32 = BATCH | ORIGIN | STATUS | LEDGERS | TRANSACTIONS | SPLIT_LINES | SPOOL |
+                         REQUEST_IDS | SEQUENCES | SENSOR_SIGNATURES | CONTINUED_LINES |
+                         SEQUENCED_LINES;
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum CtlRequest {
     Ping,
-    Write(BlockRecipe),
+    /// Write to a ledger, `None` is the default ledger. A request that is
+    /// repeated with the same request ID is only committed once.
+    Write(Option<String>, BlockRecipe, Option<String>),
+    /// Write multiple blocks, the request ID works like it does for `Write`.
+    WriteBatch(Option<String>, Vec<BlockRecipe>, Option<String>),
+    Hello(Hello),
+    Status(Option<String>),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl CtlRequest {
+    /// The ledger the request refers to, `None` is the default ledger.
+    pub fn ledger(&self) -> Option<&str> {
+        match *self {
+            CtlRequest::Write(ref ledger, _, _) |
+            CtlRequest::WriteBatch(ref ledger, _, _) |
+            CtlRequest::Status(ref ledger) => ledger.as_ref().map(|name| name.as_str()),
+            CtlRequest::Ping | CtlRequest::Hello(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum CtlResponse {
     Pong,
     Ack(BlockPointer),
-    Nack,
+    Nack(NackCode, String),
+    /// One pointer for each recipe of a batch that has been committed, in
+    /// order. If this is shorter than the batch, the remaining recipes failed.
+    AckBatch(Vec<BlockPointer>),
+    Hello(Hello),
+    Status(Status),
 }
 
-pub struct Server {
-    #[allow(dead_code)]
-    ctx: zmq::Context,
-    socket: zmq::Socket,
+impl CtlResponse {
+    #[inline]
+    pub fn nack<I: Into<String>>(code: NackCode, msg: I) -> CtlResponse {
+        CtlResponse::Nack(code, msg.into())
+    }
 }
 
-impl Server {
-    pub fn bind(url: &str) -> Result<Server> {
-        let ctx = zmq::Context::new();
-        let socket = ctx.socket(zmq::REP)?;
+/// Request IDs are printable ASCII without spaces, so they can be grepped
+/// for in the logs of the sensor.
+pub fn validate_request_id(id: &str) -> bool {
+    !id.is_empty() &&
+        id.len() <= MAX_REQUEST_ID_LEN &&
+        id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// A random request ID, this is used for retries.
+pub fn random_request_id() -> String {
+    randombytes::randombytes(16).iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
 
-        socket.bind(url)?;
+/// Protocol version and capabilities of one side of the connection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hello {
+    pub version: u16,
+    pub capabilities: u32,
+}
 
-        // fix permissions
-        if url.starts_with("ipc://") {
-            // TODO: write a proper solution
-            let perms = Permissions::from_mode(0o770);
-            fs::set_permissions(&url[6..], perms)?;
+impl Hello {
+    #[inline]
+    pub fn current() -> Hello {
+        Hello {
+            version: PROTOCOL_VERSION,
+            capabilities: capabilities::ALL,
         }
+    }
 
-        Ok(Server {
-            ctx,
-            socket,
-        })
+    #[inline]
+    pub fn has(&self, capability: u32) -> bool {
+        self.capabilities & capability == capability
     }
+}
 
-    pub fn recv(&mut self) -> Result<CtlRequest> {
-        let bytes = self.socket.recv_msg(0)?;
-        debug!("ctl(req, raw): {:?}", bytes);
+/// State of the daemon, see `tr1pctl status`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub version: String,
+    /// Seconds since the daemon has been started
+    pub uptime: u64,
+    pub head: BlockPointer,
+    /// The init block of the current session
+    pub session: BlockPointer,
+    /// Blocks written in this session, including the init block
+    pub session_blocks: u64,
+    pub datadir: String,
+    /// Active sandbox stages, eg. `stage1`, `stage2` and `chroot`
+    pub sandbox: Vec<String>,
+    pub config_fingerprint: String,
+}
+
+/// The reason a request has been rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NackCode {
+    Unknown,
+    TooLarge,
+    StorageFailure,
+    RateLimited,
+    Forbidden,
+    InvalidRequest,
+    UnsupportedVersion,
+}
 
-        let request = CtlRequest::decode(&bytes)?;
-        debug!("ctl(req): {:?}", request);
-        Ok(request)
+impl NackCode {
+    pub fn from_byte(b: u8) -> NackCode {
+        match b {
+            0x01 => NackCode::TooLarge,
+            0x02 => NackCode::StorageFailure,
+            0x03 => NackCode::RateLimited,
+            0x04 => NackCode::Forbidden,
+            0x05 => NackCode::InvalidRequest,
+            0x06 => NackCode::UnsupportedVersion,
+            _    => NackCode::Unknown,
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        match *self {
+            NackCode::Unknown            => 0x00,
+            NackCode::TooLarge           => 0x01,
+            NackCode::StorageFailure     => 0x02,
+            NackCode::RateLimited        => 0x03,
+            NackCode::Forbidden          => 0x04,
+            NackCode::InvalidRequest     => 0x05,
+            NackCode::UnsupportedVersion => 0x06,
+        }
     }
+}
 
-    pub fn reply(&mut self, reply: &CtlResponse) -> Result<()> {
+impl fmt::Display for NackCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            NackCode::Unknown            => "unknown error",
+            NackCode::TooLarge           => "too large",
+            NackCode::StorageFailure     => "storage failure",
+            NackCode::RateLimited        => "rate limited",
+            NackCode::Forbidden          => "forbidden",
+            NackCode::InvalidRequest     => "invalid request",
+            NackCode::UnsupportedVersion => "unsupported version",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+enum ServerTransport {
+    #[cfg(feature="zmq")]
+    Zmq(ZmqServer),
+    Unix(UnixServer),
+}
+
+/// Identifies the client a reply has to be sent to. The token belongs to
+/// the transport that received the request, replies can be sent from any
+/// thread and are written by the frontend.
+#[derive(Debug, Clone)]
+pub struct ReplyToken(Token);
+
+#[derive(Debug, Clone)]
+enum Token {
+    #[cfg(feature="zmq")]
+    Zmq(ZmqPeer),
+    Unix(Peer),
+}
+
+impl ReplyToken {
+    pub fn reply(self, reply: &CtlResponse) -> Result<()> {
         debug!("ctl(resp): {:?}", reply);
 
         let mut bytes = Vec::new();
         reply.encode(&mut bytes);
-        self.socket.send(&bytes, 0)?;
 
-        Ok(())
+        match self.0 {
+            #[cfg(feature="zmq")]
+            Token::Zmq(peer) => peer.reply(&bytes),
+            Token::Unix(peer) => peer.reply(&bytes),
+        }
+    }
+}
+
+/// A request and the client that sent it.
+#[derive(Debug)]
+pub struct Request {
+    pub token: ReplyToken,
+    /// Credentials of the client, this is only available on native unix sockets.
+    pub origin: Option<Origin>,
+    pub msg: CtlRequest,
+}
+
+impl Request {
+    /// The identity that is used for rate limiting and request IDs.
+    pub fn client_id(&self) -> ClientId {
+        match (self.token).0 {
+            #[cfg(feature="zmq")]
+            Token::Zmq(ZmqPeer { key: Some(ref key), .. }) => ClientId::Curve(key.clone()),
+            #[cfg(feature="zmq")]
+            Token::Zmq(ZmqPeer { key: None, .. }) => ClientId::Anonymous,
+            Token::Unix(ref peer) => ClientId::Uid(peer.origin.uid),
+        }
+    }
+}
+
+/// Identifies a client for rate limiting.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClientId {
+    /// Local processes of the same user share their limits.
+    Uid(u32),
+    /// A zmq client, by its curve key in Z85.
+    Curve(String),
+    /// zmq clients without curve can't be told apart, they share their limits.
+    Anonymous,
+    /// A syslog sender, by its address. Local senders share their limits.
+    Syslog(Option<IpAddr>),
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientId::Uid(uid) => write!(f, "uid={}", uid),
+            ClientId::Curve(ref key) => write!(f, "curve={}", key),
+            ClientId::Anonymous => write!(f, "anonymous client"),
+            ClientId::Syslog(Some(addr)) => write!(f, "syslog={}", addr),
+            ClientId::Syslog(None) => write!(f, "local syslog"),
+        }
+    }
+}
+
+/// The frontend of the daemon.
+///
+/// Many clients can be connected at the same time, every request carries a
+/// [`ReplyToken`] so the reply can be sent later, possibly from a different
+/// thread.
+///
+/// [`ReplyToken`]: struct.ReplyToken.html
+pub struct Server {
+    transport: ServerTransport,
+}
+
+impl Server {
+    /// Bind to a zmq url, or a native unix socket with `unix:///path/to/sock`.
+    #[cfg(feature="zmq")]
+    pub fn bind(url: &str) -> Result<Server> {
+        Server::bind_curve(url, None)
+    }
+
+    /// Bind to a native unix socket with `unix:///path/to/sock`.
+    #[cfg(not(feature="zmq"))]
+    pub fn bind(url: &str) -> Result<Server> {
+        if url.starts_with("unix://") {
+            Server::bind_unix(&url[7..])
+        } else {
+            Err(ErrorKind::UnsupportedTransport(url.to_string()).into())
+        }
+    }
+
+    #[cfg(feature="zmq")]
+    pub fn bind_curve(url: &str, curve: Option<&CurveServer>) -> Result<Server> {
+        if url.starts_with("unix://") {
+            if curve.is_some() {
+                warn!("curve is not supported on native unix sockets, ignoring");
+            }
+
+            return Server::bind_unix(&url[7..]);
+        }
+
+        let server = ZmqServer::bind(url, curve)?;
+        Ok(Server {
+            transport: ServerTransport::Zmq(server),
+        })
+    }
+
+    fn bind_unix(path: &str) -> Result<Server> {
+        let server = UnixServer::bind(path)?;
+        Ok(Server {
+            transport: ServerTransport::Unix(server),
+        })
+    }
+
+    /// Wait for the next request. Replies are forwarded to their clients in
+    /// the meantime. Requests that can't be decoded are rejected right away.
+    pub fn recv(&mut self) -> Result<Request> {
+        loop {
+            let (token, origin, bytes) = match self.transport {
+                #[cfg(feature="zmq")]
+                ServerTransport::Zmq(ref mut server) => {
+                    match server.recv()? {
+                        Some((peer, bytes)) => (Token::Zmq(peer), None, bytes),
+                        None => continue,
+                    }
+                },
+                ServerTransport::Unix(ref mut server) => {
+                    let (peer, bytes) = server.recv()?;
+                    let origin = Some(peer.origin.clone());
+                    (Token::Unix(peer), origin, bytes)
+                },
+            };
+            debug!("ctl(req, raw): {:?}", bytes);
+
+            let token = ReplyToken(token);
+
+            match CtlRequest::decode(&bytes) {
+                Ok(msg) => {
+                    debug!("ctl(req): {:?}", msg);
+                    return Ok(Request {
+                        token,
+                        origin,
+                        msg,
+                    });
+                },
+                Err(err) => {
+                    warn!("received invalid request: {}", err);
+                    let reply = CtlResponse::nack(NackCode::InvalidRequest, "failed to decode request");
+                    self.reply(token, &reply)?;
+                },
+            }
+        }
+    }
+
+    /// Reply from the frontend thread, see [`ReplyToken::reply`].
+    ///
+    /// [`ReplyToken::reply`]: struct.ReplyToken.html#method.reply
+    pub fn reply(&mut self, token: ReplyToken, reply: &CtlResponse) -> Result<()> {
+        token.reply(reply)
     }
 }
 
+/// Default time to wait for the daemon before a request fails.
+pub const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+/// Default time `Client::close` waits for outstanding messages.
+pub const DEFAULT_LINGER_MS: u64 = 1_000;
+/// Delay between two attempts of the same request.
+const RETRY_DELAY_MS: u64 = 100;
+
+#[derive(Debug, Clone)]
 pub struct ClientBuilder {
     url: String,
+    #[cfg(feature="zmq")]
+    curve: Option<CurveClient>,
+    send_timeout: Option<Duration>,
+    recv_timeout: Option<Duration>,
+    /// Only used by zmq, native unix sockets don't queue anything.
+    #[cfg_attr(not(feature="zmq"), allow(dead_code))]
+    linger: Duration,
+    retries: usize,
+    ledger: Option<String>,
+    request_ids: bool,
 }
 
 impl ClientBuilder {
     pub fn new<I: Into<String>>(url: I) -> ClientBuilder {
         ClientBuilder {
             url: url.into(),
+            #[cfg(feature="zmq")]
+            curve: None,
+            send_timeout: Some(Duration::from_millis(DEFAULT_TIMEOUT_MS)),
+            recv_timeout: Some(Duration::from_millis(DEFAULT_TIMEOUT_MS)),
+            linger: Duration::from_millis(DEFAULT_LINGER_MS),
+            retries: 0,
+            ledger: None,
+            request_ids: false,
         }
     }
 
+    #[cfg(feature="zmq")]
+    pub fn curve(mut self, curve: CurveClient) -> ClientBuilder {
+        self.curve = Some(curve);
+        self
+    }
+
+    /// Set the send and receive timeout, `None` waits forever.
+    pub fn timeout(self, timeout: Option<Duration>) -> ClientBuilder {
+        self.send_timeout(timeout)
+            .recv_timeout(timeout)
+    }
+
+    pub fn send_timeout(mut self, timeout: Option<Duration>) -> ClientBuilder {
+        self.send_timeout = timeout;
+        self
+    }
+
+    pub fn recv_timeout(mut self, timeout: Option<Duration>) -> ClientBuilder {
+        self.recv_timeout = timeout;
+        self
+    }
+
+    pub fn linger(mut self, linger: Duration) -> ClientBuilder {
+        self.linger = linger;
+        self
+    }
+
+    /// How often a failed request is sent again on a new connection.
+    ///
+    /// Note that a request that timed out might still have been processed
+    /// by the daemon, retrying a write can commit the same message twice.
+    pub fn retries(mut self, retries: usize) -> ClientBuilder {
+        self.retries = retries;
+        self
+    }
+
+    /// Send each `Write` with a random request ID, a retried write is only
+    /// committed once. This is ignored if the daemon doesn't support it.
+    pub fn request_ids(mut self, enabled: bool) -> ClientBuilder {
+        self.request_ids = enabled;
+        self
+    }
+
+    /// Write to a named ledger instead of the default ledger. Status
+    /// requests also refer to this ledger.
+    pub fn ledger<I: Into<String>>(mut self, ledger: Option<I>) -> ClientBuilder {
+        self.ledger = ledger.map(|name| name.into());
+        self
+    }
+
+    /// Connect to the daemon and negotiate the protocol version.
     pub fn connect(&self) -> Result<Client> {
-        let ctx = zmq::Context::new();
-        let socket = ctx.socket(zmq::REQ)?;
+        let transport = self.connect_transport()?;
+
+        let mut client = self.build();
+        client.transport = Some(transport);
+
+        // daemons from before the handshake don't understand the request,
+        // they are treated as version 0 without any capabilities
+        if let Err(err) = client.hello() {
+            match *err.kind() {
+                ErrorKind::UnsupportedVersion(_) |
+                ErrorKind::Nack(NackCode::UnsupportedVersion, _) => return Err(err),
+                _ if err.is_unreachable() => return Err(err),
+                _ => warn!("handshake failed, assuming the daemon has no capabilities: {}", err),
+            }
+        }
 
-        socket.connect(&self.url)?;
+        Ok(client)
+    }
 
-        Ok(Client {
-            ctx,
-            socket,
-        })
+    /// Create a client without connecting to the daemon, [`Client::hello`]
+    /// needs to be called before the first request.
+    ///
+    /// [`Client::hello`]: struct.Client.html#method.hello
+    pub fn build(&self) -> Client {
+        Client {
+            builder: self.clone(),
+            transport: None,
+            server: Hello {
+                version: 0,
+                capabilities: 0,
+            },
+        }
+    }
+
+    fn connect_transport(&self) -> Result<ClientTransport> {
+        if self.url.starts_with("unix://") {
+            let client = UnixClient::connect(&self.url[7..])?;
+            client.set_timeouts(self.send_timeout, self.recv_timeout)?;
+            return Ok(ClientTransport::Unix(client));
+        }
+
+        self.connect_zmq()
+    }
+
+    #[cfg(feature="zmq")]
+    fn connect_zmq(&self) -> Result<ClientTransport> {
+        let client = ZmqClient::connect(&self.url,
+                                        self.curve.as_ref(),
+                                        self.send_timeout,
+                                        self.recv_timeout,
+                                        self.linger)?;
+        Ok(ClientTransport::Zmq(client))
+    }
+
+    #[cfg(not(feature="zmq"))]
+    fn connect_zmq(&self) -> Result<ClientTransport> {
+        Err(ErrorKind::UnsupportedTransport(self.url.clone()).into())
+    }
+}
+
+enum ClientTransport {
+    #[cfg(feature="zmq")]
+    Zmq(ZmqClient),
+    Unix(UnixClient),
+}
+
+impl ClientTransport {
+    fn roundtrip(&mut self, bytes: &[u8]) -> Result<Vec<u8>> {
+        match *self {
+            #[cfg(feature="zmq")]
+            ClientTransport::Zmq(ref client) => client.roundtrip(bytes),
+            ClientTransport::Unix(ref mut client) => {
+                client.send(bytes)
+                    .and_then(|_| client.recv())
+            },
+        }
     }
 }
 
+/// Client for the daemon socket.
+///
+/// If a request fails, the connection is dropped and a new one is opened for
+/// the next request. This resets the REQ socket, which would refuse to send
+/// anything until it received the reply that never arrived.
 pub struct Client {
-    #[allow(dead_code)]
-    ctx: zmq::Context,
-    socket: zmq::Socket,
+    builder: ClientBuilder,
+    transport: Option<ClientTransport>,
+    server: Hello,
 }
 
 impl Client {
+    /// Exchange protocol versions and capabilities with the daemon.
+    pub fn hello(&mut self) -> Result<&Hello> {
+        let reply = self.send(&CtlRequest::Hello(Hello::current()))?;
+
+        match reply {
+            CtlResponse::Hello(hello) => {
+                if hello.version < MIN_PROTOCOL_VERSION {
+                    return Err(ErrorKind::UnsupportedVersion(hello.version).into());
+                }
+                debug!("daemon speaks protocol version {} (capabilities: {:#x})",
+                       hello.version, hello.capabilities);
+                self.server = hello;
+                Ok(&self.server)
+            },
+            CtlResponse::Nack(code, msg) => Err(ErrorKind::Nack(code, msg).into()),
+            _ => Err(ErrorKind::UnexpectedResponse(reply).into()),
+        }
+    }
+
+    /// The version and capabilities the daemon announced.
+    #[inline]
+    pub fn server(&self) -> &Hello {
+        &self.server
+    }
+
+    /// The ledger that is written to, daemons that don't support named
+    /// ledgers only have the default ledger.
+    fn ledger(&self) -> Result<Option<String>> {
+        match self.builder.ledger {
+            Some(_) if !self.server.has(capabilities::LEDGERS) => {
+                Err("daemon doesn't support named ledgers".into())
+            },
+            ref ledger => Ok(ledger.clone()),
+        }
+    }
+
     pub fn send(&mut self, req: &CtlRequest) -> Result<CtlResponse> {
         debug!("ctl(req): {:?}", req);
 
         let mut bytes = Vec::new();
         req.encode(&mut bytes);
-        self.socket.send(&bytes, 0)?;
 
-        let bytes = self.socket.recv_msg(0)?;
+        let mut attempt = 0;
+        let bytes = loop {
+            match self.roundtrip(&bytes) {
+                Ok(bytes) => break bytes,
+                Err(err) => {
+                    // the connection is in an unknown state, start over
+                    self.transport = None;
+
+                    if attempt >= self.builder.retries {
+                        return Err(err);
+                    }
+                    attempt += 1;
+
+                    warn!("request failed, retrying ({}/{}): {}", attempt, self.builder.retries, err);
+                    thread::sleep(Duration::from_millis(RETRY_DELAY_MS * attempt as u64));
+                },
+            }
+        };
+
         let reply = CtlResponse::decode(&bytes)?;
         debug!("ctl(reply): {:?}", reply);
 
         Ok(reply)
     }
 
+    fn roundtrip(&mut self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let transport = match self.transport.take() {
+            Some(transport) => transport,
+            None => {
+                debug!("connecting to {:?}", self.builder.url);
+                self.builder.connect_transport()?
+            },
+        };
+
+        self.transport.get_or_insert(transport).roundtrip(bytes)
+    }
+
+    pub fn ping(&mut self) -> Result<()> {
+        let reply = self.send(&CtlRequest::Ping)?;
+
+        match reply {
+            CtlResponse::Pong => Ok(()),
+            CtlResponse::Nack(code, msg) => Err(ErrorKind::Nack(code, msg).into()),
+            _ => Err(ErrorKind::UnexpectedResponse(reply).into()),
+        }
+    }
+
+    pub fn status(&mut self) -> Result<Status> {
+        if !self.server.has(capabilities::STATUS) {
+            return Err("daemon doesn't support status requests".into());
+        }
+
+        let ledger = self.ledger()?;
+        let reply = self.send(&CtlRequest::Status(ledger))?;
+
+        match reply {
+            CtlResponse::Status(status) => Ok(status),
+            CtlResponse::Nack(code, msg) => Err(ErrorKind::Nack(code, msg).into()),
+            _ => Err(ErrorKind::UnexpectedResponse(reply).into()),
+        }
+    }
+
+    /// Wait until the daemon processed everything that has been sent so far.
+    ///
+    /// Every write is acknowledged on its own, so this is a round trip that
+    /// fails if the daemon is gone.
+    #[inline]
+    pub fn flush(&mut self) -> Result<()> {
+        self.ping()
+    }
+
+    /// Flush and disconnect. Dropping the client without closing it doesn't
+    /// wait for anything.
+    pub fn close(mut self) -> Result<()> {
+        self.flush()?;
+        self.transport = None;
+        Ok(())
+    }
+
     #[inline]
     pub fn write_block(&mut self, block: BlockRecipe) -> Result<BlockPointer> {
-        let reply = self.send(&CtlRequest::Write(block))?;
+        let id = if self.builder.request_ids && self.server.has(capabilities::REQUEST_IDS) {
+            Some(random_request_id())
+        } else {
+            None
+        };
+        self.write_block_with_id(block, id)
+    }
+
+    /// Write a block with a request ID that is stored in the block. If the
+    /// daemon already committed a request with this ID, the pointer of the
+    /// original block is returned.
+    pub fn write_block_with_id(&mut self, block: BlockRecipe, id: Option<String>) -> Result<BlockPointer> {
+        if let Some(ref id) = id {
+            if !self.server.has(capabilities::REQUEST_IDS) {
+                return Err("daemon doesn't support request ids".into());
+            }
+            if !validate_request_id(id) {
+                return Err(format!("invalid request id: {:?}", id).into());
+            }
+        }
+
+        let ledger = self.ledger()?;
+        let reply = self.send(&CtlRequest::Write(ledger, block, id))?;
 
         match reply {
             CtlResponse::Ack(pointer) => Ok(pointer),
+            CtlResponse::Nack(code, msg) => Err(ErrorKind::Nack(code, msg).into()),
             _ => Err(ErrorKind::UnexpectedResponse(reply).into()),
         }
     }
-}
 
-impl Drop for Client {
-    fn drop(&mut self) {
-        use std::time;
-        use std::thread;
-        thread::sleep(time::Duration::from_millis(50)); // dirty workaround for linger
+    /// Write the messages as one group that isn't interleaved with messages
+    /// of other clients. Returns the pointer of the last block that has been
+    /// written for it.
+    pub fn write_transaction(&mut self, parts: Vec<Vec<u8>>) -> Result<BlockPointer> {
+        if !self.server.has(capabilities::TRANSACTIONS) {
+            return Err("daemon doesn't support transactions".into());
+        }
+
+        let recipe = BlockRecipe::transaction(parts)?;
+        self.write_block(recipe)
+    }
+
+    /// Write the parts of a line that exceeded the maximum block size, the
+    /// daemon marks them and writes an alert. The parts are sent in requests
+    /// of at most [`MAX_SPLIT_PARTS`], each request after the first one
+    /// continues the line. `context` is the last part that has already been
+    /// written if the parts continue a previous line. The sequence number
+    /// `seq` is stored in the first part. Returns the pointer of the last
+    /// block that has been written for it.
+    ///
+    /// [`MAX_SPLIT_PARTS`]: ../recipe/constant.MAX_SPLIT_PARTS.html
+    pub fn write_split_line(&mut self, parts: Vec<Vec<u8>>, mut context: Option<Vec<u8>>, mut seq: Option<Sequence>) -> Result<BlockPointer> {
+        let chunks = parts.chunks(MAX_SPLIT_PARTS)
+            .map(|chunk| chunk.to_vec())
+            .collect::<Vec<_>>();
+
+        let mut last = None;
+        for chunk in chunks {
+            let next = chunk.last().cloned();
+            last = Some(self.write_split_chunk(chunk, context, seq.take())?);
+            context = next;
+        }
+
+        last.ok_or_else(|| "split line has no parts".into())
+    }
+
+    fn write_split_chunk(&mut self, parts: Vec<Vec<u8>>, context: Option<Vec<u8>>, seq: Option<Sequence>) -> Result<BlockPointer> {
+        if seq.is_some() && !self.server.has(capabilities::SEQUENCED_LINES) {
+            return Err("daemon doesn't support numbered split lines".into());
+        }
+
+        let continued = context.is_some() && self.server.has(capabilities::CONTINUED_LINES);
+        if !self.server.has(capabilities::SPLIT_LINES) {
+            // the parts can't be marked, but they aren't lost either
+            warn!("daemon doesn't support split lines, writing {} parts as separate messages", parts.len());
+
+            let len = parts.len();
+            let blocks = parts.into_iter()
+                .map(BlockRecipe::info)
+                .collect::<::std::result::Result<Vec<_>, _>>()?;
+
+            let pointers = self.write_batch(blocks)?;
+            return match pointers.last() {
+                Some(pointer) if pointers.len() == len => Ok(pointer.clone()),
+                _ => Err(ErrorKind::PartialBatch(pointers.len(), len).into()),
+            };
+        }
+        if context.is_some() && !continued {
+            warn!("daemon doesn't support continued lines, writing {} parts as a new line", parts.len());
+        }
+
+        let recipe = match (context, seq) {
+            (Some(context), _) if continued => BlockRecipe::continued_line(context, parts)?,
+            (_, Some(seq)) => BlockRecipe::sequenced_line(seq, parts)?,
+            _ => BlockRecipe::split_line(parts)?,
+        };
+        self.write_block(recipe)
+    }
+
+    /// Replay messages from the spool, the daemon records when they have
+    /// been spooled. Returns the pointer of the last block that has been
+    /// written for them.
+    pub fn write_replay(&mut self, entries: Vec<SpoolEntry>) -> Result<BlockPointer> {
+        if !self.server.has(capabilities::SPOOL) {
+            return Err("daemon doesn't support spool replays".into());
+        }
+
+        let recipe = BlockRecipe::replay(entries)?;
+        self.write_block(recipe)
+    }
+
+    /// Write multiple blocks in one round trip. Returns the pointers of the
+    /// committed blocks, see [`CtlResponse::AckBatch`].
+    ///
+    /// [`CtlResponse::AckBatch`]: enum.CtlResponse.html#variant.AckBatch
+    ///
+    /// Falls back to one round trip per block if the daemon doesn't support
+    /// batches.
+    pub fn write_batch(&mut self, blocks: Vec<BlockRecipe>) -> Result<Vec<BlockPointer>> {
+        if !self.server.has(capabilities::BATCH) {
+            let mut pointers = Vec::new();
+            for block in blocks {
+                match self.write_block(block) {
+                    Ok(pointer) => pointers.push(pointer),
+                    Err(err) => {
+                        if pointers.is_empty() {
+                            return Err(err);
+                        }
+                        error!("{}", err);
+                        break;
+                    },
+                }
+            }
+            return Ok(pointers);
+        }
+
+        let id = if self.builder.request_ids && self.server.has(capabilities::REQUEST_IDS) {
+            Some(random_request_id())
+        } else {
+            None
+        };
+
+        let ledger = self.ledger()?;
+        let reply = self.send(&CtlRequest::WriteBatch(ledger, blocks, id))?;
+
+        match reply {
+            CtlResponse::AckBatch(pointers) => Ok(pointers),
+            CtlResponse::Nack(code, msg) => Err(ErrorKind::Nack(code, msg).into()),
+            _ => Err(ErrorKind::UnexpectedResponse(reply).into()),
+        }
     }
 }
@@ -0,0 +1,494 @@
+use libc;
+
+use std::cmp;
+use std::fs::{self, Permissions};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use blocks::Origin;
+use rpc::errors::{Result, Error, ErrorKind};
+
+/// Requests and responses are small, anything above this is rejected.
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Connections that are served at the same time, further connections are closed.
+pub const MAX_CONNECTIONS: usize = 256;
+
+/// A client has to finish a frame within this time once it started sending it.
+const FRAME_TIMEOUT: u64 = 5;
+
+/// Bytes that are read from a connection at once.
+const READ_SIZE: usize = 65536;
+
+/// A client that doesn't read its replies is dropped once this much is queued.
+const MAX_PENDING_REPLIES: usize = 2 * MAX_FRAME_SIZE;
+
+
+/// Write a length-prefixed frame.
+pub fn write_frame<W: Write>(w: &mut W, bytes: &[u8]) -> Result<()> {
+    if bytes.len() > MAX_FRAME_SIZE {
+        return Err(ErrorKind::FrameTooLarge(bytes.len()).into());
+    }
+
+    let len = bytes.len() as u32;
+    let header = [(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+    w.write_all(&header)?;
+    w.write_all(bytes)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Read a length-prefixed frame, returns `None` if the peer closed the connection.
+pub fn read_frame<R: Read>(r: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut header = [0; 4];
+    match r.read_exact(&mut header) {
+        Ok(_) => (),
+        Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    let len = frame_len(&header)?;
+
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    Ok(Some(buf))
+}
+
+/// Decode the length of a frame from its header.
+fn frame_len(header: &[u8]) -> Result<usize> {
+    let len = ((header[0] as usize) << 24) |
+              ((header[1] as usize) << 16) |
+              ((header[2] as usize) << 8) |
+              (header[3] as usize);
+
+    if len > MAX_FRAME_SIZE {
+        return Err(ErrorKind::FrameTooLarge(len).into());
+    }
+
+    Ok(len)
+}
+
+/// Read the credentials of the process on the other end of the socket.
+#[cfg(target_os="linux")]
+pub fn peer_cred(stream: &UnixStream) -> Result<Origin> {
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let ret = unsafe {
+        libc::getsockopt(stream.as_raw_fd(),
+                         libc::SOL_SOCKET,
+                         libc::SO_PEERCRED,
+                         &mut cred as *mut libc::ucred as *mut libc::c_void,
+                         &mut len)
+    };
+
+    if ret != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(Origin {
+        uid: cred.uid,
+        gid: cred.gid,
+        pid: cred.pid as u32,
+    })
+}
+
+/// Read the credentials of the process on the other end of the socket.
+///
+/// getpeereid(2) doesn't report the pid, it's always set to 0.
+#[cfg(not(target_os="linux"))]
+pub fn peer_cred(stream: &UnixStream) -> Result<Origin> {
+    let mut uid: libc::uid_t = 0;
+    let mut gid: libc::gid_t = 0;
+
+    let ret = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+
+    if ret != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(Origin {
+        uid,
+        gid,
+        pid: 0,
+    })
+}
+
+struct Connection {
+    id: u64,
+    stream: UnixStream,
+    origin: Origin,
+    /// Bytes of frames that haven't been taken yet
+    buf: Vec<u8>,
+    /// When the client started to send the frame that is incomplete
+    started: Option<Instant>,
+    /// Replies that haven't been written yet
+    out: Vec<u8>,
+    closed: bool,
+}
+
+impl Connection {
+    /// Read what the client sent so far, the socket doesn't block.
+    fn fill(&mut self) {
+        let mut chunk = [0; READ_SIZE];
+        match (&self.stream).read(&mut chunk) {
+            Ok(0) => {
+                debug!("unix: connection closed: {}", self.origin);
+                self.closed = true;
+            },
+            Ok(n) => {
+                if self.buf.is_empty() {
+                    self.started = Some(Instant::now());
+                }
+                self.buf.extend(&chunk[..n]);
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted ||
+                            err.kind() == io::ErrorKind::WouldBlock => (),
+            Err(err) => {
+                warn!("unix: dropping connection: {:?}", err);
+                self.closed = true;
+            },
+        }
+    }
+
+    /// Take the next frame if it has been received completely.
+    fn frame(&mut self) -> Option<Vec<u8>> {
+        if self.closed || self.buf.len() < 4 {
+            return None;
+        }
+
+        let len = match frame_len(&self.buf[..4]) {
+            Ok(len) => len,
+            Err(err) => {
+                warn!("unix: dropping connection: {:?}", err);
+                self.closed = true;
+                return None;
+            },
+        };
+
+        if self.buf.len() < 4 + len {
+            return None;
+        }
+
+        let frame = self.buf[4..4 + len].to_vec();
+        self.buf.drain(..4 + len);
+        self.started = if self.buf.is_empty() { None } else { Some(Instant::now()) };
+        Some(frame)
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.started.map(|started| started + Duration::from_secs(FRAME_TIMEOUT))
+    }
+
+    /// Queue a reply and write as much as the client accepts right now.
+    fn queue(&mut self, bytes: &[u8]) {
+        if let Err(err) = write_frame(&mut self.out, bytes) {
+            error!("unix: failed to encode reply: {:?}", err);
+            return;
+        }
+
+        if self.out.len() > MAX_PENDING_REPLIES {
+            warn!("unix: {} doesn't read its replies, dropping connection", self.origin);
+            self.closed = true;
+            return;
+        }
+
+        self.flush();
+    }
+
+    /// Write queued replies, the socket doesn't block.
+    fn flush(&mut self) {
+        while !self.closed && !self.out.is_empty() {
+            match (&self.stream).write(&self.out) {
+                Ok(0) => self.closed = true,
+                Ok(n) => {
+                    self.out.drain(..n);
+                },
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    warn!("unix: dropping connection: {:?}", err);
+                    self.closed = true;
+                },
+            }
+        }
+    }
+}
+
+/// A client connection, replies are queued for the frontend thread which
+/// writes them without blocking. A client that doesn't read can't hold up
+/// the thread that replies.
+#[derive(Debug, Clone)]
+pub struct Peer {
+    id: u64,
+    pub origin: Origin,
+    replies: mpsc::Sender<(u64, Vec<u8>)>,
+    wake: Arc<UnixStream>,
+}
+
+impl Peer {
+    pub fn reply(&self, bytes: &[u8]) -> Result<()> {
+        if self.replies.send((self.id, bytes.to_vec())).is_err() {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "unix server stopped").into());
+        }
+        wake(&self.wake);
+        Ok(())
+    }
+}
+
+/// Interrupt the poll of the frontend. If the socket is full the frontend
+/// is going to wake up anyway.
+fn wake(stream: &UnixStream) {
+    match (&*stream).write(&[0]) {
+        Ok(_) => (),
+        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => (),
+        Err(err) => warn!("unix: failed to wake up frontend: {:?}", err),
+    }
+}
+
+/// Native unix socket listener that serves many connections from one thread.
+pub struct UnixServer {
+    listener: UnixListener,
+    connections: Vec<Connection>,
+    next: usize,
+    next_id: u64,
+    replies_tx: mpsc::Sender<(u64, Vec<u8>)>,
+    replies_rx: mpsc::Receiver<(u64, Vec<u8>)>,
+    wake_tx: Arc<UnixStream>,
+    wake_rx: UnixStream,
+}
+
+impl UnixServer {
+    pub fn bind(path: &str) -> Result<UnixServer> {
+        // remove stale socket from a previous run
+        if fs::symlink_metadata(path).is_ok() {
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+
+        // TODO: write a proper solution
+        let perms = Permissions::from_mode(0o770);
+        fs::set_permissions(path, perms)?;
+
+        let (replies_tx, replies_rx) = mpsc::channel();
+        let (wake_tx, wake_rx) = UnixStream::pair()?;
+        wake_tx.set_nonblocking(true)?;
+        wake_rx.set_nonblocking(true)?;
+
+        Ok(UnixServer {
+            listener,
+            connections: Vec::new(),
+            next: 0,
+            next_id: 0,
+            replies_tx,
+            replies_rx,
+            wake_tx: Arc::new(wake_tx),
+            wake_rx,
+        })
+    }
+
+    fn accept(&mut self) -> Result<()> {
+        let (stream, _) = self.listener.accept()?;
+
+        if self.connections.len() >= MAX_CONNECTIONS {
+            warn!("unix: {} connections are open, closing new connection", MAX_CONNECTIONS);
+            return Ok(());
+        }
+
+        stream.set_nonblocking(true)?;
+
+        let origin = peer_cred(&stream)?;
+        debug!("unix: accepted connection from {}", origin);
+
+        self.next_id += 1;
+        self.connections.push(Connection {
+            id: self.next_id,
+            stream,
+            origin,
+            buf: Vec::new(),
+            started: None,
+            out: Vec::new(),
+            closed: false,
+        });
+        Ok(())
+    }
+
+    /// Wait until a connection is readable, a queued reply can be written,
+    /// another thread sent a reply or the next frame expires.
+    fn poll(&self) -> Result<Vec<libc::pollfd>> {
+        let mut fds = vec![libc::pollfd {
+            fd: self.listener.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }, libc::pollfd {
+            fd: self.wake_rx.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+
+        for conn in &self.connections {
+            fds.push(libc::pollfd {
+                fd: conn.stream.as_raw_fd(),
+                events: if conn.out.is_empty() { libc::POLLIN } else { libc::POLLIN | libc::POLLOUT },
+                revents: 0,
+            });
+        }
+
+        let now = Instant::now();
+        let timeout = self.connections.iter()
+            .filter_map(|conn| conn.deadline())
+            .min()
+            .map(|deadline| {
+                let wait = if deadline > now { deadline - now } else { Duration::from_secs(0) };
+                let ms = wait.as_secs() * 1000 + u64::from(wait.subsec_nanos() / 1_000_000) + 1;
+                cmp::min(ms, i32::max_value() as u64) as libc::c_int
+            })
+            .unwrap_or(-1);
+
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err.into());
+            }
+        }
+
+        Ok(fds)
+    }
+
+    /// Take a complete frame, start looking after the last client we
+    /// served so a busy client can't starve the others.
+    fn next_frame(&mut self) -> Option<(Peer, Vec<u8>)> {
+        let n = self.connections.len();
+        for i in 0..n {
+            let idx = (self.next + i) % n;
+            if let Some(bytes) = self.connections[idx].frame() {
+                self.next = idx + 1;
+                let conn = &self.connections[idx];
+                let peer = Peer {
+                    id: conn.id,
+                    origin: conn.origin.clone(),
+                    replies: self.replies_tx.clone(),
+                    wake: self.wake_tx.clone(),
+                };
+                return Some((peer, bytes));
+            }
+        }
+        None
+    }
+
+    /// Move the queued replies to their connections.
+    fn forward_replies(&mut self) {
+        let mut buf = [0; 64];
+        while let Ok(n) = (&self.wake_rx).read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+
+        while let Ok((id, bytes)) = self.replies_rx.try_recv() {
+            self.queue(id, &bytes);
+        }
+    }
+
+    fn queue(&mut self, id: u64, bytes: &[u8]) {
+        match self.connections.iter_mut().find(|conn| conn.id == id) {
+            Some(conn) => conn.queue(bytes),
+            None => debug!("unix: connection closed, dropping reply"),
+        }
+    }
+
+    /// Wait until any of the clients sent a request. Replies are written in
+    /// the meantime. Clients only get a limited time to finish a frame, a
+    /// slow client can't stall the others.
+    pub fn recv(&mut self) -> Result<(Peer, Vec<u8>)> {
+        loop {
+            self.forward_replies();
+
+            if let Some(frame) = self.next_frame() {
+                return Ok(frame);
+            }
+
+            let now = Instant::now();
+            for conn in &mut self.connections {
+                if conn.deadline().map(|deadline| deadline <= now).unwrap_or(false) {
+                    warn!("unix: {} didn't finish a frame in time, dropping connection", conn.origin);
+                    conn.closed = true;
+                }
+            }
+            self.connections.retain(|conn| !conn.closed);
+
+            let fds = self.poll()?;
+
+            // fds[0] is the listener, fds[1] the wakeup, connections start at 2
+            for (conn, fd) in self.connections.iter_mut().zip(&fds[2..]) {
+                if fd.revents & libc::POLLOUT != 0 {
+                    conn.flush();
+                }
+                if fd.revents & !libc::POLLOUT != 0 {
+                    conn.fill();
+                }
+            }
+
+            if fds[0].revents != 0 {
+                if let Err(err) = self.accept() {
+                    warn!("unix: failed to accept connection: {:?}", err);
+                }
+            }
+        }
+    }
+}
+
+/// Client side of the native unix socket transport.
+pub struct UnixClient {
+    stream: UnixStream,
+}
+
+impl UnixClient {
+    pub fn connect(path: &str) -> Result<UnixClient> {
+        let stream = UnixStream::connect(path)?;
+        Ok(UnixClient {
+            stream,
+        })
+    }
+
+    /// Set the send and receive timeout, `None` waits forever.
+    pub fn set_timeouts(&self, send: Option<Duration>, recv: Option<Duration>) -> Result<()> {
+        self.stream.set_write_timeout(send)?;
+        self.stream.set_read_timeout(recv)?;
+        Ok(())
+    }
+
+    pub fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        write_frame(&mut self.stream, bytes)
+            .map_err(timeout_error)
+    }
+
+    pub fn recv(&mut self) -> Result<Vec<u8>> {
+        match read_frame(&mut self.stream).map_err(timeout_error)? {
+            Some(bytes) => Ok(bytes),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "daemon closed the connection").into()),
+        }
+    }
+}
+
+/// Socket timeouts show up as `WouldBlock` or `TimedOut`, depending on the platform.
+fn timeout_error(err: Error) -> Error {
+    let timeout = match *err.kind() {
+        ErrorKind::Io(ref err) => err.kind() == io::ErrorKind::WouldBlock ||
+                                  err.kind() == io::ErrorKind::TimedOut,
+        _ => false,
+    };
+
+    if timeout {
+        ErrorKind::Timeout.into()
+    } else {
+        err
+    }
+}
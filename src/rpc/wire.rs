@@ -1,8 +1,12 @@
-use nom::{IResult, be_u8, be_u16};
+use nom::{IResult, be_u8, be_u16, be_u32, be_u64};
 
-use rpc::{BlockRecipe, CtlRequest, CtlResponse};
+use blocks::{BlockPointer, SensorSignature, Sequence};
+use rpc::{BlockRecipe, CtlRequest, CtlResponse, Hello, NackCode, Status};
 use rpc::errors::{Result, ErrorKind};
-use wire::{pointer, len_to_u16_vec};
+use spool::SpoolEntry;
+use wire::{key_id, pointer, signature, encode_str, short_string, long_string, len_to_u16_vec, u32_to_vec, u64_to_vec};
+
+use std::cmp;
 
 
 impl BlockRecipe {
@@ -15,6 +19,49 @@ impl BlockRecipe {
                 buf.extend(&len_to_u16_vec(bytes.len()).expect("block len overflow"));
                 buf.extend(bytes);
             },
+            Transaction(ref parts) => {
+                buf.extend(b"\x02");
+                encode_parts(buf, parts);
+            },
+            SplitLine(ref parts) => {
+                buf.extend(b"\x03");
+                encode_parts(buf, parts);
+            },
+            ContinuedLine(ref context, ref parts) => {
+                buf.extend(b"\x07");
+                buf.extend(&len_to_u16_vec(context.len()).expect("block len overflow"));
+                buf.extend(context);
+                encode_parts(buf, parts);
+            },
+            SequencedLine(ref seq, ref parts) => {
+                buf.extend(b"\x08");
+                encode_str(buf, &seq.source, 255);
+                buf.extend(&u64_to_vec(seq.seq));
+                encode_parts(buf, parts);
+            },
+            Replay(ref entries) => {
+                buf.extend(b"\x04");
+                buf.extend(&len_to_u16_vec(entries.len()).expect("replay len overflow"));
+                for entry in entries {
+                    buf.extend(&u64_to_vec(entry.seq));
+                    buf.extend(&u64_to_vec(entry.time));
+                    buf.extend(&len_to_u16_vec(entry.msg.len()).expect("block len overflow"));
+                    buf.extend(&entry.msg);
+                }
+            },
+            Sequenced(ref seq, ref bytes) => {
+                buf.extend(b"\x05");
+                encode_str(buf, &seq.source, 255);
+                buf.extend(&u64_to_vec(seq.seq));
+                buf.extend(&len_to_u16_vec(bytes.len()).expect("block len overflow"));
+                buf.extend(bytes);
+            },
+            Signed(ref sig, ref inner) => {
+                buf.extend(b"\x06");
+                buf.extend(&sig.key_id.0);
+                buf.extend(sig.signature.0.iter());
+                inner.encode(buf);
+            },
         }
     }
 
@@ -27,6 +74,14 @@ impl BlockRecipe {
     }
 }
 
+fn encode_parts(buf: &mut Vec<u8>, parts: &[Vec<u8>]) {
+    buf.extend(&len_to_u16_vec(parts.len()).expect("transaction len overflow"));
+    for part in parts {
+        buf.extend(&len_to_u16_vec(part.len()).expect("block len overflow"));
+        buf.extend(part);
+    }
+}
+
 fn recipe_info(input: &[u8]) -> IResult<&[u8], Vec<u8>> {
     do_parse!(input,
         length: be_u16          >>
@@ -37,11 +92,108 @@ fn recipe_info(input: &[u8]) -> IResult<&[u8], Vec<u8>> {
     )
 }
 
+fn recipe_transaction(input: &[u8]) -> IResult<&[u8], Vec<Vec<u8>>> {
+    do_parse!(input,
+        count: be_u16                               >>
+        parts: count!(recipe_info, count as usize)  >>
+        (parts)
+    )
+}
+
+fn recipe_continued(input: &[u8]) -> IResult<&[u8], BlockRecipe> {
+    do_parse!(input,
+        context: recipe_info        >>
+        parts: recipe_transaction   >>
+        (BlockRecipe::ContinuedLine(context, parts))
+    )
+}
+
+fn spool_entry(input: &[u8]) -> IResult<&[u8], SpoolEntry> {
+    do_parse!(input,
+        seq: be_u64         >>
+        time: be_u64        >>
+        msg: recipe_info    >>
+        ({
+            SpoolEntry {
+                seq,
+                time,
+                msg,
+            }
+        })
+    )
+}
+
+fn recipe_replay(input: &[u8]) -> IResult<&[u8], Vec<SpoolEntry>> {
+    do_parse!(input,
+        count: be_u16                                   >>
+        entries: count!(spool_entry, count as usize)    >>
+        (entries)
+    )
+}
+
+fn recipe_sequenced(input: &[u8]) -> IResult<&[u8], BlockRecipe> {
+    do_parse!(input,
+        source: short_string    >>
+        seq: be_u64             >>
+        bytes: recipe_info      >>
+        ({
+            BlockRecipe::Sequenced(Sequence {
+                source,
+                seq,
+            }, bytes)
+        })
+    )
+}
+
+fn recipe_sequenced_line(input: &[u8]) -> IResult<&[u8], BlockRecipe> {
+    do_parse!(input,
+        source: short_string        >>
+        seq: be_u64                 >>
+        parts: recipe_transaction   >>
+        ({
+            BlockRecipe::SequencedLine(Sequence {
+                source,
+                seq,
+            }, parts)
+        })
+    )
+}
+
+/// Only single messages can be signed, the inner recipe can't be signed
+/// again. Parsing it with `recipe` would allow unlimited nesting.
+fn recipe_signed_inner(input: &[u8]) -> IResult<&[u8], BlockRecipe> {
+    switch!(input, be_u8,
+        0x01 => map!(recipe_info, BlockRecipe::Info) |
+        0x05 => call!(recipe_sequenced)
+    )
+}
+
+fn recipe_signed(input: &[u8]) -> IResult<&[u8], BlockRecipe> {
+    do_parse!(input,
+        key_id: key_id                  >>
+        signature: signature            >>
+        inner: recipe_signed_inner      >>
+        ({
+            BlockRecipe::Signed(SensorSignature {
+                key_id,
+                signature,
+            }, Box::new(inner))
+        })
+    )
+}
+
 fn recipe(input: &[u8]) -> IResult<&[u8], BlockRecipe> {
     do_parse!(input,
         recipe: switch!(be_u8,
             0x00 => value!(BlockRecipe::Rekey) |
-            0x01 => map!(recipe_info, BlockRecipe::Info)
+            0x01 => map!(recipe_info, BlockRecipe::Info) |
+            0x02 => map!(recipe_transaction, BlockRecipe::Transaction) |
+            0x03 => map!(recipe_transaction, BlockRecipe::SplitLine) |
+            0x04 => map!(recipe_replay, BlockRecipe::Replay) |
+            0x05 => call!(recipe_sequenced) |
+            0x06 => call!(recipe_signed) |
+            0x07 => call!(recipe_continued) |
+            0x08 => call!(recipe_sequenced_line)
         ) >>
         (recipe)
     )
@@ -52,10 +204,47 @@ impl CtlRequest {
         use self::CtlRequest::*;
         match *self {
             Ping => { buf.extend(b"\x00"); },
-            Write(ref recipe) => {
+            // requests for the default ledger are understood by older daemons
+            Write(None, ref recipe, None) => {
                 buf.extend(b"\x01");
                 recipe.encode(buf);
             },
+            Write(Some(ref ledger), ref recipe, None) => {
+                buf.extend(b"\x05");
+                encode_str(buf, ledger, 255);
+                recipe.encode(buf);
+            },
+            // ledger names can't be empty, this is the default ledger
+            Write(ref ledger, ref recipe, Some(ref id)) => {
+                buf.extend(b"\x08");
+                encode_str(buf, ledger.as_ref().map(|x| x.as_str()).unwrap_or(""), 255);
+                encode_str(buf, id, 255);
+                recipe.encode(buf);
+            },
+            WriteBatch(None, ref recipes, None) => {
+                buf.extend(b"\x02");
+                encode_batch(buf, recipes);
+            },
+            WriteBatch(Some(ref ledger), ref recipes, None) => {
+                buf.extend(b"\x06");
+                encode_str(buf, ledger, 255);
+                encode_batch(buf, recipes);
+            },
+            WriteBatch(ref ledger, ref recipes, Some(ref id)) => {
+                buf.extend(b"\x09");
+                encode_str(buf, ledger.as_ref().map(|x| x.as_str()).unwrap_or(""), 255);
+                encode_str(buf, id, 255);
+                encode_batch(buf, recipes);
+            },
+            Hello(ref hello) => {
+                buf.extend(b"\x03");
+                hello.encode(buf);
+            },
+            Status(None) => { buf.extend(b"\x04"); },
+            Status(Some(ref ledger)) => {
+                buf.extend(b"\x07");
+                encode_str(buf, ledger, 255);
+            },
         }
     }
 
@@ -68,11 +257,74 @@ impl CtlRequest {
     }
 }
 
+fn encode_batch(buf: &mut Vec<u8>, recipes: &[BlockRecipe]) {
+    buf.extend(&len_to_u16_vec(recipes.len()).expect("batch len overflow"));
+    for recipe in recipes {
+        recipe.encode(buf);
+    }
+}
+
+fn recipe_batch(input: &[u8]) -> IResult<&[u8], Vec<BlockRecipe>> {
+    do_parse!(input,
+        count: be_u16                           >>
+        recipes: count!(recipe, count as usize) >>
+        (recipes)
+    )
+}
+
+fn ledger_write(input: &[u8]) -> IResult<&[u8], CtlRequest> {
+    do_parse!(input,
+        ledger: short_string    >>
+        recipe: recipe          >>
+        (CtlRequest::Write(Some(ledger), recipe, None))
+    )
+}
+
+fn request_id_write(input: &[u8]) -> IResult<&[u8], CtlRequest> {
+    do_parse!(input,
+        ledger: short_string    >>
+        id: short_string        >>
+        recipe: recipe          >>
+        ({
+            let ledger = if ledger.is_empty() { None } else { Some(ledger) };
+            CtlRequest::Write(ledger, recipe, Some(id))
+        })
+    )
+}
+
+fn ledger_batch(input: &[u8]) -> IResult<&[u8], CtlRequest> {
+    do_parse!(input,
+        ledger: short_string    >>
+        recipes: recipe_batch   >>
+        (CtlRequest::WriteBatch(Some(ledger), recipes, None))
+    )
+}
+
+fn request_id_batch(input: &[u8]) -> IResult<&[u8], CtlRequest> {
+    do_parse!(input,
+        ledger: short_string    >>
+        id: short_string        >>
+        recipes: recipe_batch   >>
+        ({
+            let ledger = if ledger.is_empty() { None } else { Some(ledger) };
+            CtlRequest::WriteBatch(ledger, recipes, Some(id))
+        })
+    )
+}
+
 fn request(input: &[u8]) -> IResult<&[u8], CtlRequest> {
     do_parse!(input,
         request: switch!(be_u8,
             0x00 => value!(CtlRequest::Ping) |
-            0x01 => map!(recipe, CtlRequest::Write)
+            0x01 => map!(recipe, |recipe| CtlRequest::Write(None, recipe, None)) |
+            0x02 => map!(recipe_batch, |recipes| CtlRequest::WriteBatch(None, recipes, None)) |
+            0x03 => map!(hello, CtlRequest::Hello) |
+            0x04 => value!(CtlRequest::Status(None)) |
+            0x05 => call!(ledger_write) |
+            0x06 => call!(ledger_batch) |
+            0x07 => map!(short_string, |ledger| CtlRequest::Status(Some(ledger))) |
+            0x08 => call!(request_id_write) |
+            0x09 => call!(request_id_batch)
         ) >>
         (request)
     )
@@ -87,7 +339,29 @@ impl CtlResponse {
                 buf.extend(b"\x01");
                 buf.extend(pointer.bytes());
             },
-            Nack => { buf.extend(b"\x02"); },
+            Nack(ref code, ref msg) => {
+                // messages are only informational, truncate them if needed
+                let msg = &msg.as_bytes()[..msg.len().min(1024)];
+                buf.extend(b"\x02");
+                buf.push(code.to_byte());
+                buf.extend(&len_to_u16_vec(msg.len()).expect("message len overflow"));
+                buf.extend(msg);
+            },
+            AckBatch(ref pointers) => {
+                buf.extend(b"\x03");
+                buf.extend(&len_to_u16_vec(pointers.len()).expect("batch len overflow"));
+                for pointer in pointers {
+                    buf.extend(pointer.bytes());
+                }
+            },
+            Hello(ref hello) => {
+                buf.extend(b"\x04");
+                hello.encode(buf);
+            },
+            Status(ref status) => {
+                buf.extend(b"\x05");
+                status.encode(buf);
+            },
         }
     }
 
@@ -100,12 +374,107 @@ impl CtlResponse {
     }
 }
 
+fn pointer_batch(input: &[u8]) -> IResult<&[u8], Vec<BlockPointer>> {
+    do_parse!(input,
+        count: be_u16                               >>
+        pointers: count!(pointer, count as usize)   >>
+        (pointers)
+    )
+}
+
+fn nack(input: &[u8]) -> IResult<&[u8], CtlResponse> {
+    // daemons before protocol version 1 send a bare nack
+    if input.is_empty() {
+        return IResult::Done(input, CtlResponse::nack(NackCode::Unknown, ""));
+    }
+
+    do_parse!(input,
+        code: be_u8         >>
+        length: be_u16      >>
+        msg: take!(length)  >>
+        ({
+            CtlResponse::Nack(
+                NackCode::from_byte(code),
+                String::from_utf8_lossy(msg).into_owned(),
+            )
+        })
+    )
+}
+
+impl Hello {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend(&len_to_u16_vec(self.version as usize).expect("u16 can't overflow"));
+        buf.extend(&u32_to_vec(self.capabilities));
+    }
+}
+
+fn hello(input: &[u8]) -> IResult<&[u8], Hello> {
+    do_parse!(input,
+        version: be_u16         >>
+        capabilities: be_u32    >>
+        ({
+            Hello {
+                version,
+                capabilities,
+            }
+        })
+    )
+}
+
+impl Status {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        encode_str(buf, &self.version, 255);
+        buf.extend(&u64_to_vec(self.uptime));
+        buf.extend(self.head.bytes());
+        buf.extend(self.session.bytes());
+        buf.extend(&u64_to_vec(self.session_blocks));
+        encode_str(buf, &self.datadir, 4096);
+
+        let stages = &self.sandbox[..cmp::min(self.sandbox.len(), 255)];
+        buf.push(stages.len() as u8);
+        for stage in stages {
+            encode_str(buf, stage, 255);
+        }
+
+        encode_str(buf, &self.config_fingerprint, 255);
+    }
+}
+
+fn status(input: &[u8]) -> IResult<&[u8], Status> {
+    do_parse!(input,
+        version: short_string                           >>
+        uptime: be_u64                                  >>
+        head: pointer                                   >>
+        session: pointer                                >>
+        session_blocks: be_u64                          >>
+        datadir: long_string                            >>
+        count: be_u8                                    >>
+        sandbox: count!(short_string, count as usize)   >>
+        config_fingerprint: short_string                >>
+        ({
+            Status {
+                version,
+                uptime,
+                head,
+                session,
+                session_blocks,
+                datadir,
+                sandbox,
+                config_fingerprint,
+            }
+        })
+    )
+}
+
 fn response(input: &[u8]) -> IResult<&[u8], CtlResponse> {
     do_parse!(input,
         response: switch!(be_u8,
             0x00 => value!(CtlResponse::Pong) |
             0x01 => map!(pointer, CtlResponse::Ack) |
-            0x02 => value!(CtlResponse::Nack)
+            0x02 => call!(nack) |
+            0x03 => map!(pointer_batch, CtlResponse::AckBatch) |
+            0x04 => map!(hello, CtlResponse::Hello) |
+            0x05 => map!(status, CtlResponse::Status)
         ) >>
         (response)
     )
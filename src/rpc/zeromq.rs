@@ -0,0 +1,207 @@
+use zmq;
+
+use std::cmp;
+use std::fmt;
+use std::fs::{self, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rpc::curve::{self, CurveServer, CurveClient};
+use rpc::errors::{Result, Error, ErrorKind};
+
+/// Replies are queued on this endpoint and forwarded to the frontend socket
+/// by the thread that polls it.
+const REPLY_ENDPOINT: &str = "inproc://tr1pd.replies";
+
+
+/// ROUTER socket that serves many REQ clients at once.
+pub struct ZmqServer {
+    socket: zmq::Socket,
+    replies: zmq::Socket,
+    queue: Arc<Mutex<zmq::Socket>>,
+}
+
+/// A zmq client, replies are queued for the frontend thread because zmq
+/// sockets can't be shared.
+#[derive(Clone)]
+pub struct ZmqPeer {
+    identity: Vec<u8>,
+    /// The curve key of the client in Z85
+    pub key: Option<String>,
+    queue: Arc<Mutex<zmq::Socket>>,
+}
+
+impl fmt::Debug for ZmqPeer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ZmqPeer")
+            .field("identity", &self.identity)
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+impl ZmqPeer {
+    pub fn reply(&self, bytes: &[u8]) -> Result<()> {
+        let queue = self.queue.lock().unwrap();
+        queue.send(&self.identity, zmq::SNDMORE)?;
+        queue.send(bytes, 0)?;
+        Ok(())
+    }
+}
+
+impl ZmqServer {
+    pub fn bind(url: &str, curve: Option<&CurveServer>) -> Result<ZmqServer> {
+        let ctx = zmq::Context::new();
+        let socket = ctx.socket(zmq::ROUTER)?;
+
+        if let Some(curve) = curve {
+            curve::start_authenticator(&ctx, curve.clients.clone())?;
+            curve.apply(&socket)?;
+        } else if !url.starts_with("ipc://") {
+            warn!("binding to {:?} without authentication", url);
+        }
+
+        socket.bind(url)?;
+
+        // fix permissions
+        if url.starts_with("ipc://") {
+            // TODO: write a proper solution
+            let perms = Permissions::from_mode(0o770);
+            fs::set_permissions(&url[6..], perms)?;
+        }
+
+        let replies = ctx.socket(zmq::PULL)?;
+        replies.bind(REPLY_ENDPOINT)?;
+
+        let queue = ctx.socket(zmq::PUSH)?;
+        queue.connect(REPLY_ENDPOINT)?;
+
+        Ok(ZmqServer {
+            socket,
+            replies,
+            queue: Arc::new(Mutex::new(queue)),
+        })
+    }
+
+    /// Poll the frontend and the reply queue, returns the client and the
+    /// request if there is one.
+    pub fn recv(&mut self) -> Result<Option<(ZmqPeer, Vec<u8>)>> {
+        let (request, reply) = {
+            let mut items = [
+                self.socket.as_poll_item(zmq::POLLIN),
+                self.replies.as_poll_item(zmq::POLLIN),
+            ];
+
+            match zmq::poll(&mut items, -1) {
+                Ok(_) => (),
+                Err(zmq::Error::EINTR) => return Ok(None),
+                Err(err) => return Err(err.into()),
+            }
+
+            (items[0].is_readable(), items[1].is_readable())
+        };
+
+        if reply {
+            let mut parts = self.replies.recv_multipart(0)?;
+            if parts.len() == 2 {
+                let bytes = parts.pop().unwrap();
+                let identity = parts.pop().unwrap();
+                self.forward(&identity, &bytes)?;
+            }
+        }
+
+        if request {
+            // the curve key of the client is attached by the authenticator
+            let mut first = self.socket.recv_msg(0)?;
+            let user_id = first.gets("User-Id")
+                .filter(|id| !id.is_empty())
+                .map(|id| id.to_string());
+
+            let mut parts = vec![first.to_vec()];
+            while self.socket.get_rcvmore()? {
+                parts.push(self.socket.recv_bytes(0)?);
+            }
+
+            // identity, empty delimiter, request
+            if parts.len() != 3 || !parts[1].is_empty() {
+                warn!("dropping malformed message with {} frames", parts.len());
+                return Ok(None);
+            }
+
+            let bytes = parts.pop().unwrap();
+            let peer = ZmqPeer {
+                identity: parts.swap_remove(0),
+                key: user_id,
+                queue: self.queue.clone(),
+            };
+            return Ok(Some((peer, bytes)));
+        }
+
+        Ok(None)
+    }
+
+    fn forward(&self, identity: &[u8], bytes: &[u8]) -> Result<()> {
+        // REQ clients expect an empty delimiter frame
+        self.socket.send(identity, zmq::SNDMORE)?;
+        self.socket.send(b"", zmq::SNDMORE)?;
+        self.socket.send(bytes, 0)?;
+        Ok(())
+    }
+}
+
+/// Convert a timeout to the format used by zmq, -1 means infinite.
+fn timeout_ms(timeout: Option<Duration>) -> i32 {
+    match timeout {
+        Some(timeout) => {
+            let ms = timeout.as_secs() * 1000 + u64::from(timeout.subsec_nanos() / 1_000_000);
+            cmp::min(ms, i32::max_value() as u64) as i32
+        },
+        None => -1,
+    }
+}
+
+pub struct ZmqClient {
+    #[allow(dead_code)]
+    ctx: zmq::Context,
+    socket: zmq::Socket,
+}
+
+impl ZmqClient {
+    pub fn connect(url: &str,
+                   curve: Option<&CurveClient>,
+                   send_timeout: Option<Duration>,
+                   recv_timeout: Option<Duration>,
+                   linger: Duration) -> Result<ZmqClient> {
+        let ctx = zmq::Context::new();
+        let socket = ctx.socket(zmq::REQ)?;
+
+        if let Some(curve) = curve {
+            curve.apply(&socket)?;
+        }
+
+        // don't queue requests for a daemon that isn't there, the send
+        // timeout would never fire otherwise
+        socket.set_immediate(true)?;
+        socket.set_sndtimeo(timeout_ms(send_timeout))?;
+        socket.set_rcvtimeo(timeout_ms(recv_timeout))?;
+        // the default is to wait forever when closing the context
+        socket.set_linger(timeout_ms(Some(linger)))?;
+
+        socket.connect(url)?;
+
+        Ok(ZmqClient {
+            ctx,
+            socket,
+        })
+    }
+
+    pub fn roundtrip(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        self.socket.send(bytes, 0)
+            .and_then(|_| self.socket.recv_bytes(0))
+            .map_err(|err| match err {
+                zmq::Error::EAGAIN => ErrorKind::Timeout.into(),
+                err => Error::from(err),
+            })
+    }
+}
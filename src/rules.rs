@@ -0,0 +1,130 @@
+//! Rules that mark security relevant messages with an alert block.
+use regex::bytes::Regex;
+
+use config::RuleConfig;
+
+use std::fmt;
+
+mod errors {
+    use regex;
+
+    error_chain! {
+        errors {
+            InvalidRule(name: String, reason: &'static str) {
+                description("invalid rule")
+                display("invalid rule {:?}: {}", name, reason)
+            }
+        }
+        foreign_links {
+            Regex(regex::Error);
+        }
+    }
+}
+pub use self::errors::{Result, Error, ErrorKind};
+
+
+pub enum Matcher {
+    Contains(Vec<u8>),
+    Regex(Regex),
+}
+
+impl Matcher {
+    pub fn is_match(&self, bytes: &[u8]) -> bool {
+        match *self {
+            Matcher::Contains(ref needle) => {
+                needle.is_empty() || bytes.windows(needle.len()).any(|w| w == &needle[..])
+            },
+            Matcher::Regex(ref regex) => regex.is_match(bytes),
+        }
+    }
+}
+
+impl fmt::Debug for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Matcher::Contains(ref needle) => write!(f, "Contains({:?})", String::from_utf8_lossy(needle)),
+            Matcher::Regex(ref regex) => write!(f, "Regex({:?})", regex.as_str()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    pub name: String,
+    pub matcher: Matcher,
+    /// Command that is executed if the rule matches
+    pub hook: Option<Vec<String>>,
+}
+
+impl Rule {
+    pub fn from_config(config: &RuleConfig) -> Result<Rule> {
+        let invalid = |reason| ErrorKind::InvalidRule(config.name.clone(), reason);
+
+        let matcher = match (config.contains.as_ref(), config.regex.as_ref()) {
+            (Some(needle), None) => Matcher::Contains(needle.as_bytes().to_vec()),
+            (None, Some(regex)) => Matcher::Regex(Regex::new(regex)?),
+            (Some(_), Some(_)) => bail!(invalid("contains and regex can't be used together")),
+            (None, None) => bail!(invalid("either contains or regex is required")),
+        };
+
+        if let Some(ref hook) = config.hook {
+            if hook.is_empty() {
+                bail!(invalid("hook needs at least a program"));
+            }
+        }
+
+        Ok(Rule {
+            name: config.name.clone(),
+            matcher,
+            hook: config.hook.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn from_config(config: &[RuleConfig]) -> Result<Rules> {
+        let rules = config.iter()
+            .map(Rule::from_config)
+            .collect::<Result<_>>()?;
+
+        Ok(Rules {
+            rules,
+        })
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    #[inline]
+    pub fn get(&self, idx: usize) -> Option<&Rule> {
+        self.rules.get(idx)
+    }
+
+    /// At least one rule executes a hook.
+    pub fn has_hooks(&self) -> bool {
+        self.rules.iter().any(|rule| rule.hook.is_some())
+    }
+
+    /// The hook of each rule, in order.
+    pub fn hooks(&self) -> Vec<Option<Vec<String>>> {
+        self.rules.iter()
+            .map(|rule| rule.hook.clone())
+            .collect()
+    }
+
+    /// Indexes of the rules that match the message.
+    pub fn matches(&self, bytes: &[u8]) -> Vec<usize> {
+        self.rules.iter()
+            .enumerate()
+            .filter(|&(_, rule)| rule.matcher.is_match(bytes))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+}
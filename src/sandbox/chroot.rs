@@ -39,8 +39,9 @@ pub fn can_chroot() -> Result<bool> {
     Ok(is_root)
 }
 
+/// Returns `true` if the process has been locked into the datadir.
 #[inline]
-pub fn lock_to_datadir(config: &mut Config) -> Result<()> {
+pub fn lock_to_datadir(config: &mut Config) -> Result<bool> {
     if can_chroot()? {
         {
             let target = config.datadir();
@@ -52,11 +53,12 @@ pub fn lock_to_datadir(config: &mut Config) -> Result<()> {
         // XXX: it's currently not recommended to use chroot
         // on a platform that isn't linux since we don't have
         // capabilities(7) there and we don't have setuid code yet.
+        Ok(true)
     } else if config.security.strict_chroot {
         panic!("strict-chroot is set and process didn't chroot");
+    } else {
+        Ok(false)
     }
-
-    Ok(())
 }
 
 #[inline]
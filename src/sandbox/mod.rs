@@ -1,5 +1,7 @@
 use config::Config;
 
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
 mod errors {
     #[cfg(target_os="linux")]
     use sandbox::capabilities;
@@ -30,6 +32,9 @@ pub mod seccomp;
 #[cfg(target_os="linux")]
 pub mod syscalls;
 
+static STAGE: AtomicUsize = AtomicUsize::new(0);
+static CHROOT: AtomicBool = AtomicBool::new(false);
+
 
 pub fn activate_stage1() -> Result<()> {
     #[cfg(target_os="linux")]
@@ -38,13 +43,16 @@ pub fn activate_stage1() -> Result<()> {
     #[cfg(target_os="openbsd")]
     pledge::activate_stage1()?;
 
+    STAGE.store(1, Ordering::SeqCst);
     info!("stage 1/2 is active");
 
     Ok(())
 }
 
 pub fn activate_stage2(mut config: &mut Config) -> Result<()> {
-    chroot::lock_to_datadir(&mut config)?;
+    if chroot::lock_to_datadir(&mut config)? {
+        CHROOT.store(true, Ordering::SeqCst);
+    }
 
     #[cfg(target_os="linux")]
     capabilities::drop()?;
@@ -55,7 +63,30 @@ pub fn activate_stage2(mut config: &mut Config) -> Result<()> {
     #[cfg(target_os="openbsd")]
     pledge::activate_tr1pd_stage2()?;
 
+    STAGE.store(2, Ordering::SeqCst);
     info!("stage 2/2 is active");
 
     Ok(())
 }
+
+/// Whether the process has been locked into the datadir.
+#[inline]
+pub fn chrooted() -> bool {
+    CHROOT.load(Ordering::SeqCst)
+}
+
+/// The sandbox stages that are active in this process.
+pub fn stages() -> Vec<String> {
+    let mut stages = Vec::new();
+
+    let stage = STAGE.load(Ordering::SeqCst);
+    for i in 1..stage + 1 {
+        stages.push(format!("stage{}", i));
+    }
+
+    if chrooted() {
+        stages.push("chroot".to_string());
+    }
+
+    stages
+}
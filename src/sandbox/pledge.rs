@@ -24,7 +24,7 @@ pub fn activate_stage1() -> Result<()> {
 #[inline]
 pub fn activate_tr1pd_stage2() -> Result<()> {
     info!("calling pledge");
-    pledge![Stdio, RPath, WPath, CPath, Inet]?;
+    pledge![Stdio, RPath, WPath, CPath, Fattr, Inet]?;
 
     info!("stage 2/2 is active");
     Ok(())
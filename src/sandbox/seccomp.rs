@@ -40,6 +40,21 @@ impl Context {
         }
     }
 
+    /// Apply the filter to all threads of the process, not just the calling
+    /// one. The zmq io threads are already running when stage 2 is activated
+    /// and would only be restricted by stage 1 otherwise. Filters stack, the
+    /// stage 1 filter stays active in every thread, so stage 2 can't allow
+    /// anything that stage 1 doesn't.
+    fn sync_threads(&mut self) -> Result<()> {
+        let ret = unsafe { seccomp_attr_set(self.ctx, scmp_filter_attr::SCMP_FLTATR_CTL_TSYNC, 1) };
+
+        if ret != 0 {
+            Err(ErrorKind::FFI.into())
+        } else {
+            Ok(())
+        }
+    }
+
     fn load(&self) -> Result<()> {
         let ret = unsafe { seccomp_load(self.ctx) };
 
@@ -87,6 +102,10 @@ pub fn activate_stage1() -> Result<()> {
     ctx.allow_syscall(Syscall::unlink)?;
     ctx.allow_syscall(Syscall::unlinkat)?;
     #[cfg(not(target_arch = "aarch64"))]
+    ctx.allow_syscall(Syscall::rename)?; // state files
+    ctx.allow_syscall(Syscall::renameat)?; // state files
+    ctx.allow_syscall(Syscall::fsync)?; // state files
+    #[cfg(not(target_arch = "aarch64"))]
     ctx.allow_syscall(Syscall::symlink)?;
     ctx.allow_syscall(Syscall::symlinkat)?;
     #[cfg(not(target_arch = "aarch64"))]
@@ -107,6 +126,7 @@ pub fn activate_stage1() -> Result<()> {
     ctx.allow_syscall(Syscall::ppoll)?;
     ctx.allow_syscall(Syscall::getsockname)?;
     ctx.allow_syscall(Syscall::getsockopt)?;
+    ctx.allow_syscall(Syscall::setsockopt)?; // SO_REUSEADDR for the syslog listener
     ctx.allow_syscall(Syscall::getpeername)?;
     ctx.allow_syscall(Syscall::sendto)?;
     ctx.allow_syscall(Syscall::clone)?;
@@ -141,6 +161,7 @@ pub fn activate_stage1() -> Result<()> {
     ctx.allow_syscall(Syscall::fcntl)?;
     ctx.allow_syscall(Syscall::brk)?;
     ctx.allow_syscall(Syscall::rt_sigprocmask)?;
+    ctx.allow_syscall(Syscall::rt_sigtimedwait)?; // waiting for signals, the filters stack
     ctx.allow_syscall(Syscall::clock_gettime)?;
     ctx.allow_syscall(Syscall::gettimeofday)?;
     ctx.allow_syscall(Syscall::prctl)?; // needed for stage2
@@ -171,6 +192,7 @@ pub fn activate_tr1pd_stage2() -> Result<()> {
     ctx.allow_syscall(Syscall::open)?;
     ctx.allow_syscall(Syscall::ioctl)?;
     ctx.allow_syscall(Syscall::close)?;
+    ctx.allow_syscall(Syscall::lseek)?; // reading the config again
     #[cfg(not(target_arch = "aarch64"))]
     ctx.allow_syscall(Syscall::readlink)?;
     ctx.allow_syscall(Syscall::readlinkat)?;
@@ -186,10 +208,16 @@ pub fn activate_tr1pd_stage2() -> Result<()> {
     #[cfg(not(target_arch = "aarch64"))]
     ctx.allow_syscall(Syscall::symlink)?;
     ctx.allow_syscall(Syscall::symlinkat)?;
+    #[cfg(feature="zmq")]
     ctx.allow_syscall(Syscall::sched_getparam)?;
+    #[cfg(feature="zmq")]
     ctx.allow_syscall(Syscall::sched_getscheduler)?;
+    #[cfg(feature="zmq")]
     ctx.allow_syscall(Syscall::sched_setscheduler)?;
     ctx.allow_syscall(Syscall::getpeername)?;
+    ctx.allow_syscall(Syscall::getsockopt)?; // SO_PEERCRED
+    ctx.allow_syscall(Syscall::setsockopt)?; // timeouts of metrics clients
+    #[cfg(feature="zmq")]
     ctx.allow_syscall(Syscall::eventfd2)?;
     ctx.allow_syscall(Syscall::getpid)?;
     #[cfg(not(target_arch = "aarch64"))]
@@ -203,10 +231,13 @@ pub fn activate_tr1pd_stage2() -> Result<()> {
     ctx.allow_syscall(Syscall::munmap)?;
     ctx.allow_syscall(Syscall::sched_getaffinity)?;
     ctx.allow_syscall(Syscall::pipe2)?;
+    #[cfg(feature="zmq")]
     ctx.allow_syscall(Syscall::epoll_create1)?;
+    #[cfg(feature="zmq")]
     ctx.allow_syscall(Syscall::epoll_ctl)?;
+    #[cfg(feature="zmq")]
     ctx.allow_syscall(Syscall::epoll_pwait)?;
-    #[cfg(not(target_arch = "aarch64"))]
+    #[cfg(all(feature="zmq", not(target_arch = "aarch64")))]
     ctx.allow_syscall(Syscall::epoll_wait)?;
     #[cfg(not(target_arch = "aarch64"))]
     ctx.allow_syscall(Syscall::stat)?;
@@ -229,6 +260,8 @@ pub fn activate_tr1pd_stage2() -> Result<()> {
     ctx.allow_syscall(Syscall::fcntl)?;
     ctx.allow_syscall(Syscall::brk)?;
     ctx.allow_syscall(Syscall::clock_gettime)?;
+    ctx.allow_syscall(Syscall::rt_sigprocmask)?; // spawning the engine thread
+    ctx.allow_syscall(Syscall::rt_sigtimedwait)?; // waiting for signals
     // ctx.allow_syscall(Syscall::prctl)?; // needed for stage2
     // ctx.allow_syscall(Syscall::seccomp)?; // needed for stage2
     // ctx.allow_syscall(Syscall::capget)?; // needed for stage2 TODO
@@ -236,6 +269,7 @@ pub fn activate_tr1pd_stage2() -> Result<()> {
     // ctx.allow_syscall(Syscall::chroot)?; // needed for stage2 TODO
     // ctx.allow_syscall(Syscall::chdir)?; // needed for stage2 TODO
 
+    ctx.sync_threads()?;
     ctx.load()?;
 
     info!("stage 2/2 is active");
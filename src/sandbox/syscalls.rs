@@ -25,6 +25,7 @@ pub enum Syscall {
     sched_getscheduler  = libc::SYS_sched_getscheduler  as isize,
     sched_setscheduler  = libc::SYS_sched_setscheduler  as isize,
     getsockname         = libc::SYS_getsockname         as isize,
+    setsockopt          = libc::SYS_setsockopt          as isize,
     getsockopt          = libc::SYS_getsockopt          as isize,
     getpeername         = libc::SYS_getpeername         as isize,
     #[cfg(not(target_arch = "aarch64"))]
@@ -61,6 +62,10 @@ pub enum Syscall {
     unlink              = libc::SYS_unlink              as isize,
     unlinkat            = libc::SYS_unlinkat            as isize,
     #[cfg(not(target_arch = "aarch64"))]
+    rename              = libc::SYS_rename              as isize,
+    renameat            = libc::SYS_renameat            as isize,
+    fsync               = libc::SYS_fsync               as isize,
+    #[cfg(not(target_arch = "aarch64"))]
     symlink             = libc::SYS_symlink             as isize,
     symlinkat           = libc::SYS_symlinkat           as isize,
     pipe2               = libc::SYS_pipe2               as isize,
@@ -90,6 +95,7 @@ pub enum Syscall {
     clock_gettime       = libc::SYS_clock_gettime       as isize,
     gettimeofday        = libc::SYS_gettimeofday        as isize,
     rt_sigprocmask      = libc::SYS_rt_sigprocmask      as isize,
+    rt_sigtimedwait     = libc::SYS_rt_sigtimedwait     as isize,
 }
 
 impl Syscall {
@@ -0,0 +1,162 @@
+//! Keys of sensors that sign their messages before they're sent.
+//!
+//! The daemon only stores the signature, `tr1pctl fsck` verifies it with
+//! the public keys in the `[sensors]` section of the config.
+use blocks::{Block, KeyId, SensorSignature};
+use crypto::{self, PublicKey, SecretKey};
+use recipe::BlockRecipe;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+mod errors {
+    use std::io;
+
+    error_chain! {
+        errors {
+            DuplicateKey(a: String, b: String) {
+                description("two sensors have the same key id")
+                display("sensors {:?} and {:?} have the same key id", a, b)
+            }
+        }
+        links {
+            Blocks(::blocks::Error, ::blocks::ErrorKind);
+            Crypto(::crypto::Error, ::crypto::ErrorKind);
+        }
+        foreign_links {
+            Io(io::Error);
+        }
+    }
+}
+pub use self::errors::{Result, ResultExt, Error, ErrorKind};
+
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    File::open(path)?.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// The secret key of a sensor, see `tr1pctl sensor-keygen`.
+#[derive(Debug)]
+pub struct SensorKey {
+    pubkey: PublicKey,
+    seckey: SecretKey,
+}
+
+impl SensorKey {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SensorKey> {
+        let seckey = crypto::to_privkey(&read_file(path.as_ref())?)?;
+        // the secret key ends with the public key
+        let pubkey = crypto::to_pubkey(&seckey.0[32..])?;
+
+        Ok(SensorKey {
+            pubkey,
+            seckey,
+        })
+    }
+
+    #[inline]
+    pub fn key_id(&self) -> KeyId {
+        KeyId::of(&self.pubkey)
+    }
+
+    /// Sign an `Info` or `Sequenced` recipe.
+    pub fn sign(&self, recipe: BlockRecipe) -> Result<BlockRecipe> {
+        let sig = match recipe.sensor_payload() {
+            Some((seq, msg)) => SensorSignature::sign(seq, msg, &self.pubkey, &self.seckey),
+            None => bail!("only single messages can be signed by a sensor"),
+        };
+        let recipe = BlockRecipe::signed(sig, recipe)?;
+        Ok(recipe)
+    }
+}
+
+/// The result of verifying the sensor signature of a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SensorCheck {
+    /// The block doesn't have a sensor signature
+    Unsigned,
+    /// Signed by the named sensor
+    Valid(String),
+    /// The key isn't in the registry
+    UnknownKey(KeyId),
+    /// The signature doesn't match the message of the named sensor
+    Invalid(String),
+    /// Signed by the named sensor, but the signature covers the plaintext
+    /// of an encrypted payload
+    Sealed(String),
+    /// Signed by the named sensor, but the payload has been redacted
+    Redacted(String),
+}
+
+/// The public keys of the known sensors, by key id.
+#[derive(Debug, Default)]
+pub struct SensorRegistry {
+    keys: BTreeMap<KeyId, (String, PublicKey)>,
+}
+
+impl SensorRegistry {
+    /// Load the public keys, `sensors` maps the name of a sensor to the
+    /// path of its public key.
+    pub fn load(sensors: &BTreeMap<String, String>) -> Result<SensorRegistry> {
+        let mut registry = SensorRegistry::default();
+        for (name, path) in sensors {
+            let pubkey = crypto::to_pubkey(&read_file(Path::new(path))?)
+                .chain_err(|| format!("invalid public key of sensor {:?}", name))?;
+            registry.insert(name.clone(), pubkey)?;
+        }
+        Ok(registry)
+    }
+
+    pub fn insert(&mut self, name: String, pubkey: PublicKey) -> Result<()> {
+        let key_id = KeyId::of(&pubkey);
+        if let Some(&(ref other, _)) = self.keys.get(&key_id) {
+            bail!(ErrorKind::DuplicateKey(other.clone(), name));
+        }
+        self.keys.insert(key_id, (name, pubkey));
+        Ok(())
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    #[inline]
+    pub fn verify(&self, block: &Block) -> SensorCheck {
+        self.verify_msg(block, block.msg().map(|msg| msg.as_slice()))
+    }
+
+    /// Same as [`SensorRegistry::verify`], but the message is passed
+    /// separately. This is needed for blocks with a commitment or a
+    /// compressed payload, `None` means the payload has been redacted.
+    ///
+    /// [`SensorRegistry::verify`]: #method.verify
+    pub fn verify_msg(&self, block: &Block, msg: Option<&[u8]>) -> SensorCheck {
+        let sig = match block.sensor_signature() {
+            Some(sig) => sig,
+            None => return SensorCheck::Unsigned,
+        };
+
+        let (name, pubkey) = match self.keys.get(&sig.key_id) {
+            Some(&(ref name, ref pubkey)) => (name, pubkey),
+            None => return SensorCheck::UnknownKey(sig.key_id.clone()),
+        };
+
+        if block.envelope().is_some() {
+            return SensorCheck::Sealed(name.clone());
+        }
+
+        let msg = match msg {
+            Some(msg) => msg,
+            None => return SensorCheck::Redacted(name.clone()),
+        };
+        match sig.verify(block.sequence(), msg, pubkey) {
+            Ok(_) => SensorCheck::Valid(name.clone()),
+            Err(_) => SensorCheck::Invalid(name.clone()),
+        }
+    }
+}
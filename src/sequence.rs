@@ -0,0 +1,280 @@
+//! Sequence numbers of sources, to detect messages that never reached the
+//! ledger.
+//!
+//! A writer numbers the messages of its source without gaps and stores the
+//! next number, so the sequence continues after a restart. `tr1pctl fsck
+//! --sources` follows the sequences of all sources in a range of blocks.
+use atomic;
+use blocks::{self, Block, Sequence};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+mod errors {
+    use std::io;
+
+    error_chain! {
+        errors {
+            InvalidSource(source: String) {
+                description("invalid source")
+                display("invalid source: {:?}", source)
+            }
+            InvalidState(path: String) {
+                description("invalid sequence file")
+                display("invalid sequence file: {:?}", path)
+            }
+            Exhausted {
+                description("sequence numbers are exhausted")
+            }
+        }
+        foreign_links {
+            Io(io::Error);
+        }
+    }
+}
+pub use self::errors::{Result, Error, ErrorKind};
+
+
+/// Hands out the sequence numbers of a source, the next number is stored
+/// in a file before the numbers are used.
+#[derive(Debug)]
+pub struct SourceCounter {
+    source: String,
+    path: PathBuf,
+    next: u64,
+}
+
+impl SourceCounter {
+    /// Continue the sequence that is stored in `path`, a new sequence
+    /// starts at 1.
+    pub fn open<I: Into<String>, P: Into<PathBuf>>(source: I, path: P) -> Result<SourceCounter> {
+        let source = source.into();
+        if !blocks::validate_source(&source) {
+            bail!(ErrorKind::InvalidSource(source));
+        }
+
+        let path = path.into();
+        let next = match load(&path)? {
+            Some(next) => next,
+            None => 1,
+        };
+
+        Ok(SourceCounter {
+            source,
+            path,
+            next,
+        })
+    }
+
+    #[inline]
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The number the next message is going to get.
+    #[inline]
+    pub fn next(&self) -> u64 {
+        self.next
+    }
+
+    /// Reserve the numbers for `count` messages. They are lost if the
+    /// messages aren't written, which shows up as a gap.
+    pub fn assign(&mut self, count: usize) -> Result<Vec<Sequence>> {
+        let next = match self.next.checked_add(count as u64) {
+            Some(next) => next,
+            None => bail!(ErrorKind::Exhausted),
+        };
+        store(&self.path, next)?;
+
+        let seqs = (self.next..next)
+            .map(|seq| Sequence {
+                source: self.source.clone(),
+                seq,
+            })
+            .collect();
+        self.next = next;
+
+        Ok(seqs)
+    }
+}
+
+fn load(path: &Path) -> Result<Option<u64>> {
+    let mut buf = String::new();
+    match File::open(path) {
+        Ok(mut file) => file.read_to_string(&mut buf)?,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    match buf.trim().parse() {
+        Ok(next) if next > 0 => Ok(Some(next)),
+        _ => bail!(ErrorKind::InvalidState(path.to_string_lossy().into_owned())),
+    }
+}
+
+fn store(path: &Path, next: u64) -> Result<()> {
+    atomic::replace(path, format!("{}\n", next).as_bytes())?;
+    Ok(())
+}
+
+/// What happened to the sequence of a source when a message has been added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Ok,
+    /// The messages between the last one and this one are missing
+    Gap(u64, u64),
+    /// The number has already been seen
+    Duplicate,
+    /// The number has been missing so far, it arrived late
+    Reordered,
+}
+
+/// The sequence of one source in a range of blocks.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SourceReport {
+    pub messages: u64,
+    pub first: u64,
+    pub last: u64,
+    /// Numbers that are still missing, as inclusive ranges
+    pub gaps: Vec<(u64, u64)>,
+    pub duplicates: u64,
+    pub reordered: u64,
+}
+
+impl SourceReport {
+    #[inline]
+    pub fn is_ok(&self) -> bool {
+        self.gaps.is_empty() && self.duplicates == 0 && self.reordered == 0
+    }
+
+    /// Number of messages that are missing, this saturates at `u64::MAX`.
+    pub fn missing(&self) -> u64 {
+        self.gaps.iter()
+            .map(|&(start, end)| (end - start).saturating_add(1))
+            .fold(0, |sum, n: u64| sum.saturating_add(n))
+    }
+}
+
+impl fmt::Display for SourceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} messages, #{} to #{}, {} missing, {} duplicates, {} reordered",
+               self.messages, self.first, self.last, self.missing(), self.duplicates, self.reordered)?;
+
+        if !self.gaps.is_empty() {
+            let gaps: Vec<_> = self.gaps.iter()
+                .map(|&(start, end)| if start == end {
+                    format!("#{}", start)
+                } else {
+                    format!("#{} to #{}", start, end)
+                })
+                .collect();
+            write!(f, " (gaps: {})", gaps.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The client that wrote a message. Sources are scoped to it, a client
+/// can't fill the gaps of another client that uses the same source name.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Writer {
+    Uid(u32),
+    /// The curve key of a zmq client
+    ClientKey(String),
+    /// zmq clients without curve can't be told apart
+    Anonymous,
+}
+
+impl Writer {
+    pub fn of(block: &Block) -> Writer {
+        match (block.origin(), block.client_key()) {
+            (Some(origin), _) => Writer::Uid(origin.uid),
+            (None, Some(key)) => Writer::ClientKey(key.to_string()),
+            (None, None) => Writer::Anonymous,
+        }
+    }
+}
+
+impl fmt::Display for Writer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Writer::Uid(uid) => write!(f, "uid={}", uid),
+            Writer::ClientKey(ref key) => write!(f, "curve={}", key),
+            Writer::Anonymous => write!(f, "anonymous"),
+        }
+    }
+}
+
+/// Follows the sequences of all sources, see `tr1pctl fsck --sources`.
+///
+/// The range may start in the middle of a sequence, the first number of
+/// each source is taken as it is. Sessions of the daemon don't matter.
+#[derive(Debug, Default)]
+pub struct SourceTracker {
+    sources: BTreeMap<(Writer, String), SourceReport>,
+}
+
+impl SourceTracker {
+    pub fn add(&mut self, writer: &Writer, seq: &Sequence) -> Verdict {
+        let key = (writer.clone(), seq.source.clone());
+        let report = match self.sources.get_mut(&key) {
+            Some(report) => report,
+            None => {
+                self.sources.insert(key, SourceReport {
+                    messages: 1,
+                    first: seq.seq,
+                    last: seq.seq,
+                    ..SourceReport::default()
+                });
+                return Verdict::Ok;
+            },
+        };
+
+        report.messages += 1;
+        let n = seq.seq;
+
+        if report.last.checked_add(1) == Some(n) {
+            report.last = n;
+            Verdict::Ok
+        } else if n > report.last {
+            let gap = (report.last + 1, n - 1);
+            report.gaps.push(gap);
+            report.last = n;
+            Verdict::Gap(gap.0, gap.1)
+        } else if fill_gap(&mut report.gaps, n) {
+            report.reordered += 1;
+            Verdict::Reordered
+        } else {
+            report.duplicates += 1;
+            Verdict::Duplicate
+        }
+    }
+
+    /// The report of each source, ordered by writer and name.
+    #[inline]
+    pub fn reports(&self) -> &BTreeMap<(Writer, String), SourceReport> {
+        &self.sources
+    }
+}
+
+/// Remove `n` from the gaps, returns `false` if it wasn't missing.
+fn fill_gap(gaps: &mut Vec<(u64, u64)>, n: u64) -> bool {
+    let idx = match gaps.iter().position(|&(start, end)| start <= n && n <= end) {
+        Some(idx) => idx,
+        None => return false,
+    };
+
+    let (start, end) = gaps.remove(idx);
+    if n < end {
+        gaps.insert(idx, (n + 1, end));
+    }
+    if start < n {
+        gaps.insert(idx, (start, n - 1));
+    }
+
+    true
+}
@@ -0,0 +1,82 @@
+//! Signals are received by a dedicated thread instead of a signal handler.
+use libc;
+
+use std::io;
+use std::mem;
+use std::ptr;
+
+mod errors {
+    use std::io;
+
+    error_chain! {
+        foreign_links {
+            Io(io::Error);
+        }
+    }
+}
+pub use self::errors::{Result, Error, ErrorKind};
+
+
+/// What the daemon should do after receiving a signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    /// SIGTERM or SIGINT, finish the current work and exit
+    Shutdown(&'static str),
+    /// SIGHUP, reload the config
+    Reload,
+}
+
+impl Signal {
+    pub fn from_raw(signo: libc::c_int) -> Option<Signal> {
+        match signo {
+            libc::SIGTERM => Some(Signal::Shutdown("SIGTERM")),
+            libc::SIGINT => Some(Signal::Shutdown("SIGINT")),
+            libc::SIGHUP => Some(Signal::Reload),
+            _ => None,
+        }
+    }
+}
+
+/// The set of signals that are handled by the daemon.
+pub struct Signals {
+    set: libc::sigset_t,
+}
+
+impl Signals {
+    /// Block the signals in the calling thread. Threads inherit the mask, so
+    /// this needs to be called before any other thread is started.
+    pub fn block() -> Result<Signals> {
+        let set = unsafe {
+            let mut set = mem::zeroed();
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, libc::SIGTERM);
+            libc::sigaddset(&mut set, libc::SIGINT);
+            libc::sigaddset(&mut set, libc::SIGHUP);
+            set
+        };
+
+        let ret = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) };
+        if ret != 0 {
+            return Err(io::Error::from_raw_os_error(ret).into());
+        }
+
+        Ok(Signals {
+            set,
+        })
+    }
+
+    /// Wait until one of the signals is pending.
+    pub fn wait(&self) -> Result<Signal> {
+        loop {
+            let mut signo = 0;
+            let ret = unsafe { libc::sigwait(&self.set, &mut signo) };
+            if ret != 0 {
+                return Err(io::Error::from_raw_os_error(ret).into());
+            }
+
+            if let Some(signal) = Signal::from_raw(signo) {
+                return Ok(signal);
+            }
+        }
+    }
+}
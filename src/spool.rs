@@ -0,0 +1,302 @@
+//! Local storage for messages that couldn't be delivered to the daemon.
+//!
+//! Each entry gets a local sequence number, the time it has been spooled
+//! and an HMAC with a key that is created in the spool directory. The key
+//! detects corrupted entries, it doesn't protect against somebody who can
+//! read the spool directory. Entries are replayed in order once the daemon
+//! is reachable again, the daemon records the sequence number and the time
+//! in the block.
+use sodiumoxide::crypto::auth::hmacsha256::{self, Key, Tag};
+
+use atomic;
+use wire::{u32_to_vec, u64_to_vec};
+
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod errors {
+    use std::io;
+
+    error_chain! {
+        errors {
+            CorruptedKey {
+                description("corrupted spool key")
+            }
+            InvalidTag(seq: u64) {
+                description("spool entry has an invalid tag")
+                display("spool entry {} has an invalid tag", seq)
+            }
+            InvalidSequence(expected: u64, found: u64) {
+                description("spool entries are out of sequence")
+                display("spool entries are out of sequence, expected {}, found {}", expected, found)
+            }
+            TrailingBytes(len: usize) {
+                description("spool ends with bytes that aren't an entry")
+                display("spool ends with {} bytes that aren't an entry", len)
+            }
+        }
+        foreign_links {
+            Io(io::Error);
+        }
+    }
+}
+pub use self::errors::{Result, Error, ErrorKind};
+
+const KEY_FILE: &str = "key";
+const SPOOL_FILE: &str = "spool";
+/// The sequence number of the last entry the daemon acknowledged.
+const REPLAYED_FILE: &str = "replayed";
+
+/// seq, time and length of the message
+const HEADER_LEN: usize = 8 + 8 + 4;
+const TAG_LEN: usize = 32;
+
+
+/// A message that has been spooled.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpoolEntry {
+    /// Local sequence number, starts at 1 and has no gaps
+    pub seq: u64,
+    /// Unix time the message has been spooled
+    pub time: u64,
+    pub msg: Vec<u8>,
+}
+
+impl SpoolEntry {
+    fn encode_header(&self, buf: &mut Vec<u8>) {
+        buf.extend(&u64_to_vec(self.seq));
+        buf.extend(&u64_to_vec(self.time));
+        buf.extend(&u32_to_vec(self.msg.len() as u32));
+    }
+
+    fn authenticated(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_LEN + self.msg.len());
+        self.encode_header(&mut buf);
+        buf.extend(&self.msg);
+        buf
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>, key: &Key) {
+        let authenticated = self.authenticated();
+        let tag = hmacsha256::authenticate(&authenticated, key);
+        buf.extend(&authenticated);
+        buf.extend(&tag.0);
+    }
+}
+
+/// Decode the next entry, returns `None` if the buffer ends in the middle
+/// of an entry.
+fn decode_entry(buf: &[u8], key: &Key) -> Result<Option<(SpoolEntry, usize)>> {
+    if buf.len() < HEADER_LEN {
+        return Ok(None);
+    }
+
+    let int = |bytes: &[u8]| bytes.iter().fold(0u64, |acc, &b| acc << 8 | u64::from(b));
+    let seq = int(&buf[..8]);
+    let time = int(&buf[8..16]);
+    let len = int(&buf[16..20]) as usize;
+
+    let end = HEADER_LEN + len;
+    if buf.len() < end + TAG_LEN {
+        return Ok(None);
+    }
+
+    let tag = Tag::from_slice(&buf[end..end + TAG_LEN]).expect("tag has the right length");
+    if !hmacsha256::verify(&tag, &buf[..end], key) {
+        bail!(ErrorKind::InvalidTag(seq));
+    }
+
+    let entry = SpoolEntry {
+        seq,
+        time,
+        msg: buf[HEADER_LEN..end].to_vec(),
+    };
+    Ok(Some((entry, end + TAG_LEN)))
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// A spool directory, it should only be used by one process at a time.
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+    key: Key,
+    file: File,
+    next_seq: u64,
+    replayed: u64,
+}
+
+impl Spool {
+    /// Open the spool, the directory and the key are created if needed.
+    /// Fails if an entry has been modified. An incomplete header at the end,
+    /// eg. after a crash, is discarded. Anything longer could be an entry
+    /// with a modified length, this fails as well.
+    pub fn open<P: Into<PathBuf>>(dir: P) -> Result<Spool> {
+        let dir = dir.into();
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)?;
+
+        let key = load_key(&dir.join(KEY_FILE))?;
+        let replayed = load_replayed(&dir.join(REPLAYED_FILE))?;
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(dir.join(SPOOL_FILE))?;
+
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let (entries, valid) = decode_entries(&buf, &key)?;
+
+        let trailing = buf.len() - valid;
+        if trailing >= HEADER_LEN + TAG_LEN {
+            bail!(ErrorKind::TrailingBytes(trailing));
+        } else if trailing > 0 {
+            warn!("discarding incomplete entry at the end of the spool");
+            file.set_len(valid as u64)?;
+        }
+
+        let last = entries.last().map(|entry| entry.seq).unwrap_or(0);
+        let next_seq = if last > replayed { last } else { replayed } + 1;
+
+        Ok(Spool {
+            dir,
+            key,
+            file,
+            next_seq,
+            replayed,
+        })
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Number of entries that haven't been replayed yet.
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.next_seq - 1 - self.replayed
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Append a message, it's on disk once this returns.
+    pub fn push(&mut self, msg: &[u8]) -> Result<SpoolEntry> {
+        let entry = SpoolEntry {
+            seq: self.next_seq,
+            time: unix_time(),
+            msg: msg.to_vec(),
+        };
+
+        let mut buf = Vec::new();
+        entry.encode(&mut buf, &self.key);
+        self.file.write_all(&buf)?;
+        self.file.sync_data()?;
+
+        self.next_seq += 1;
+        Ok(entry)
+    }
+
+    /// The entries that haven't been replayed yet, in order.
+    pub fn pending(&self) -> Result<Vec<SpoolEntry>> {
+        let mut buf = Vec::new();
+        File::open(self.dir.join(SPOOL_FILE))?
+            .read_to_end(&mut buf)?;
+
+        let (entries, _) = decode_entries(&buf, &self.key)?;
+        Ok(entries.into_iter()
+            .filter(|entry| entry.seq > self.replayed)
+            .collect())
+    }
+
+    /// Mark the entries up to `seq` as replayed, the spool is truncated
+    /// once everything has been replayed. A crash before this is recorded
+    /// replays the entries again.
+    pub fn replayed(&mut self, seq: u64) -> Result<()> {
+        store_replayed(&self.dir.join(REPLAYED_FILE), seq)?;
+        self.replayed = seq;
+
+        if self.is_empty() {
+            self.file.set_len(0)?;
+            self.file.sync_data()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the entries and the number of bytes they occupy.
+fn decode_entries(mut buf: &[u8], key: &Key) -> Result<(Vec<SpoolEntry>, usize)> {
+    let mut entries: Vec<SpoolEntry> = Vec::new();
+    let mut valid = 0;
+
+    while let Some((entry, len)) = decode_entry(buf, key)? {
+        if let Some(prev) = entries.last() {
+            if entry.seq != prev.seq + 1 {
+                bail!(ErrorKind::InvalidSequence(prev.seq + 1, entry.seq));
+            }
+        }
+
+        entries.push(entry);
+        buf = &buf[len..];
+        valid += len;
+    }
+
+    Ok((entries, valid))
+}
+
+fn load_key(path: &Path) -> Result<Key> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path);
+
+    match file {
+        Ok(mut file) => {
+            let key = hmacsha256::gen_key();
+            file.write_all(&key.0)?;
+            file.sync_all()?;
+            Ok(key)
+        },
+        Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            let mut buf = Vec::new();
+            File::open(path)?.read_to_end(&mut buf)?;
+            Key::from_slice(&buf)
+                .ok_or_else(|| ErrorKind::CorruptedKey.into())
+        },
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn load_replayed(path: &Path) -> Result<u64> {
+    let mut buf = String::new();
+    match File::open(path) {
+        Ok(mut file) => file.read_to_string(&mut buf)?,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+
+    buf.trim().parse()
+        .map_err(|_| "invalid sequence number in spool state".into())
+}
+
+fn store_replayed(path: &Path, seq: u64) -> Result<()> {
+    atomic::replace(path, format!("{}\n", seq).as_bytes())?;
+    Ok(())
+}
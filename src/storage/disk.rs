@@ -1,6 +1,7 @@
 use std::fs;
 use std::fs::File;
 use std::os::unix;
+use std::io;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 use std::fs::OpenOptions;
@@ -23,10 +24,19 @@ impl DiskStorage {
     }
 
     pub fn pointer_to_path(&self, pointer: &BlockPointer) -> PathBuf {
+        self.object_path("blocks", pointer)
+    }
+
+    /// Payloads of blocks with a commitment are stored in a separate tree.
+    pub fn payload_path(&self, pointer: &BlockPointer) -> PathBuf {
+        self.object_path("payloads", pointer)
+    }
+
+    fn object_path(&self, folder: &str, pointer: &BlockPointer) -> PathBuf {
         let (prefix, hash) = pointer.slice();
 
         let mut path = self.path.clone();
-        path.push("blocks");
+        path.push(folder);
         path.push(prefix);
         path.push(hash);
 
@@ -41,6 +51,20 @@ impl DiskStorage {
         Ok(())
     }
 
+    #[inline]
+    fn write_new(&self, path: &Path, bytes: &[u8]) -> Result<()> {
+        self.ensure_parent_folder(path)?;
+
+        let mut file = OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .create_new(true)
+                        .mode(0o640)
+                        .open(path)?;
+        file.write_all(bytes)?;
+        Ok(())
+    }
+
     #[inline]
     pub fn into_engine(self) -> StorageEngine {
         StorageEngine::Disk(self)
@@ -51,15 +75,7 @@ impl BlockStorage for DiskStorage {
     fn write_bytes(&mut self, pointer: &BlockPointer, bytes: Vec<u8>) -> Result<()> {
         let path = self.pointer_to_path(&pointer);
 
-        self.ensure_parent_folder(&path)?;
-
-        let mut file = OpenOptions::new()
-                        .write(true)
-                        .create(true)
-                        .create_new(true)
-                        .mode(0o640)
-                        .open(&path)?;
-        file.write_all(&bytes)?;
+        self.write_new(&path, &bytes)?;
 
         println!("wrote {:x} to {:?}", pointer, path);
 
@@ -118,4 +134,31 @@ impl BlockStorage for DiskStorage {
         unix::fs::symlink(src, dest)?;
         Ok(())
     }
+
+    fn write_payload(&mut self, pointer: &BlockPointer, bytes: Vec<u8>) -> Result<()> {
+        let path = self.payload_path(pointer);
+        self.write_new(&path, &bytes)
+    }
+
+    fn get_payload(&self, pointer: &BlockPointer) -> Result<Option<Vec<u8>>> {
+        let path = self.payload_path(pointer);
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        Ok(Some(buf))
+    }
+
+    fn remove_payload(&mut self, pointer: &BlockPointer) -> Result<bool> {
+        let path = self.payload_path(pointer);
+        match fs::remove_file(path) {
+            Ok(_) => Ok(true),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
 }
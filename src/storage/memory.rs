@@ -6,6 +6,7 @@ use std::collections::BTreeMap;
 #[derive(Default)]
 pub struct MemoryStorage {
     blocks: BTreeMap<BlockPointer, Vec<u8>>,
+    payloads: BTreeMap<BlockPointer, Vec<u8>>,
     head: BlockPointer,
 }
 
@@ -41,4 +42,17 @@ impl BlockStorage for MemoryStorage {
         self.head = pointer.clone();
         Ok(())
     }
+
+    fn write_payload(&mut self, pointer: &BlockPointer, bytes: Vec<u8>) -> Result<()> {
+        self.payloads.insert(pointer.clone(), bytes);
+        Ok(())
+    }
+
+    fn get_payload(&self, pointer: &BlockPointer) -> Result<Option<Vec<u8>>> {
+        Ok(self.payloads.get(pointer).cloned())
+    }
+
+    fn remove_payload(&mut self, pointer: &BlockPointer) -> Result<bool> {
+        Ok(self.payloads.remove(pointer).is_some())
+    }
 }
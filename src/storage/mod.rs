@@ -39,6 +39,18 @@ pub trait BlockStorage {
 
     fn update_head(&mut self, pointer: &BlockPointer) -> Result<()>;
 
+    /// Store the payload of a block with a [`Commitment`], this happens
+    /// before the block is written.
+    ///
+    /// [`Commitment`]: ../blocks/struct.Commitment.html
+    fn write_payload(&mut self, pointer: &BlockPointer, bytes: Vec<u8>) -> Result<()>;
+
+    /// Returns `None` if the payload has been redacted.
+    fn get_payload(&self, pointer: &BlockPointer) -> Result<Option<Vec<u8>>>;
+
+    /// Returns `false` if the payload has already been redacted.
+    fn remove_payload(&mut self, pointer: &BlockPointer) -> Result<bool>;
+
     #[inline]
     fn push(&mut self, block: &Block) -> Result<BlockPointer> {
         let (pointer, bytes) = block.sha3_encode();
@@ -58,6 +70,21 @@ pub trait BlockStorage {
         }
     }
 
+    /// The message of a block, the payload of a block with a commitment is
+    /// loaded and verified. Returns `None` if it has been redacted, or if the
+    /// block has no message at all.
+    fn get_msg(&self, block: &Block) -> Result<Option<Vec<u8>>> {
+        let commitment = match block.commitment() {
+            Some(commitment) => commitment,
+            None => return Ok(block.msg().cloned()),
+        };
+
+        match self.get_payload(&block.sha3())? {
+            Some(stored) => Ok(Some(commitment.open(&stored)?.to_vec())),
+            None => Ok(None),
+        }
+    }
+
     fn resolve_pointer(&self, spec: spec::SpecPointer) -> Result<BlockPointer> {
         use spec::SpecPointer::*;
 
@@ -139,6 +166,8 @@ pub trait BlockStorage {
 pub enum StorageEngine {
     Disk(DiskStorage),
     Memory(MemoryStorage),
+    #[cfg(test)]
+    Mock(::tests::mocks::storage::MockStorage),
 }
 
 impl BlockStorage for StorageEngine {
@@ -147,6 +176,8 @@ impl BlockStorage for StorageEngine {
         match *self {
             StorageEngine::Disk(ref mut s) => s.write_bytes(pointer, bytes),
             StorageEngine::Memory(ref mut s) => s.write_bytes(pointer, bytes),
+            #[cfg(test)]
+            StorageEngine::Mock(ref mut s) => s.write_bytes(pointer, bytes),
         }
     }
 
@@ -155,6 +186,8 @@ impl BlockStorage for StorageEngine {
         match *self {
             StorageEngine::Disk(ref s) => s.get_bytes(pointer),
             StorageEngine::Memory(ref s) => s.get_bytes(pointer),
+            #[cfg(test)]
+            StorageEngine::Mock(ref s) => s.get_bytes(pointer),
         }
     }
 
@@ -163,6 +196,8 @@ impl BlockStorage for StorageEngine {
         match *self {
             StorageEngine::Disk(ref s) => s.get_head(),
             StorageEngine::Memory(ref s) => s.get_head(),
+            #[cfg(test)]
+            StorageEngine::Mock(ref s) => s.get_head(),
         }
     }
 
@@ -171,6 +206,38 @@ impl BlockStorage for StorageEngine {
         match *self {
             StorageEngine::Disk(ref mut s) => s.update_head(pointer),
             StorageEngine::Memory(ref mut s) => s.update_head(pointer),
+            #[cfg(test)]
+            StorageEngine::Mock(ref mut s) => s.update_head(pointer),
+        }
+    }
+
+    #[inline]
+    fn write_payload(&mut self, pointer: &BlockPointer, bytes: Vec<u8>) -> Result<()> {
+        match *self {
+            StorageEngine::Disk(ref mut s) => s.write_payload(pointer, bytes),
+            StorageEngine::Memory(ref mut s) => s.write_payload(pointer, bytes),
+            #[cfg(test)]
+            StorageEngine::Mock(ref mut s) => s.write_payload(pointer, bytes),
+        }
+    }
+
+    #[inline]
+    fn get_payload(&self, pointer: &BlockPointer) -> Result<Option<Vec<u8>>> {
+        match *self {
+            StorageEngine::Disk(ref s) => s.get_payload(pointer),
+            StorageEngine::Memory(ref s) => s.get_payload(pointer),
+            #[cfg(test)]
+            StorageEngine::Mock(ref s) => s.get_payload(pointer),
+        }
+    }
+
+    #[inline]
+    fn remove_payload(&mut self, pointer: &BlockPointer) -> Result<bool> {
+        match *self {
+            StorageEngine::Disk(ref mut s) => s.remove_payload(pointer),
+            StorageEngine::Memory(ref mut s) => s.remove_payload(pointer),
+            #[cfg(test)]
+            StorageEngine::Mock(ref mut s) => s.remove_payload(pointer),
         }
     }
 }
@@ -0,0 +1,336 @@
+use blocks::Syslog;
+
+use std::fs::{self, Permissions};
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::{IpAddr, TcpListener, TcpStream, UdpSocket};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixDatagram;
+use std::str;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+mod errors {
+    use std::io;
+
+    error_chain! {
+        errors {
+            MessageTooLarge(len: usize) {
+                description("syslog message too large")
+                display("syslog message too large: {} bytes", len)
+            }
+        }
+        foreign_links {
+            Io(io::Error);
+        }
+    }
+}
+pub use self::errors::{Result, Error, ErrorKind};
+
+
+/// Anything larger doesn't fit into an info block anyway.
+pub const MAX_MESSAGE_SIZE: usize = 65535;
+
+/// Each tcp connection has its own thread, further connections are closed.
+pub const MAX_TCP_CONNECTIONS: usize = 256;
+
+/// tcp connections that don't send anything are closed after this time.
+const TCP_IDLE_TIMEOUT: u64 = 300;
+
+/// Used if the message doesn't start with a priority (user.notice).
+const DEFAULT_PRI: u8 = 13;
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                            "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+
+/// A message that has been received by one of the listeners.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyslogMessage {
+    pub syslog: Syslog,
+    /// The message as it has been received, including the header.
+    pub bytes: Vec<u8>,
+}
+
+/// Parse an RFC 5424 or RFC 3164 message.
+///
+/// This never fails, the full message is always kept. If the header can't
+/// be parsed, the fields that couldn't be found are left empty.
+pub fn parse(bytes: &[u8]) -> SyslogMessage {
+    let bytes = trim_end(bytes);
+
+    let (pri, rest) = match parse_pri(bytes) {
+        Some((pri, rest)) => (pri, rest),
+        None => (DEFAULT_PRI, bytes),
+    };
+
+    let rest = String::from_utf8_lossy(rest);
+    let (hostname, app_name) = if rest.starts_with("1 ") {
+        parse_rfc5424(&rest[2..])
+    } else {
+        parse_rfc3164(&rest)
+    };
+
+    SyslogMessage {
+        syslog: Syslog {
+            facility: pri >> 3,
+            severity: pri & 0x07,
+            hostname,
+            app_name,
+        },
+        bytes: bytes.to_vec(),
+    }
+}
+
+/// Remove trailing newlines and NUL bytes that some senders append.
+fn trim_end(mut bytes: &[u8]) -> &[u8] {
+    while let Some((&last, rest)) = bytes.split_last() {
+        match last {
+            b'\n' | b'\r' | b'\0' => bytes = rest,
+            _ => break,
+        }
+    }
+    bytes
+}
+
+/// Parse `<PRI>`, the priority is at most 191.
+fn parse_pri(bytes: &[u8]) -> Option<(u8, &[u8])> {
+    if bytes.first() != Some(&b'<') {
+        return None;
+    }
+
+    let end = bytes.iter().take(5).position(|&b| b == b'>')?;
+    let pri = str::from_utf8(&bytes[1..end]).ok()?;
+
+    if pri.is_empty() || !pri.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    match pri.parse::<u8>() {
+        Ok(pri) if pri <= 191 => Some((pri, &bytes[end + 1..])),
+        _ => None,
+    }
+}
+
+fn nil(value: &str) -> Option<String> {
+    match value {
+        "" | "-" => None,
+        value => Some(value.to_string()),
+    }
+}
+
+/// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID ...`, after the version.
+fn parse_rfc5424(rest: &str) -> (Option<String>, Option<String>) {
+    let mut fields = rest.splitn(4, ' ').skip(1);
+
+    let hostname = fields.next().and_then(nil);
+    let app_name = fields.next().and_then(nil);
+
+    (hostname, app_name)
+}
+
+/// `Mmm dd hh:mm:ss`, the day is padded with a space.
+fn is_bsd_timestamp(rest: &str) -> bool {
+    let bytes = rest.as_bytes();
+
+    bytes.len() >= 16 &&
+        MONTHS.iter().any(|m| rest.starts_with(m)) &&
+        bytes[3] == b' ' &&
+        bytes[6] == b' ' &&
+        bytes[9] == b':' &&
+        bytes[12] == b':' &&
+        bytes[15] == b' '
+}
+
+/// `TIMESTAMP HOSTNAME TAG: MSG`. Messages from the local syslog(3) don't
+/// have a hostname, in that case the tag directly follows the timestamp.
+fn parse_rfc3164(rest: &str) -> (Option<String>, Option<String>) {
+    let has_timestamp = is_bsd_timestamp(rest);
+    let rest = if has_timestamp {
+        &rest[16..]
+    } else {
+        rest
+    };
+
+    let mut fields = rest.splitn(3, ' ');
+    let first = fields.next().unwrap_or("");
+
+    if is_tag(first) {
+        return (None, parse_tag(first));
+    }
+
+    // without a timestamp there's no header, everything is the message
+    if !has_timestamp {
+        return (None, None);
+    }
+
+    let hostname = nil(first);
+    let app_name = match fields.next() {
+        Some(tag) if is_tag(tag) => parse_tag(tag),
+        _ => None,
+    };
+
+    (hostname, app_name)
+}
+
+fn is_tag(field: &str) -> bool {
+    field.ends_with(':') || field.contains('[')
+}
+
+/// `sshd[1234]:` and `sshd:` both return `sshd`.
+fn parse_tag(field: &str) -> Option<String> {
+    let end = field.find(|c| c == '[' || c == ':')
+        .unwrap_or_else(|| field.len());
+    nil(&field[..end])
+}
+
+
+/// Parse the message and pass it on with the address of the sender, empty
+/// messages are ignored. Local senders don't have an address.
+fn emit<F: Fn(Option<IpAddr>, SyslogMessage)>(callback: &F, peer: Option<IpAddr>, bytes: &[u8]) {
+    if !trim_end(bytes).is_empty() {
+        callback(peer, parse(bytes));
+    }
+}
+
+/// Receive messages on a unix datagram socket, like `/dev/log`.
+pub fn listen_unix<F>(path: &str, callback: F) -> Result<()>
+    where F: Fn(Option<IpAddr>, SyslogMessage) + Send + 'static
+{
+    // remove stale socket from a previous run
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path)?;
+    }
+
+    let socket = UnixDatagram::bind(path)?;
+
+    // TODO: write a proper solution
+    let perms = Permissions::from_mode(0o770);
+    fs::set_permissions(path, perms)?;
+
+    info!("syslog: listening on unix://{}", path);
+    thread::spawn(move || {
+        let mut buf = vec![0; MAX_MESSAGE_SIZE];
+        loop {
+            match socket.recv(&mut buf) {
+                Ok(n) => emit(&callback, None, &buf[..n]),
+                Err(err) => error!("syslog: failed to receive: {:?}", err),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+pub fn listen_udp<F>(addr: &str, callback: F) -> Result<()>
+    where F: Fn(Option<IpAddr>, SyslogMessage) + Send + 'static
+{
+    let socket = UdpSocket::bind(addr)?;
+
+    info!("syslog: listening on udp://{}", addr);
+    thread::spawn(move || {
+        let mut buf = vec![0; MAX_MESSAGE_SIZE];
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((n, addr)) => emit(&callback, Some(addr.ip()), &buf[..n]),
+                Err(err) => error!("syslog: failed to receive: {:?}", err),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Each connection gets its own thread, up to `MAX_TCP_CONNECTIONS`. Both
+/// octet counting and newline delimited framing are supported, see RFC 6587.
+pub fn listen_tcp<F>(addr: &str, callback: F) -> Result<()>
+    where F: Fn(Option<IpAddr>, SyslogMessage) + Send + Clone + 'static
+{
+    let listener = TcpListener::bind(addr)?;
+
+    info!("syslog: listening on tcp://{}", addr);
+    thread::spawn(move || {
+        let connections = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    error!("syslog: failed to accept connection: {:?}", err);
+                    continue;
+                },
+            };
+
+            if connections.load(Ordering::SeqCst) >= MAX_TCP_CONNECTIONS {
+                warn!("syslog: {} tcp connections are open, closing connection from {:?}",
+                      MAX_TCP_CONNECTIONS, stream.peer_addr().ok());
+                continue;
+            }
+
+            connections.fetch_add(1, Ordering::SeqCst);
+            let connections = connections.clone();
+            let callback = callback.clone();
+            thread::spawn(move || {
+                if let Err(err) = serve_tcp(stream, callback) {
+                    warn!("syslog: dropping connection: {}", err);
+                }
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn serve_tcp<F: Fn(Option<IpAddr>, SyslogMessage)>(stream: TcpStream, callback: F) -> Result<()> {
+    let peer = stream.peer_addr().ok().map(|addr| addr.ip());
+    stream.set_read_timeout(Some(Duration::from_secs(TCP_IDLE_TIMEOUT)))?;
+    let mut stream = BufReader::new(stream);
+
+    while let Some(bytes) = read_frame(&mut stream)? {
+        emit(&callback, peer, &bytes);
+    }
+
+    Ok(())
+}
+
+/// Read the next message from a tcp stream, returns `None` if the peer
+/// closed the connection.
+pub fn read_frame<R: BufRead>(stream: &mut R) -> Result<Option<Vec<u8>>> {
+    let octet_counting = {
+        let buf = stream.fill_buf()?;
+        match buf.first() {
+            Some(b) => b.is_ascii_digit(),
+            None => return Ok(None),
+        }
+    };
+
+    if octet_counting {
+        let mut len = Vec::new();
+        stream.by_ref().take(8).read_until(b' ', &mut len)?;
+        if len.last() == Some(&b' ') {
+            len.pop();
+        }
+
+        let len = str::from_utf8(&len).ok()
+            .and_then(|len| len.parse::<usize>().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid frame length"))?;
+
+        if len > MAX_MESSAGE_SIZE {
+            return Err(ErrorKind::MessageTooLarge(len).into());
+        }
+
+        let mut buf = vec![0; len];
+        stream.read_exact(&mut buf)?;
+        Ok(Some(buf))
+    } else {
+        let mut buf = Vec::new();
+        stream.by_ref().take(MAX_MESSAGE_SIZE as u64 + 1).read_until(b'\n', &mut buf)?;
+
+        if buf.len() > MAX_MESSAGE_SIZE {
+            return Err(ErrorKind::MessageTooLarge(buf.len()).into());
+        }
+
+        Ok(Some(buf))
+    }
+}
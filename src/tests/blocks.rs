@@ -1,4 +1,4 @@
-use blocks::{BlockPointer, Block};
+use blocks::{self, BlockPointer, Block, KeyUsage, RekeyPolicy, Spooled, TxPart, TxTracker};
 use blocks::{InitBlock, RekeyBlock, AlertBlock, InfoBlock};
 use crypto::{self, SignRing};
 use crypto::{PublicKey, Signature};
@@ -444,7 +444,15 @@ fn info_bytes() {
 }
 
 use engine::Engine;
+use metrics::Metrics;
+use recipe::BlockRecipe;
+use spool::SpoolEntry;
 use storage::{MemoryStorage, BlockStorage};
+use tests::mocks::storage::{ClonableError, MockStorage};
+use wire;
+
+use std::thread;
+use std::time::Duration;
 
 #[test]
 fn test_small_block() {
@@ -489,3 +497,323 @@ fn test_too_large_block() {
         _ => panic!("not BlockTooLarge error"),
     };
 }
+
+#[test]
+fn rekey_policy_default() {
+    let policy = RekeyPolicy::default();
+    assert!(policy.is_default());
+    assert_eq!(policy.to_string(), "every info block");
+    assert_eq!(policy.max_age(), None);
+
+    let mut usage = KeyUsage::default();
+    assert!(!policy.is_due(&usage, None));
+    usage.add(10);
+    assert!(policy.is_due(&usage, None));
+}
+
+#[test]
+fn rekey_policy_limits() {
+    let policy = RekeyPolicy {
+        infos: Some(3),
+        seconds: Some(60),
+        bytes: Some(100),
+    };
+    assert!(!policy.is_default());
+    assert_eq!(policy.to_string(), "3 info blocks, 60 seconds, 100 bytes");
+    assert_eq!(policy.max_age(), Some(60));
+
+    let mut usage = KeyUsage::default();
+    usage.add(10);
+    usage.add(10);
+    assert!(!policy.is_due(&usage, None));
+    assert!(!policy.is_due(&usage, Some(59)));
+    assert!(policy.is_due(&usage, Some(60)));
+
+    usage.add(10);
+    assert!(policy.is_due(&usage, None));
+
+    usage.reset();
+    usage.add(100);
+    assert!(policy.is_due(&usage, None));
+}
+
+#[test]
+fn engine_rekey_policy() {
+    let (pk, sk) = crypto::gen_keypair();
+    let ring = SignRing::new(pk, sk);
+    let storage = MemoryStorage::new().into_engine();
+    let policy = RekeyPolicy {
+        infos: Some(2),
+        seconds: None,
+        bytes: None,
+    };
+    let mut engine = Engine::start_with_policy(storage, ring, Metrics::new(), policy).unwrap();
+    // init and the announcement of the policy
+    assert_eq!(engine.session_blocks(), 2);
+
+    engine.recipe(BlockRecipe::Info(b"ohai".to_vec())).unwrap();
+    assert_eq!(engine.session_blocks(), 4);
+    engine.recipe(BlockRecipe::Info(b"ohai".to_vec())).unwrap();
+    assert_eq!(engine.session_blocks(), 5);
+    engine.recipe(BlockRecipe::Info(b"ohai".to_vec())).unwrap();
+    assert_eq!(engine.session_blocks(), 7);
+}
+
+#[test]
+fn engine_rekey_policy_announcement() {
+    let (pk, sk) = crypto::gen_keypair();
+    let ring = SignRing::new(pk, sk);
+    let storage = MemoryStorage::new().into_engine();
+    let policy = RekeyPolicy {
+        infos: None,
+        seconds: None,
+        bytes: Some(16),
+    };
+    let mut engine = Engine::start_with_policy(storage, ring, Metrics::new(), policy).unwrap();
+    // the announcement is longer than the key of the init block may sign
+    assert_eq!(engine.session_blocks(), 3);
+
+    // the next message is signed with a fresh key
+    engine.recipe(BlockRecipe::Info(b"ohai".to_vec())).unwrap();
+    assert_eq!(engine.session_blocks(), 4);
+}
+
+#[test]
+fn engine_rekey_policy_bytes() {
+    let (pk, sk) = crypto::gen_keypair();
+    let ring = SignRing::new(pk, sk);
+    let storage = MemoryStorage::new().into_engine();
+    let policy = RekeyPolicy {
+        infos: None,
+        seconds: None,
+        bytes: Some(100),
+    };
+    let mut engine = Engine::start_with_policy(storage, ring, Metrics::new(), policy).unwrap();
+    // "rekey policy: 100 bytes" is signed with the key of the init block
+    assert_eq!(engine.session_blocks(), 2);
+
+    engine.recipe(BlockRecipe::Info(vec![b'a'; 50])).unwrap();
+    assert_eq!(engine.session_blocks(), 3);
+    // the key reached the limit with this block
+    engine.recipe(BlockRecipe::Info(vec![b'a'; 27])).unwrap();
+    assert_eq!(engine.session_blocks(), 5);
+    engine.recipe(BlockRecipe::Info(vec![b'a'; 99])).unwrap();
+    assert_eq!(engine.session_blocks(), 6);
+}
+
+#[test]
+fn engine_rekey_policy_seconds() {
+    let (pk, sk) = crypto::gen_keypair();
+    let ring = SignRing::new(pk, sk);
+    let storage = MemoryStorage::new().into_engine();
+    let policy = RekeyPolicy {
+        infos: None,
+        seconds: Some(1),
+        bytes: None,
+    };
+    let mut engine = Engine::start_with_policy(storage, ring, Metrics::new(), policy).unwrap();
+    assert_eq!(engine.session_blocks(), 2);
+    assert!(engine.rekey_if_expired().unwrap().is_none());
+
+    // the key signed the announcement, it's replaced once it's too old
+    thread::sleep(Duration::from_secs(1));
+    let rekey = engine.rekey_if_expired().unwrap().unwrap();
+    assert_eq!(engine.head(), &rekey.sha3());
+    assert_eq!(engine.session_blocks(), 3);
+
+    // a key that didn't sign anything doesn't expire
+    thread::sleep(Duration::from_secs(1));
+    assert!(engine.rekey_if_expired().unwrap().is_none());
+
+    // the key is already too old when the message is written
+    engine.recipe(BlockRecipe::Info(b"ohai".to_vec())).unwrap();
+    assert_eq!(engine.session_blocks(), 5);
+    engine.recipe(BlockRecipe::Info(b"ohai".to_vec())).unwrap();
+    assert_eq!(engine.session_blocks(), 6);
+    assert!(engine.rekey_if_expired().unwrap().is_none());
+}
+
+#[test]
+fn engine_default_rekey_policy() {
+    let (pk, sk) = crypto::gen_keypair();
+    let ring = SignRing::new(pk, sk);
+    let storage = MemoryStorage::new().into_engine();
+    let mut engine = Engine::start(storage, ring).unwrap();
+    assert_eq!(engine.session_blocks(), 1);
+
+    engine.recipe(BlockRecipe::Info(b"ohai".to_vec())).unwrap();
+    assert_eq!(engine.session_blocks(), 3);
+}
+
+#[test]
+fn engine_transaction() {
+    let (pk, sk) = crypto::gen_keypair();
+    let ring = SignRing::new(pk, sk);
+    let storage = MemoryStorage::new().into_engine();
+    let mut engine = Engine::start(storage, ring).unwrap();
+
+    let parts = vec![b"sudo id\n".to_vec(), b"uid=0(root)\n".to_vec()];
+    engine.recipe(BlockRecipe::Transaction(parts)).unwrap();
+    // init, each part is followed by a rekey
+    assert_eq!(engine.session_blocks(), 5);
+
+    let storage = engine.storage();
+    let rekey = storage.get(&storage.get_head().unwrap()).unwrap();
+    let last = storage.get(rekey.prev()).unwrap();
+    assert_eq!(last.msg(), Some(&b"uid=0(root)\n".to_vec()));
+    assert_eq!(last.transaction(), Some(&TxPart { index: 1, count: 2 }));
+
+    // nothing is written if one of the parts is invalid
+    let parts = vec![b"ohai\n".to_vec(), vec![0; 65536]];
+    assert!(engine.recipe(BlockRecipe::Transaction(parts)).is_err());
+    assert!(engine.recipe(BlockRecipe::Transaction(Vec::new())).is_err());
+    assert_eq!(engine.session_blocks(), 5);
+}
+
+#[test]
+fn engine_aborts_transaction() {
+    let (pk, sk) = crypto::gen_keypair();
+    let ring = SignRing::new(pk, sk);
+    let storage = MockStorage::new();
+    let write_bytes = storage.write_bytes.clone();
+    let mut engine = Engine::start(storage.into_engine(), ring).unwrap();
+    let init = engine.head().clone();
+
+    // the storage fails to write the block of the second part
+    write_bytes.use_closure(Box::new(|(_, bytes): (BlockPointer, Vec<u8>)| {
+        if bytes.windows(12).any(|x| x == b"uid=0(root)\n") {
+            Err(ClonableError)
+        } else {
+            Ok(())
+        }
+    }));
+
+    let parts = vec![b"sudo id\n".to_vec(), b"uid=0(root)\n".to_vec()];
+    assert!(engine.recipe(BlockRecipe::Transaction(parts)).is_err());
+
+    // init, the first part and its rekey, the failed part, then the alert
+    let blocks = write_bytes.calls().into_iter()
+        .map(|(_, bytes)| wire::block(&bytes).unwrap().1)
+        .collect::<Vec<_>>();
+    assert_eq!(blocks.len(), 5);
+    assert_eq!(blocks[1].prev(), &init);
+    assert_eq!(blocks[1].transaction(), Some(&TxPart { index: 0, count: 2 }));
+    assert_eq!(blocks[3].transaction(), Some(&TxPart { index: 1, count: 2 }));
+
+    // the alert follows the last block that has been written
+    let alert = &blocks[4];
+    assert_eq!(alert.prev(), &blocks[2].sha3());
+    assert_eq!(engine.head(), &alert.sha3());
+    let prefix = format!("{} after 1 of 2 parts: ", blocks::TX_ABORTED);
+    assert!(alert.msg().unwrap().starts_with(prefix.as_bytes()));
+
+    let mut tracker = TxTracker::default();
+    assert!(tracker.info(blocks[1].transaction()));
+    assert!(tracker.alert(alert.msg().unwrap()));
+    assert!(!tracker.is_open());
+
+    // nothing has been written yet if the first part fails
+    write_bytes.reset_calls();
+    let parts = vec![b"uid=0(root)\n".to_vec(), b"sudo id\n".to_vec()];
+    assert!(engine.recipe(BlockRecipe::Transaction(parts)).is_err());
+    assert_eq!(write_bytes.num_calls(), 1);
+    assert_eq!(engine.head(), &alert.sha3());
+}
+
+#[test]
+fn tx_tracker() {
+    let part = |index, count| TxPart { index, count };
+
+    let mut tracker = TxTracker::default();
+    assert!(tracker.info(None));
+    assert!(tracker.info(Some(&part(0, 2))));
+    assert!(tracker.is_open());
+    assert!(tracker.info(Some(&part(1, 2))));
+    assert!(!tracker.is_open());
+
+    // interleaved with a regular message
+    assert!(tracker.info(Some(&part(0, 2))));
+    assert!(!tracker.info(None));
+
+    // part of a transaction that never started
+    assert!(!tracker.info(Some(&part(1, 2))));
+
+    // the daemon restarted in the middle of a transaction
+    assert!(tracker.info(Some(&part(0, 3))));
+    assert!(!tracker.init());
+    assert!(tracker.init());
+
+    // the range starts in the middle of a transaction
+    let mut tracker = TxTracker::default();
+    assert!(tracker.info(Some(&part(2, 3))));
+    assert!(!tracker.is_open());
+
+    // the daemon failed to write the rest of the transaction
+    assert!(tracker.info(Some(&part(0, 3))));
+    assert!(!tracker.alert(b"tr1pd: line of 65537 bytes has been split into 2 blocks"));
+    assert!(tracker.is_open());
+    assert!(tracker.alert(format!("{} after 1 of 3 parts", blocks::TX_ABORTED).as_bytes()));
+    assert!(!tracker.is_open());
+    assert!(tracker.info(None));
+
+    // the index doesn't overflow
+    let mut tracker = TxTracker::default();
+    assert!(!tracker.info(Some(&part(u16::max_value(), 3))));
+    assert!(!tracker.is_open());
+    assert!(part(u16::max_value(), u16::max_value()).is_last());
+    assert!(part(0, 0).is_last());
+    assert_eq!(part(u16::max_value(), u16::max_value()).to_string(), "65536/65535");
+}
+
+#[test]
+fn engine_split_line() {
+    let (pk, sk) = crypto::gen_keypair();
+    let ring = SignRing::new(pk, sk);
+    let storage = MemoryStorage::new().into_engine();
+    let mut engine = Engine::start(storage, ring).unwrap();
+
+    let parts = vec![vec![b'a'; 65535], b"a\n".to_vec()];
+    engine.recipe(BlockRecipe::SplitLine(parts)).unwrap();
+    // init, each part is followed by a rekey, then the alert
+    assert_eq!(engine.session_blocks(), 6);
+
+    let storage = engine.storage();
+    let alert = storage.get(&storage.get_head().unwrap()).unwrap();
+    assert_eq!(alert.msg(), Some(&b"tr1pd: line of 65537 bytes has been split into 2 blocks".to_vec()));
+
+    let rekey = storage.get(alert.prev()).unwrap();
+    let last = storage.get(rekey.prev()).unwrap();
+    assert_eq!(last.split(), Some(&TxPart { index: 1, count: 2 }));
+    assert_eq!(last.transaction(), None);
+}
+
+#[test]
+fn engine_replay() {
+    let (pk, sk) = crypto::gen_keypair();
+    let ring = SignRing::new(pk, sk);
+    let storage = MemoryStorage::new().into_engine();
+    let mut engine = Engine::start(storage, ring).unwrap();
+
+    let entries = vec![
+        SpoolEntry { seq: 7, time: 1514764800, msg: b"ohai\n".to_vec() },
+        SpoolEntry { seq: 8, time: 1514764860, msg: b"wat\n".to_vec() },
+    ];
+    engine.recipe(BlockRecipe::Replay(entries)).unwrap();
+    // init, each message is followed by a rekey, then the alert
+    assert_eq!(engine.session_blocks(), 6);
+
+    let storage = engine.storage();
+    let alert = storage.get(&storage.get_head().unwrap()).unwrap();
+    assert_eq!(alert.msg(), Some(&b"tr1pd: replayed 2 spooled messages (#7 to #8), spooled between 1514764800 and 1514764860".to_vec()));
+
+    let rekey = storage.get(alert.prev()).unwrap();
+    let last = storage.get(rekey.prev()).unwrap();
+    assert_eq!(last.spooled(), Some(&Spooled { seq: 8, time: 1514764860 }));
+
+    let out_of_order = vec![
+        SpoolEntry { seq: 2, time: 0, msg: b"ohai\n".to_vec() },
+        SpoolEntry { seq: 1, time: 0, msg: b"wat\n".to_vec() },
+    ];
+    assert!(engine.recipe(BlockRecipe::Replay(out_of_order)).is_err());
+    assert!(engine.recipe(BlockRecipe::Replay(Vec::new())).is_err());
+}
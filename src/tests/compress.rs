@@ -0,0 +1,97 @@
+use blocks::{Codec, Compressed};
+use compress::{self, Compressor};
+use config::RuleConfig;
+use crypto::{self, SignRing};
+use engine::Engine;
+use recipe::BlockRecipe;
+use rules::Rules;
+use storage::{MemoryStorage, BlockStorage};
+
+use std::sync::Arc;
+
+
+fn repetitive(len: usize) -> Vec<u8> {
+    b"Oct 19 00:00:00 web01 sshd[1337]: Accepted publickey for root\n".iter()
+        .cycle()
+        .take(len)
+        .cloned()
+        .collect()
+}
+
+#[test]
+fn compress_payloads() {
+    let compressor = Compressor::new(64);
+    assert_eq!(compressor.compress(b"ohai\n").unwrap(), None);
+
+    let msg = repetitive(4096);
+    let (compressed, bytes) = compressor.compress(&msg).unwrap().unwrap();
+    assert_eq!(compressed, Compressed {
+        codec: Codec::Deflate,
+        len: 4096,
+    });
+    assert!(bytes.len() < msg.len());
+    assert_eq!(compress::decompress(&compressed, &bytes).unwrap(), msg);
+
+    // the length has to match
+    let mut wrong = compressed.clone();
+    wrong.len -= 1;
+    assert!(compress::decompress(&wrong, &bytes).is_err());
+    wrong.len = 65536;
+    assert!(compress::decompress(&wrong, &bytes).is_err());
+
+    let unknown = Compressed {
+        codec: Codec::Unknown(0x7f),
+        len: 4096,
+    };
+    assert!(compress::decompress(&unknown, &bytes).is_err());
+
+    // payloads that don't get smaller are stored as they are
+    let mut x: u32 = 1;
+    let noise: Vec<u8> = (0..1024).map(|_| {
+        x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (x >> 16) as u8
+    }).collect();
+    assert_eq!(compressor.compress(&noise).unwrap(), None);
+}
+
+#[test]
+fn engine_compresses_payloads() {
+    let (pk, sk) = crypto::gen_keypair();
+    let storage = MemoryStorage::new().into_engine();
+    let mut engine = Engine::start(storage, SignRing::new(pk, sk)).unwrap();
+    engine.set_compressor(Some(Compressor::new(512)));
+    engine.set_redactable(true);
+
+    // the rules still see the uncompressed message
+    let rules = Rules::from_config(&[RuleConfig {
+        name: "root login".into(),
+        contains: Some("Accepted publickey for root".into()),
+        regex: None,
+        hook: None,
+    }]).unwrap();
+    engine.set_rules(Arc::new(rules), None);
+
+    let msg = repetitive(8192);
+    engine.recipe(BlockRecipe::Info(msg.clone())).unwrap();
+
+    let storage = engine.storage();
+    let alert = storage.get(&storage.get_head().unwrap()).unwrap();
+    assert!(alert.compressed().is_none());
+    assert!(alert.msg().unwrap().starts_with(b"rule \"root login\" matched "));
+
+    // the commitment covers the compressed bytes
+    let info = storage.get(alert.prev()).unwrap();
+    info.verify_longterm(&pk).unwrap();
+    let compressed = info.compressed().unwrap();
+    let bytes = storage.get_msg(&info).unwrap().unwrap();
+    assert!(bytes.len() < msg.len());
+    assert_eq!(compress::decompress(compressed, &bytes).unwrap(), msg);
+
+    // messages below the threshold aren't compressed
+    engine.recipe(BlockRecipe::Info(b"ohai\n".to_vec())).unwrap();
+    let storage = engine.storage();
+    let rekey = storage.get(&storage.get_head().unwrap()).unwrap();
+    let info = storage.get(rekey.prev()).unwrap();
+    assert!(info.compressed().is_none());
+    assert_eq!(storage.get_msg(&info).unwrap(), Some(b"ohai\n".to_vec()));
+}
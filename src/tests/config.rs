@@ -1,4 +1,9 @@
-use config::{Config, DaemonConfig, SecurityConfig};
+use log::LevelFilter;
+
+use blocks::RekeyPolicy;
+use config::{Config, DaemonConfig, LedgerConfig, RateLimitConfig, RuleConfig, SecurityConfig, SyslogConfig};
+
+use std::collections::BTreeMap;
 
 
 #[test]
@@ -21,10 +26,70 @@ fn parse_config() {
     pub_key = "/etc/tr1pd/pub.key"
     sec_key = "/etc/tr1pd/sec.key"
 
+    curve_pub_key = "/etc/tr1pd/curve.pk"
+    curve_sec_key = "/etc/tr1pd/curve.sk"
+    curve_clients = [
+        "Yne@$w-vo<fVvi]a<NY6T1ed:M$fCG*[IaLV{hID",
+    ]
+
+    metrics = "127.0.0.1:9163"
+    dedup_window = 4096
+    auditors = ["/etc/tr1pd/auditor.pk"]
+    redactable = true
+    compress_threshold = 512
+
+    log_level = "info"
+    socket_mode = "0770"
+
+    [daemon.rekey]
+    infos = 100
+    seconds = 60
+
+    [daemon.rate_limit]
+    messages_per_second = 100
+    bytes_per_second = 1048576
+    max_message_size = 4096
+
     [security]
     strict_chroot = true
+
+    [syslog]
+    unix = "/run/tr1pd/syslog.sock"
+    udp = "127.0.0.1:514"
+
+    [[rules]]
+    name = "root login"
+    contains = "Accepted publickey for root"
+    hook = ["/usr/local/bin/notify", "--urgent"]
+
+    [ledgers.auth]
+    pub_key = "/etc/tr1pd/auth.pk"
+    sec_key = "/etc/tr1pd/auth.sk"
+
+    [ledgers.web]
+    auditors = []
+    redactable = false
+
+    [sensors]
+    web01 = "/etc/tr1pd/sensors/web01.pk"
     "#;
 
+    let mut sensors = BTreeMap::new();
+    sensors.insert("web01".to_string(), "/etc/tr1pd/sensors/web01.pk".to_string());
+
+    let mut ledgers = BTreeMap::new();
+    ledgers.insert("auth".to_string(), LedgerConfig {
+        pub_key: Some("/etc/tr1pd/auth.pk".into()),
+        sec_key: Some("/etc/tr1pd/auth.sk".into()),
+        auditors: None,
+        redactable: None,
+    });
+    ledgers.insert("web".to_string(), LedgerConfig {
+        auditors: Some(Vec::new()),
+        redactable: Some(false),
+        ..LedgerConfig::default()
+    });
+
     let config = Config::parse(&data).unwrap();
     assert_eq!(config, Config {
         daemon: DaemonConfig {
@@ -33,9 +98,139 @@ fn parse_config() {
 
             pub_key: Some("/etc/tr1pd/pub.key".into()),
             sec_key: Some("/etc/tr1pd/sec.key".into()),
+
+            curve_pub_key: Some("/etc/tr1pd/curve.pk".into()),
+            curve_sec_key: Some("/etc/tr1pd/curve.sk".into()),
+            curve_clients: vec![
+                "Yne@$w-vo<fVvi]a<NY6T1ed:M$fCG*[IaLV{hID".into(),
+            ],
+
+            metrics: Some("127.0.0.1:9163".into()),
+
+            rate_limit: RateLimitConfig {
+                messages_per_second: Some(100),
+                messages_burst: None,
+                bytes_per_second: Some(1048576),
+                bytes_burst: None,
+                max_message_size: Some(4096),
+            },
+            rekey: RekeyPolicy {
+                infos: Some(100),
+                seconds: Some(60),
+                bytes: None,
+            },
+            dedup_window: Some(4096),
+            auditors: vec!["/etc/tr1pd/auditor.pk".into()],
+            redactable: true,
+            compress_threshold: Some(512),
+            hook_user: None,
+
+            log_level: Some("info".into()),
+            socket_mode: Some("0770".into()),
         },
         security: SecurityConfig {
             strict_chroot: true,
         },
+        syslog: SyslogConfig {
+            unix: Some("/run/tr1pd/syslog.sock".into()),
+            udp: Some("127.0.0.1:514".into()),
+            tcp: None,
+        },
+        rules: vec![
+            RuleConfig {
+                name: "root login".into(),
+                contains: Some("Accepted publickey for root".into()),
+                regex: None,
+                hook: Some(vec!["/usr/local/bin/notify".into(), "--urgent".into()]),
+            },
+        ],
+        ledgers,
+        sensors,
     });
+
+    assert_eq!(config.ledger_auditors(None), &["/etc/tr1pd/auditor.pk".to_string()][..]);
+    assert_eq!(config.ledger_auditors(Some("auth")), &["/etc/tr1pd/auditor.pk".to_string()][..]);
+    assert!(config.ledger_auditors(Some("web")).is_empty());
+    assert!(config.ledger_redactable(None));
+    assert!(config.ledger_redactable(Some("auth")));
+    assert!(!config.ledger_redactable(Some("web")));
+}
+
+#[test]
+fn ledger_keys() {
+    let config = Config::parse(r#"
+    [ledgers.auth]
+    pub_key = "/etc/tr1pd/auth.pk"
+    sec_key = "/etc/tr1pd/auth.sk"
+
+    [ledgers.web]
+
+    [ledgers.broken]
+    pub_key = "/etc/tr1pd/broken.pk"
+    "#).unwrap();
+
+    assert_eq!(config.ledger_keys(None).unwrap(), ("/etc/tr1pd/lt.pk", "/etc/tr1pd/lt.sk"));
+    assert_eq!(config.ledger_keys(Some("auth")).unwrap(), ("/etc/tr1pd/auth.pk", "/etc/tr1pd/auth.sk"));
+    assert_eq!(config.ledger_keys(Some("web")).unwrap(), ("/etc/tr1pd/lt.pk", "/etc/tr1pd/lt.sk"));
+    assert_eq!(config.ledger_keys(Some("unknown")).unwrap(), ("/etc/tr1pd/lt.pk", "/etc/tr1pd/lt.sk"));
+    assert!(config.ledger_keys(Some("broken")).is_err());
+}
+
+#[test]
+fn config_fingerprint() {
+    let config = Config::parse(r#"
+    [daemon]
+    socket = "unix:///run/tr1pd/tr1pd.sock"
+    "#).unwrap();
+
+    let fingerprint = config.fingerprint();
+    assert_eq!(fingerprint.len(), 64);
+    assert_eq!(fingerprint, Config::parse(r#"
+    [daemon]
+    socket   =   "unix:///run/tr1pd/tr1pd.sock"
+    "#).unwrap().fingerprint());
+
+    // plain values after tables need to be reordered
+    let config = Config::parse(r#"
+    [daemon.rate_limit]
+    messages_per_second = 100
+
+    [daemon]
+    log_level = "info"
+    "#).unwrap();
+    assert_eq!(config.fingerprint().len(), 64);
+}
+
+#[test]
+fn reloadable_settings() {
+    let config = Config::parse(r#"
+    [daemon]
+    log_level = "debug"
+    socket_mode = "0660"
+    "#).unwrap();
+
+    assert_eq!(config.log_level().unwrap(), Some(LevelFilter::Debug));
+    assert_eq!(config.socket_mode().unwrap(), Some(0o660));
+
+    let config = Config::default();
+    assert_eq!(config.log_level().unwrap(), None);
+    assert_eq!(config.socket_mode().unwrap(), None);
+}
+
+#[test]
+fn invalid_reloadable_settings() {
+    let config = Config::parse(r#"
+    [daemon]
+    log_level = "verbose"
+    socket_mode = "0999"
+    "#).unwrap();
+
+    assert!(config.log_level().is_err());
+    assert!(config.socket_mode().is_err());
+
+    let config = Config::parse(r#"
+    [daemon]
+    socket_mode = "10000"
+    "#).unwrap();
+    assert!(config.socket_mode().is_err());
 }
@@ -0,0 +1,62 @@
+use blocks::BlockPointer;
+use dedup::{DedupWindow, Lookup};
+use recipe::BlockRecipe;
+use rpc::{ClientId, CtlResponse};
+
+
+fn info(msg: &[u8]) -> Vec<BlockRecipe> {
+    vec![BlockRecipe::Info(msg.to_vec())]
+}
+
+fn ack(b: u8) -> CtlResponse {
+    CtlResponse::Ack(BlockPointer([b; 32]))
+}
+
+#[test]
+fn dedup_retries() {
+    let mut window = DedupWindow::new(16);
+    let client = ClientId::Uid(1000);
+
+    assert_eq!(window.check(&client, None, "a1", &info(b"ohai\n")), Lookup::New);
+    window.insert(&client, None, "a1", &info(b"ohai\n"), ack(0x01));
+
+    assert_eq!(window.check(&client, None, "a1", &info(b"ohai\n")), Lookup::Duplicate(ack(0x01)));
+    assert_eq!(window.check(&client, None, "a1", &info(b"wat\n")), Lookup::Conflict);
+
+    // request ids are scoped to a ledger and a client
+    assert_eq!(window.check(&client, Some("auth"), "a1", &info(b"ohai\n")), Lookup::New);
+    assert_eq!(window.check(&ClientId::Uid(1001), None, "a1", &info(b"ohai\n")), Lookup::New);
+}
+
+#[test]
+fn dedup_batches() {
+    let mut window = DedupWindow::new(16);
+    let client = ClientId::Uid(1000);
+    let batch = vec![
+        BlockRecipe::Info(b"ohai\n".to_vec()),
+        BlockRecipe::Info(b"wat\n".to_vec()),
+    ];
+    let reply = CtlResponse::AckBatch(vec![BlockPointer([0x01; 32]), BlockPointer([0x02; 32])]);
+
+    window.insert(&client, None, "b1", &batch, reply.clone());
+    assert_eq!(window.check(&client, None, "b1", &batch), Lookup::Duplicate(reply));
+    assert_eq!(window.check(&client, None, "b1", &batch[..1]), Lookup::Conflict);
+}
+
+#[test]
+fn dedup_window_is_bounded() {
+    let mut window = DedupWindow::new(2);
+    let client = ClientId::Uid(1000);
+
+    for (i, id) in ["a1", "a2", "a3"].iter().enumerate() {
+        window.insert(&client, None, id, &info(b"ohai\n"), ack(i as u8));
+    }
+    assert_eq!(window.len(), 2);
+
+    assert_eq!(window.check(&client, None, "a1", &info(b"ohai\n")), Lookup::New);
+    assert_eq!(window.check(&client, None, "a3", &info(b"ohai\n")), Lookup::Duplicate(ack(0x02)));
+
+    let mut disabled = DedupWindow::new(0);
+    disabled.insert(&client, None, "a1", &info(b"ohai\n"), ack(0x01));
+    assert!(disabled.is_empty());
+}
@@ -0,0 +1,137 @@
+use compress::Compressor;
+use config::RuleConfig;
+use crypto::{self, SignRing};
+use engine::Engine;
+use envelope::{self, AuditorKey, Sealer};
+use recipe::{self, BlockRecipe};
+use rules::Rules;
+use storage::{MemoryStorage, BlockStorage};
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::process;
+use std::sync::Arc;
+
+
+fn auditor(name: &str) -> (envelope::PublicKey, AuditorKey) {
+    let (pk, sk) = envelope::gen_keypair();
+
+    let path = env::temp_dir().join(format!("tr1pd-test-{}-{}.sk", name, process::id()));
+    File::create(&path).unwrap().write_all(&sk.0).unwrap();
+    let key = AuditorKey::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(key.key_id(), envelope::key_id(&pk));
+    (pk, key)
+}
+
+#[test]
+fn seal_for_auditors() {
+    let (pk1, key1) = auditor("alice");
+    let (pk2, key2) = auditor("bob");
+    let (_, outsider) = auditor("mallory");
+
+    let sealer = Sealer::new(vec![pk1, pk2]).unwrap();
+    let (envelope, ciphertext) = sealer.seal(b"uid=1000(alice) logged in\n").unwrap();
+    assert_eq!(envelope.recipients.len(), 2);
+    assert_ne!(&ciphertext[..], &b"uid=1000(alice) logged in\n"[..]);
+
+    assert_eq!(key1.open(&envelope, &ciphertext).unwrap(), Some(b"uid=1000(alice) logged in\n".to_vec()));
+    assert_eq!(key2.open(&envelope, &ciphertext).unwrap(), Some(b"uid=1000(alice) logged in\n".to_vec()));
+    assert_eq!(outsider.open(&envelope, &ciphertext).unwrap(), None);
+
+    let mut tampered = ciphertext.clone();
+    tampered[0] ^= 0x01;
+    assert!(key1.open(&envelope, &tampered).is_err());
+
+    assert!(Sealer::new(Vec::new()).is_err());
+    assert!(sealer.seal(&[0; 65535 - envelope::OVERHEAD]).is_ok());
+    assert!(sealer.seal(&[0; 65535 - envelope::OVERHEAD + 1]).is_err());
+}
+
+#[test]
+fn engine_seals_payloads() {
+    let (pk, key) = auditor("engine");
+
+    let (daemon_pk, daemon_sk) = crypto::gen_keypair();
+    let storage = MemoryStorage::new().into_engine();
+    let mut engine = Engine::start(storage, SignRing::new(daemon_pk, daemon_sk)).unwrap();
+    engine.set_sealer(Some(Sealer::new(vec![pk]).unwrap()));
+
+    // the rules still see the plaintext
+    let rules = Rules::from_config(&[RuleConfig {
+        name: "root login".into(),
+        contains: Some("Accepted publickey for root".into()),
+        regex: None,
+        hook: None,
+    }]).unwrap();
+    engine.set_rules(Arc::new(rules), None);
+
+    engine.recipe(BlockRecipe::Info(b"Accepted publickey for root\n".to_vec())).unwrap();
+
+    let storage = engine.storage();
+    let alert = storage.get(&storage.get_head().unwrap()).unwrap();
+    assert!(alert.envelope().is_none());
+    assert!(alert.msg().unwrap().starts_with(b"rule \"root login\" matched "));
+
+    let info = storage.get(alert.prev()).unwrap();
+    info.verify_longterm(&daemon_pk).unwrap();
+    let envelope = info.envelope().unwrap();
+    assert_eq!(key.open(envelope, info.msg().unwrap()).unwrap(), Some(b"Accepted publickey for root\n".to_vec()));
+
+    // parts are validated before the first one is written
+    let head = storage.get_head().unwrap();
+    let parts = vec![b"ohai\n".to_vec(), vec![0; 65535]];
+    assert!(engine.recipe(BlockRecipe::Transaction(parts)).is_err());
+    assert_eq!(engine.storage().get_head().unwrap(), head);
+}
+
+#[test]
+fn engine_doesnt_compress_sealed_payloads() {
+    let (pk, key) = auditor("compress");
+
+    let (daemon_pk, daemon_sk) = crypto::gen_keypair();
+    let storage = MemoryStorage::new().into_engine();
+    let mut engine = Engine::start(storage, SignRing::new(daemon_pk, daemon_sk)).unwrap();
+    engine.set_compressor(Some(Compressor::new(16)));
+    engine.set_sealer(Some(Sealer::new(vec![pk]).unwrap()));
+
+    let msg = b"ohai ohai ohai ohai ohai ohai ohai ohai\n".to_vec();
+    engine.recipe(BlockRecipe::Info(msg.clone())).unwrap();
+
+    let storage = engine.storage();
+    let rekey = storage.get(&storage.get_head().unwrap()).unwrap();
+    let info = storage.get(rekey.prev()).unwrap();
+    assert!(info.compressed().is_none());
+    let envelope = info.envelope().unwrap();
+    assert_eq!(key.open(envelope, info.msg().unwrap()).unwrap(), Some(msg));
+}
+
+#[test]
+fn engine_seals_full_line_parts() {
+    let (pk, key) = auditor("parts");
+
+    let (daemon_pk, daemon_sk) = crypto::gen_keypair();
+    let storage = MemoryStorage::new().into_engine();
+    let mut engine = Engine::start(storage, SignRing::new(daemon_pk, daemon_sk)).unwrap();
+    engine.set_sealer(Some(Sealer::new(vec![pk]).unwrap()));
+
+    // the client leaves room for the envelope
+    let parts = vec![vec![b'a'; recipe::MAX_LINE_PART], b"\n".to_vec()];
+    engine.recipe(BlockRecipe::SplitLine(parts.clone())).unwrap();
+
+    // the parts are separated by rekey blocks
+    let storage = engine.storage();
+    let mut pointer = storage.get_head().unwrap();
+    let mut msgs = Vec::new();
+    while msgs.len() < 2 {
+        let block = storage.get(&pointer).unwrap();
+        if let Some(envelope) = block.envelope() {
+            msgs.push(key.open(envelope, block.msg().unwrap()).unwrap().unwrap());
+        }
+        pointer = block.prev().clone();
+    }
+    msgs.reverse();
+    assert_eq!(msgs, parts);
+}
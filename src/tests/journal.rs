@@ -0,0 +1,177 @@
+use journal::{self, Entry, Cursor, CursorFile};
+use recipe;
+
+use std::env;
+use std::fs;
+use std::iter;
+use std::process;
+
+
+const CURSOR_1: &str = "s=739ad463348b4ceca5a9e69c95a3c93f;i=4ece7;b=6c7c6013a8834f0b9c1b0b9d3a1b9b3e;m=5d7ebac9;t=56a9e0c9bc4e8;x=e8d6f2a7cb1b8c04";
+const CURSOR_2: &str = "s=739ad463348b4ceca5a9e69c95a3c93f;i=4ece8;b=6c7c6013a8834f0b9c1b0b9d3a1b9b3e;m=5d7ebaf1;t=56a9e0c9bc510;x=36e2e2b3ff9c6e9a";
+
+fn entry(cursor: &str, msg: &str) -> Vec<u8> {
+    format!("__CURSOR={}\n\
+             __REALTIME_TIMESTAMP=1523356004689128\n\
+             _BOOT_ID=6c7c6013a8834f0b9c1b0b9d3a1b9b3e\n\
+             _PID=1042\n\
+             _UID=0\n\
+             _SYSTEMD_UNIT=sshd.service\n\
+             MESSAGE={}\n\
+             \n", cursor, msg).into_bytes()
+}
+
+#[test]
+fn read_entries() {
+    let mut input = entry(CURSOR_1, "Accepted publickey for root");
+    input.extend(entry(CURSOR_2, "pam_unix(sshd:session): session opened"));
+
+    let mut stream = &input[..];
+    let first = journal::read_entry(&mut stream).unwrap().unwrap();
+    let second = journal::read_entry(&mut stream).unwrap().unwrap();
+    assert_eq!(journal::read_entry(&mut stream).unwrap(), None);
+
+    assert_eq!(first.cursor(), Some(CURSOR_1));
+    assert_eq!(first.get("_SYSTEMD_UNIT"), Some(&b"sshd.service"[..]));
+    assert_eq!(first.get("MESSAGE"), Some(&b"Accepted publickey for root"[..]));
+    // not in the list of fields that are kept
+    assert_eq!(first.get("_BOOT_ID"), None);
+    assert_eq!(first.fields.len(), 6);
+
+    assert_eq!(second.cursor(), Some(CURSOR_2));
+}
+
+#[test]
+fn read_binary_field() {
+    let mut input = b"_PID=1\nMESSAGE\n".to_vec();
+    input.extend(&[0x0b, 0, 0, 0, 0, 0, 0, 0]);
+    input.extend(b"hello\nworld\n\n");
+
+    let entry = journal::read_entry(&mut &input[..]).unwrap().unwrap();
+    assert_eq!(entry.get("MESSAGE"), Some(&b"hello\nworld"[..]));
+    assert_eq!(entry.get("_PID"), Some(&b"1"[..]));
+}
+
+#[test]
+fn read_truncated_binary_field() {
+    let mut input = b"MESSAGE\n".to_vec();
+    input.extend(&[0x0b, 0, 0, 0, 0, 0, 0, 0]);
+    input.extend(b"hello");
+
+    assert!(journal::read_entry(&mut &input[..]).is_err());
+}
+
+#[test]
+fn read_oversized_field() {
+    let field = b"MESSAGE=".iter()
+        .cloned()
+        .chain(iter::repeat(b'A').take(64 * 1024 * 1024))
+        .collect::<Vec<_>>();
+
+    let err = journal::read_entry(&mut &field[..]).unwrap_err();
+    assert_eq!(err.to_string(), "invalid journal entry: field too large");
+}
+
+#[test]
+fn encode_entry() {
+    let entry = Entry {
+        fields: vec![
+            ("__CURSOR".into(), CURSOR_1.as_bytes().to_vec()),
+            ("MESSAGE".into(), b"hello\nworld".to_vec()),
+        ],
+    };
+
+    let bytes = entry.encode();
+    let mut expected = format!("__CURSOR={}\nMESSAGE\n", CURSOR_1).into_bytes();
+    expected.extend(&[0x0b, 0, 0, 0, 0, 0, 0, 0]);
+    expected.extend(b"hello\nworld\n");
+    assert_eq!(bytes, expected);
+
+    // the encoded entry can be read again
+    assert_eq!(journal::read_entry(&mut &bytes[..]).unwrap(), Some(entry));
+}
+
+#[test]
+fn encode_entry_splits_large_entries() {
+    let entry = Entry {
+        fields: vec![
+            ("_PID".into(), b"1".to_vec()),
+            ("MESSAGE".into(), vec![b'A'; 70_000]),
+        ],
+    };
+
+    let parts = entry.parts();
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].len(), recipe::MAX_LINE_PART);
+    assert_eq!(parts.concat(), entry.encode());
+
+    // nothing has been cut
+    let bytes = parts.concat();
+    assert_eq!(journal::read_entry(&mut &bytes[..]).unwrap(), Some(entry));
+}
+
+#[test]
+fn encode_entry_truncates_message() {
+    let entry = Entry {
+        fields: vec![
+            ("_PID".into(), b"1".to_vec()),
+            ("MESSAGE".into(), vec![b'A'; journal::MAX_ENTRY_SIZE]),
+        ],
+    };
+
+    let bytes = entry.encode();
+    assert!(bytes.len() <= journal::MAX_ENTRY_SIZE);
+
+    // the marker records how much is missing
+    let idx = bytes.windows(17).position(|w| w == b"\nTR1PD_TRUNCATED=").unwrap();
+    let truncated: usize = String::from_utf8_lossy(&bytes[idx + 17..bytes.len() - 1]).parse().unwrap();
+    let msg = journal::read_entry(&mut &bytes[..]).unwrap().unwrap();
+    assert_eq!(msg.get("MESSAGE").unwrap().len() + truncated, journal::MAX_ENTRY_SIZE);
+}
+
+#[test]
+fn encode_entry_truncates_other_fields() {
+    let entry = Entry {
+        fields: vec![
+            ("__CURSOR".into(), CURSOR_1.as_bytes().to_vec()),
+            ("_SYSTEMD_UNIT".into(), vec![b'A'; journal::MAX_ENTRY_SIZE]),
+            ("MESSAGE".into(), b"hello\nworld".to_vec()),
+        ],
+    };
+
+    let bytes = entry.encode();
+    assert!(bytes.len() <= journal::MAX_ENTRY_SIZE);
+
+    // the cursor is kept, the message is gone first
+    let entry = journal::read_entry(&mut &bytes[..]).unwrap().unwrap();
+    assert_eq!(entry.cursor(), Some(CURSOR_1));
+    assert_eq!(entry.get("MESSAGE"), Some(&b""[..]));
+    assert!(entry.get(journal::TRUNCATED_FIELD).is_none());
+    assert!(bytes.windows(16).any(|w| w == b"TR1PD_TRUNCATED="));
+}
+
+#[test]
+fn cursor_order() {
+    let first = Cursor::parse(CURSOR_1).unwrap();
+    let second = Cursor::parse(CURSOR_2).unwrap();
+
+    assert_eq!(first.seqnum, 0x4ece7);
+    assert!(second.is_after(&first));
+    assert!(!first.is_after(&second));
+    assert!(!first.is_after(&first));
+
+    assert_eq!(Cursor::parse("garbage"), None);
+}
+
+#[test]
+fn cursor_file() {
+    let path = env::temp_dir().join(format!("tr1pd-test-{}.cursor", process::id()));
+    let file = CursorFile::new(path.clone());
+
+    assert_eq!(file.load().unwrap(), None);
+    file.store(CURSOR_1).unwrap();
+    file.store(CURSOR_2).unwrap();
+    assert_eq!(file.load().unwrap(), Some(CURSOR_2.to_string()));
+
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,53 @@
+use crypto::{self, SignRing};
+use engine::Engine;
+use ledger::{self, Ledgers};
+use storage::MemoryStorage;
+
+use std::path::PathBuf;
+
+
+fn engine() -> Engine {
+    let (pk, sk) = crypto::gen_keypair();
+    let storage = MemoryStorage::new().into_engine();
+    Engine::start(storage, SignRing::new(pk, sk)).unwrap()
+}
+
+#[test]
+fn ledger_names() {
+    assert!(ledger::validate_name("auth").is_ok());
+    assert!(ledger::validate_name("web-01_access").is_ok());
+    assert!(ledger::validate_name(&"a".repeat(ledger::MAX_NAME_LEN)).is_ok());
+
+    assert!(ledger::validate_name("").is_err());
+    assert!(ledger::validate_name("..").is_err());
+    assert!(ledger::validate_name("a/b").is_err());
+    assert!(ledger::validate_name("auth\0").is_err());
+    assert!(ledger::validate_name(&"a".repeat(ledger::MAX_NAME_LEN + 1)).is_err());
+}
+
+#[test]
+fn ledger_paths() {
+    assert_eq!(ledger::path("/var/lib/tr1pd", None).unwrap(),
+               PathBuf::from("/var/lib/tr1pd"));
+    assert_eq!(ledger::path("/var/lib/tr1pd", Some("auth")).unwrap(),
+               PathBuf::from("/var/lib/tr1pd/ledgers/auth"));
+    assert!(ledger::path("/var/lib/tr1pd", Some("../auth")).is_err());
+}
+
+#[test]
+fn separate_chains() {
+    let mut ledgers = Ledgers::new(engine());
+    ledgers.insert("auth".into(), engine()).unwrap();
+    assert!(ledgers.insert("../auth".into(), engine()).is_err());
+
+    ledgers.get_mut(Some("auth")).unwrap().info(b"ohai\n".to_vec()).unwrap();
+    assert!(ledgers.get_mut(Some("web")).is_none());
+
+    assert_eq!(ledgers.get_mut(None).unwrap().session_blocks(), 1);
+    assert_eq!(ledgers.get_mut(Some("auth")).unwrap().session_blocks(), 2);
+
+    let names: Vec<_> = ledgers.iter_mut()
+        .map(|(name, _)| name.map(|name| name.to_string()))
+        .collect();
+    assert_eq!(names, vec![None, Some("auth".to_string())]);
+}
@@ -0,0 +1,65 @@
+use metrics::{self, Metrics};
+use rpc::NackCode;
+
+use std::time::Duration;
+
+
+#[test]
+fn render_counters() {
+    let metrics = Metrics::default();
+    metrics.init_block();
+    metrics.info_block(12);
+    metrics.info_block(30);
+    metrics.rekey_block();
+    metrics.nack(NackCode::RateLimited);
+    metrics.storage_error();
+
+    let out = metrics.render();
+    assert!(out.contains("# TYPE tr1pd_blocks_written_total counter\n"));
+    assert!(out.contains("tr1pd_blocks_written_total{type=\"init\"} 1\n"));
+    assert!(out.contains("tr1pd_blocks_written_total{type=\"info\"} 2\n"));
+    assert!(out.contains("tr1pd_blocks_written_total{type=\"rekey\"} 1\n"));
+    assert!(out.contains("tr1pd_info_bytes_written_total 42\n"));
+    assert!(out.contains("tr1pd_nacks_total{code=\"rate_limited\"} 1\n"));
+    assert!(out.contains("tr1pd_nacks_total{code=\"too_large\"} 0\n"));
+    assert!(out.contains("tr1pd_storage_errors_total 1\n"));
+    assert!(out.contains("tr1pd_session_age_seconds "));
+}
+
+#[test]
+fn render_without_session() {
+    let out = Metrics::default().render();
+    assert!(!out.contains("tr1pd_session_age_seconds"));
+}
+
+#[test]
+fn render_latency_histogram() {
+    let metrics = Metrics::default();
+    metrics.write_latency(Duration::from_millis(2));
+    metrics.write_latency(Duration::from_millis(20));
+    metrics.write_latency(Duration::from_secs(3));
+
+    let out = metrics.render();
+    assert!(out.contains("tr1pd_write_duration_seconds_bucket{le=\"0.001\"} 0\n"));
+    assert!(out.contains("tr1pd_write_duration_seconds_bucket{le=\"0.0025\"} 1\n"));
+    assert!(out.contains("tr1pd_write_duration_seconds_bucket{le=\"0.025\"} 2\n"));
+    assert!(out.contains("tr1pd_write_duration_seconds_bucket{le=\"1\"} 2\n"));
+    assert!(out.contains("tr1pd_write_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+    assert!(out.contains("tr1pd_write_duration_seconds_sum 3.022\n"));
+    assert!(out.contains("tr1pd_write_duration_seconds_count 3\n"));
+}
+
+#[test]
+fn route_requests() {
+    let metrics = Metrics::default();
+
+    let (status, body) = metrics::route(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n", &metrics);
+    assert_eq!(status, "200 OK");
+    assert_eq!(body, metrics.render());
+
+    let (status, _) = metrics::route(b"GET / HTTP/1.1\r\n\r\n", &metrics);
+    assert_eq!(status, "404 Not Found");
+
+    let (status, _) = metrics::route(b"POST /metrics HTTP/1.1\r\n\r\n", &metrics);
+    assert_eq!(status, "405 Method Not Allowed");
+}
@@ -1,4 +1,4 @@
-use storage::{self, BlockStorage, Result};
+use storage::{self, StorageEngine, BlockStorage, Result};
 use blocks::BlockPointer;
 
 use pseudo::Mock;
@@ -23,6 +23,12 @@ pub struct MockStorage {
     pub get_head: Mock<(), result::Result<BlockPointer, ClonableError>>,
 
     pub update_head: Mock<BlockPointer, result::Result<(), ClonableError>>,
+
+    pub write_payload: Mock<(BlockPointer, Vec<u8>), result::Result<(), ClonableError>>,
+
+    pub get_payload: Mock<BlockPointer, result::Result<Option<Vec<u8>>, ClonableError>>,
+
+    pub remove_payload: Mock<BlockPointer, result::Result<bool, ClonableError>>,
 }
 
 impl MockStorage {
@@ -32,8 +38,16 @@ impl MockStorage {
             get_bytes: Mock::new(Err(ClonableError)),
             get_head: Mock::new(Err(ClonableError)),
             update_head: Mock::new(Ok(())),
+            write_payload: Mock::new(Ok(())),
+            get_payload: Mock::new(Ok(None)),
+            remove_payload: Mock::new(Ok(false)),
         }
     }
+
+    #[inline]
+    pub fn into_engine(self) -> StorageEngine {
+        StorageEngine::Mock(self)
+    }
 }
 
 impl BlockStorage for MockStorage {
@@ -52,4 +66,16 @@ impl BlockStorage for MockStorage {
     fn update_head(&mut self, pointer: &BlockPointer) -> Result<()> {
         self.update_head.call(pointer.clone()).map_err(|x| x.into())
     }
+
+    fn write_payload(&mut self, pointer: &BlockPointer, bytes: Vec<u8>) -> Result<()> {
+        self.write_payload.call((pointer.clone(), bytes)).map_err(|x| x.into())
+    }
+
+    fn get_payload(&self, pointer: &BlockPointer) -> Result<Option<Vec<u8>>> {
+        self.get_payload.call(pointer.clone()).map_err(|x| x.into())
+    }
+
+    fn remove_payload(&mut self, pointer: &BlockPointer) -> Result<bool> {
+        self.remove_payload.call(pointer.clone()).map_err(|x| x.into())
+    }
 }
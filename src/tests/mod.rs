@@ -1,7 +1,23 @@
 mod blocks;
+mod compress;
 mod config;
 mod crypto;
-mod mocks;
+mod dedup;
+mod envelope;
+mod journal;
+mod ledger;
+mod metrics;
+mod ratelimit;
+mod recipe;
+mod redact;
+pub mod mocks;
+mod reload;
+mod rpc;
+mod rules;
+mod sensors;
+mod sequence;
 mod spec;
+mod spool;
 mod storage;
+mod syslog;
 mod wire;
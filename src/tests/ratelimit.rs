@@ -0,0 +1,142 @@
+use config::RateLimitConfig;
+use ratelimit::{RateLimiter, TokenBucket, Verdict, MAX_CLIENTS, QUIET_PERIOD_SECS, SUMMARY_INTERVAL_SECS};
+
+use std::time::{Duration, Instant};
+
+
+fn limiter() -> RateLimiter<u32> {
+    RateLimiter::new(RateLimitConfig {
+        messages_per_second: Some(10),
+        messages_burst: Some(20),
+        bytes_per_second: Some(1000),
+        bytes_burst: None,
+        max_message_size: Some(512),
+    })
+}
+
+#[test]
+fn token_bucket_refill() {
+    let now = Instant::now();
+    let mut bucket = TokenBucket::new(10, 20, now);
+
+    assert!(bucket.has(20, now));
+    bucket.take(20);
+    assert!(!bucket.has(1, now));
+
+    let later = now + Duration::from_millis(500);
+    assert!(bucket.has(5, later));
+    assert!(!bucket.has(6, later));
+
+    // never refills beyond the capacity
+    assert!(bucket.is_full(now + Duration::from_secs(60)));
+}
+
+#[test]
+fn token_bucket_larger_than_capacity() {
+    let now = Instant::now();
+    let mut bucket = TokenBucket::new(10, 20, now);
+
+    // allowed because the bucket is full, but puts it into debt
+    assert!(bucket.has(50, now));
+    bucket.take(50);
+    assert!(!bucket.has(1, now + Duration::from_secs(2)));
+    assert!(bucket.has(1, now + Duration::from_secs(4)));
+}
+
+#[test]
+fn limit_messages() {
+    let now = Instant::now();
+    let mut limiter = limiter();
+
+    for _ in 0..20 {
+        assert_eq!(limiter.check(&1000, &[10], now), Verdict::Allow);
+    }
+    assert_eq!(limiter.check(&1000, &[10], now), Verdict::Throttled);
+
+    // other clients have their own buckets
+    assert_eq!(limiter.check(&1001, &[10], now), Verdict::Allow);
+
+    let later = now + Duration::from_secs(1);
+    assert_eq!(limiter.check(&1000, &[10], later), Verdict::Allow);
+}
+
+#[test]
+fn limit_number_of_clients() {
+    let now = Instant::now();
+    let mut limiter = limiter();
+
+    // none of them are idle, so they can't be forgotten
+    for client in 0..MAX_CLIENTS as u32 {
+        assert_eq!(limiter.check(&client, &[10], now), Verdict::Allow);
+    }
+    assert_eq!(limiter.check(&1000, &[10], now), Verdict::Allow);
+    assert_eq!(limiter.check(&(MAX_CLIENTS as u32), &[10], now), Verdict::Throttled);
+
+    // once their buckets refilled there is room again
+    let later = now + Duration::from_secs(60);
+    assert_eq!(limiter.check(&(MAX_CLIENTS as u32), &[10], later), Verdict::Allow);
+}
+
+#[test]
+fn limit_bytes() {
+    let now = Instant::now();
+    let mut limiter = limiter();
+
+    assert_eq!(limiter.check(&1000, &[500, 500], now), Verdict::Allow);
+    assert_eq!(limiter.check(&1000, &[1], now), Verdict::Throttled);
+}
+
+#[test]
+fn limit_size() {
+    let now = Instant::now();
+    let mut limiter = limiter();
+
+    assert_eq!(limiter.check(&1000, &[10, 513], now), Verdict::TooLarge);
+    // nothing has been taken from the buckets
+    assert_eq!(limiter.check(&1000, &[500, 500], now), Verdict::Allow);
+}
+
+#[test]
+fn summary_after_quiet_period() {
+    let now = Instant::now();
+    let mut limiter = limiter();
+
+    assert_eq!(limiter.check(&1000, &[500, 500], now), Verdict::Allow);
+    assert_eq!(limiter.check(&1000, &[100], now), Verdict::Throttled);
+    // 100 bytes have been refilled
+    let last = now + Duration::from_millis(100);
+    assert_eq!(limiter.check(&1000, &[100, 200], last), Verdict::Throttled);
+
+    // still throttled
+    assert!(limiter.summaries(last + Duration::from_secs(1)).is_empty());
+
+    let summaries = limiter.summaries(last + Duration::from_secs(QUIET_PERIOD_SECS));
+    assert_eq!(summaries.len(), 1);
+
+    let summary = &summaries[0];
+    assert_eq!(summary.client, 1000);
+    assert_eq!(summary.requests, 2);
+    assert_eq!(summary.messages, 3);
+    assert_eq!(summary.bytes, 400);
+    assert!(!summary.ongoing);
+    assert_eq!(summary.to_string(), "rate limit: rejected 2 requests (3 messages, 400 bytes) from 1000 in 0s");
+
+    // only reported once
+    assert!(limiter.summaries(now + Duration::from_secs(60)).is_empty());
+}
+
+#[test]
+fn summary_while_throttled() {
+    let start = Instant::now();
+    let mut limiter = limiter();
+
+    let mut now = start;
+    while now <= start + Duration::from_secs(SUMMARY_INTERVAL_SECS) {
+        limiter.check(&1000, &[512, 512], now);
+        now += Duration::from_millis(100);
+    }
+
+    let summaries = limiter.summaries(now);
+    assert_eq!(summaries.len(), 1);
+    assert!(summaries[0].ongoing);
+}
@@ -0,0 +1,101 @@
+use recipe::{self, read_line, BlockRecipe, Record};
+
+
+fn read_all(mut input: &[u8]) -> Vec<Record> {
+    let mut records = Vec::new();
+    let mut continued = false;
+    while let Some(record) = read_line(&mut input, continued).unwrap() {
+        continued = record.is_unfinished();
+        records.push(record);
+    }
+    records
+}
+
+#[test]
+fn read_lines() {
+    let records = read_all(b"ohai\nwat\r\nno newline");
+    assert_eq!(records, vec![
+        Record::Msg(b"ohai\n".to_vec()),
+        Record::Msg(b"wat\r\n".to_vec()),
+        Record::Msg(b"no newline".to_vec()),
+    ]);
+}
+
+#[test]
+fn read_invalid_utf8() {
+    let records = read_all(b"\xff\xfe\x00\n\xc3\x28\n");
+    assert_eq!(records, vec![
+        Record::Msg(b"\xff\xfe\x00\n".to_vec()),
+        Record::Msg(b"\xc3\x28\n".to_vec()),
+    ]);
+}
+
+#[test]
+fn read_overlong_line() {
+    let mut input = vec![b'a'; recipe::MAX_LINE_PART * 2 + 10];
+    input.push(b'\n');
+    input.extend(b"ohai\n");
+
+    let records = read_all(&input);
+    assert_eq!(records.len(), 2);
+
+    match records[0] {
+        Record::Split(ref parts) => {
+            assert_eq!(parts.len(), 3);
+            assert_eq!(parts[0].len(), recipe::MAX_LINE_PART);
+            assert_eq!(parts[1].len(), recipe::MAX_LINE_PART);
+            assert_eq!(parts[2].len(), 11);
+            assert_eq!(parts.concat(), &input[..input.len() - 5]);
+
+            assert!(BlockRecipe::split_line(parts.clone()).is_ok());
+        },
+        ref record => panic!("line hasn't been split: {:?}", record),
+    }
+    assert_eq!(records[1], Record::Msg(b"ohai\n".to_vec()));
+}
+
+#[test]
+fn read_continued_line() {
+    let mut input = vec![b'a'; recipe::MAX_LINE_PART * recipe::MAX_TRANSACTION_PARTS + 10];
+    input.push(b'\n');
+    input.extend(b"ohai\n");
+
+    let records = read_all(&input);
+    assert_eq!(records.len(), 3);
+
+    match records[0] {
+        Record::Split(ref parts) => assert_eq!(parts.len(), recipe::MAX_TRANSACTION_PARTS),
+        ref record => panic!("line hasn't been split: {:?}", record),
+    }
+    assert!(records[0].is_unfinished());
+    // the rest of the line is flagged, even if it would fit into a message
+    let mut rest = vec![b'a'; 10];
+    rest.push(b'\n');
+    assert_eq!(records[1], Record::Continued(vec![rest]));
+    assert!(!records[1].is_unfinished());
+    assert_eq!(records[2], Record::Msg(b"ohai\n".to_vec()));
+}
+
+#[test]
+fn read_line_at_part_boundary() {
+    // the newline doesn't fit into the first part anymore
+    let mut input = vec![b'a'; recipe::MAX_LINE_PART];
+    input.push(b'\n');
+
+    let records = read_all(&input);
+    assert_eq!(records, vec![
+        Record::Split(vec![vec![b'a'; recipe::MAX_LINE_PART], b"\n".to_vec()]),
+    ]);
+
+    let input = vec![b'a'; recipe::MAX_LINE_PART - 1];
+    let records = read_all(&input);
+    assert_eq!(records, vec![Record::Msg(input)]);
+}
+
+#[test]
+fn batch_size_fits_into_a_request() {
+    assert_eq!(recipe::parse_batch_size("64"), Ok(64));
+    assert_eq!(recipe::parse_batch_size("1024"), Ok(1024));
+    assert!(recipe::parse_batch_size("1025").is_err());
+    assert!(recipe::parse_batch_size("0").is_err());
+}
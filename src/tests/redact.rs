@@ -0,0 +1,79 @@
+use blocks::{BlockPointer, Commitment, SALT_LEN};
+use crypto::{self, SignRing};
+use engine::Engine;
+use recipe::BlockRecipe;
+use storage::{DiskStorage, MemoryStorage, BlockStorage};
+
+use std::env;
+use std::fs;
+use std::process;
+
+
+#[test]
+fn commit_and_open() {
+    let (commitment, stored) = Commitment::commit(b"uid=1000(alice) logged in\n");
+    assert_eq!(stored.len(), SALT_LEN + 26);
+    assert_eq!(commitment.open(&stored).unwrap(), &b"uid=1000(alice) logged in\n"[..]);
+
+    let mut tampered = stored.clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 0x01;
+    assert!(commitment.open(&tampered).is_err());
+    assert!(commitment.open(&stored[..SALT_LEN - 1]).is_err());
+}
+
+#[test]
+fn engine_commits_payloads() {
+    let (pk, sk) = crypto::gen_keypair();
+    let storage = MemoryStorage::new().into_engine();
+    let mut engine = Engine::start(storage, SignRing::new(pk, sk)).unwrap();
+    engine.set_redactable(true);
+
+    engine.recipe(BlockRecipe::Info(b"uid=1000(alice) logged in\n".to_vec())).unwrap();
+
+    // the default policy replaces the key after every info block
+    let rekey = engine.storage().get(engine.head()).unwrap();
+    let pointer = rekey.prev().clone();
+    let info = engine.storage().get(&pointer).unwrap();
+    info.verify_longterm(&pk).unwrap();
+    assert!(info.commitment().is_some());
+    assert!(info.msg().unwrap().is_empty());
+    assert_eq!(engine.storage().get_msg(&info).unwrap(), Some(b"uid=1000(alice) logged in\n".to_vec()));
+
+    let mut storage = MemoryStorage::new();
+    let stored = engine.storage().get_payload(&pointer).unwrap().unwrap();
+    storage.write_payload(&pointer, stored).unwrap();
+
+    // the block stays intact, only the payload is gone
+    assert!(storage.remove_payload(&pointer).unwrap());
+    assert!(!storage.remove_payload(&pointer).unwrap());
+    assert_eq!(storage.get_msg(&info).unwrap(), None);
+    info.verify_longterm(&pk).unwrap();
+
+    storage.write_payload(&pointer, vec![0; SALT_LEN + 26]).unwrap();
+    assert!(storage.get_msg(&info).is_err());
+
+    // messages still need to fit into a block
+    assert!(engine.recipe(BlockRecipe::Info(vec![0; 65536])).is_err());
+}
+
+#[test]
+fn disk_storage_payloads() {
+    let path = env::temp_dir().join(format!("tr1pd-test-redact-{}", process::id()));
+    let mut storage = DiskStorage::new(path.clone());
+
+    let pointer = BlockPointer([0x29; 32]);
+    assert_eq!(storage.get_payload(&pointer).unwrap(), None);
+
+    storage.write_payload(&pointer, b"ohai\n".to_vec()).unwrap();
+    assert!(storage.payload_path(&pointer).starts_with(path.join("payloads")));
+    assert_eq!(storage.get_payload(&pointer).unwrap(), Some(b"ohai\n".to_vec()));
+    // payloads can't be replaced
+    assert!(storage.write_payload(&pointer, b"ohai\n".to_vec()).is_err());
+
+    assert!(storage.remove_payload(&pointer).unwrap());
+    assert_eq!(storage.get_payload(&pointer).unwrap(), None);
+    assert!(!storage.remove_payload(&pointer).unwrap());
+
+    fs::remove_dir_all(&path).unwrap();
+}
@@ -0,0 +1,61 @@
+use libc;
+
+use config::Config;
+use reload::{self, Anchor, Reloader};
+use signals::Signal;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::process;
+
+
+#[test]
+fn socket_path() {
+    assert_eq!(reload::socket_path("unix:///run/tr1pd/tr1pd.sock"), Some("/run/tr1pd/tr1pd.sock"));
+    assert_eq!(reload::socket_path("ipc:///run/tr1pd/tr1pd.sock"), Some("/run/tr1pd/tr1pd.sock"));
+    assert_eq!(reload::socket_path("tcp://127.0.0.1:7123"), None);
+}
+
+#[test]
+fn anchor_follows_replaced_file() {
+    let path = env::temp_dir().join(format!("tr1pd-test-{}.toml", process::id()));
+    File::create(&path).unwrap().write_all(b"[daemon]\n").unwrap();
+
+    let anchor = Anchor::open(&path).unwrap();
+    assert_eq!(anchor.read().unwrap(), b"[daemon]\n");
+
+    // editors usually write a new file and rename it
+    fs::remove_file(&path).unwrap();
+    File::create(&path).unwrap().write_all(b"[security]\n").unwrap();
+    assert_eq!(anchor.read().unwrap(), b"[security]\n");
+    // inside the chroot only the file that was opened at startup is readable
+    assert_eq!(anchor.read_opened().unwrap(), b"[daemon]\n");
+    assert_eq!(anchor.read_opened().unwrap(), b"[daemon]\n");
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn reloader_sets_socket_mode() {
+    let path = env::temp_dir().join(format!("tr1pd-test-{}.sock", process::id()));
+    File::create(&path).unwrap();
+
+    let url = format!("unix://{}", path.to_str().unwrap());
+    let mut reloader = Reloader::new(None, &url, true).unwrap();
+    reloader.apply(&Config::parse("[daemon]\nsocket_mode = \"0640\"\n").unwrap()).unwrap();
+
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn signal_from_raw() {
+    assert_eq!(Signal::from_raw(libc::SIGTERM), Some(Signal::Shutdown("SIGTERM")));
+    assert_eq!(Signal::from_raw(libc::SIGINT), Some(Signal::Shutdown("SIGINT")));
+    assert_eq!(Signal::from_raw(libc::SIGHUP), Some(Signal::Reload));
+    assert_eq!(Signal::from_raw(libc::SIGUSR1), None);
+}
@@ -0,0 +1,438 @@
+use blocks::{BlockPointer, KeyId, SensorSignature, Sequence};
+use crypto::Signature;
+use recipe::{self, BlockRecipe};
+use rpc::{self, capabilities, ClientBuilder, CtlRequest, CtlResponse, ErrorKind, Hello, NackCode, ReplyToken, Server, Status};
+use spool::SpoolEntry;
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("tr1pd-test-{}-{}.sock", name, process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+
+#[test]
+fn encode_decode_ping() {
+    let req = CtlRequest::Ping;
+
+    let mut bytes = Vec::new();
+    req.encode(&mut bytes);
+    assert_eq!(bytes, vec![0x00]);
+
+    assert_eq!(CtlRequest::decode(&bytes).unwrap(), req);
+}
+
+#[test]
+fn encode_decode_write_batch() {
+    let req = CtlRequest::WriteBatch(None, vec![
+        BlockRecipe::Info(b"ohai\n".to_vec()),
+        BlockRecipe::Rekey,
+        BlockRecipe::Info(b"wat\n".to_vec()),
+    ], None);
+
+    let mut bytes = Vec::new();
+    req.encode(&mut bytes);
+    assert_eq!(&bytes[..3], &[0x02, 0x00, 0x03]);
+
+    assert_eq!(CtlRequest::decode(&bytes).unwrap(), req);
+}
+
+#[test]
+fn encode_decode_ledger_requests() {
+    let req = CtlRequest::Write(None, BlockRecipe::Rekey, None);
+    let mut bytes = Vec::new();
+    req.encode(&mut bytes);
+    assert_eq!(bytes, vec![0x01, 0x00]);
+
+    let reqs = vec![
+        CtlRequest::Write(Some("auth".into()), BlockRecipe::Info(b"ohai\n".to_vec()), None),
+        CtlRequest::WriteBatch(Some("auth".into()), vec![BlockRecipe::Rekey], None),
+        CtlRequest::WriteBatch(Some("auth".into()), vec![BlockRecipe::Rekey], Some("a1b2".into())),
+        CtlRequest::Status(Some("auth".into())),
+        CtlRequest::Write(Some("auth".into()), BlockRecipe::Transaction(vec![
+            b"sudo id\n".to_vec(),
+            b"uid=0(root)\n".to_vec(),
+        ]), None),
+        CtlRequest::WriteBatch(Some("auth".into()), vec![
+            BlockRecipe::SplitLine(vec![vec![b'a'; 65535], b"\n".to_vec()]),
+        ], None),
+        CtlRequest::Write(Some("auth".into()), BlockRecipe::ContinuedLine(vec![b'a'; 65535], vec![
+            b"\n".to_vec(),
+        ]), None),
+        CtlRequest::Write(Some("auth".into()), BlockRecipe::SequencedLine(Sequence {
+            source: "web01".into(),
+            seq: 4,
+        }, vec![vec![b'a'; 65535], b"\n".to_vec()]), None),
+        CtlRequest::Write(Some("auth".into()), BlockRecipe::Rekey, Some("a1b2".into())),
+        CtlRequest::Write(Some("auth".into()), BlockRecipe::Replay(vec![
+            SpoolEntry { seq: 1, time: 1514764800, msg: b"ohai\n".to_vec() },
+            SpoolEntry { seq: 2, time: 1514764801, msg: Vec::new() },
+        ]), None),
+        CtlRequest::Write(Some("auth".into()), BlockRecipe::Sequenced(Sequence {
+            source: "web01".into(),
+            seq: 3,
+        }, b"ohai\n".to_vec()), None),
+        CtlRequest::Write(Some("auth".into()), BlockRecipe::Signed(SensorSignature {
+            key_id: KeyId([0x02; 8]),
+            signature: Signature::from_slice(&[0x03; 64]).unwrap(),
+        }, Box::new(BlockRecipe::Info(b"ohai\n".to_vec()))), None),
+    ];
+
+    for req in reqs {
+        let mut bytes = Vec::new();
+        req.encode(&mut bytes);
+        assert_eq!(&bytes[1..6], b"\x04auth");
+
+        let decoded = CtlRequest::decode(&bytes).unwrap();
+        assert_eq!(decoded.ledger(), Some("auth"));
+        assert_eq!(decoded, req);
+    }
+}
+
+#[test]
+fn encode_decode_request_id() {
+    let req = CtlRequest::Write(None, BlockRecipe::Rekey, Some("a1b2".into()));
+
+    let mut bytes = Vec::new();
+    req.encode(&mut bytes);
+    assert_eq!(bytes, b"\x08\x00\x04a1b2\x00");
+
+    let decoded = CtlRequest::decode(&bytes).unwrap();
+    assert_eq!(decoded.ledger(), None);
+    assert_eq!(decoded, req);
+
+    let req = CtlRequest::WriteBatch(None, vec![BlockRecipe::Rekey], Some("a1b2".into()));
+
+    let mut bytes = Vec::new();
+    req.encode(&mut bytes);
+    assert_eq!(bytes, b"\x09\x00\x04a1b2\x00\x01\x00");
+    assert_eq!(CtlRequest::decode(&bytes).unwrap(), req);
+
+    assert!(rpc::validate_request_id("a1b2"));
+    assert!(rpc::validate_request_id(&rpc::random_request_id()));
+    assert!(!rpc::validate_request_id(""));
+    assert!(!rpc::validate_request_id("a b"));
+    assert!(!rpc::validate_request_id(&"a".repeat(rpc::MAX_REQUEST_ID_LEN + 1)));
+}
+
+#[test]
+fn encode_decode_ack_batch() {
+    let resp = CtlResponse::AckBatch(vec![
+        BlockPointer([0x01; 32]),
+        BlockPointer([0x02; 32]),
+    ]);
+
+    let mut bytes = Vec::new();
+    resp.encode(&mut bytes);
+    assert_eq!(bytes.len(), 1 + 2 + 2 * 32);
+
+    assert_eq!(CtlResponse::decode(&bytes).unwrap(), resp);
+}
+
+#[test]
+fn decode_truncated_batch() {
+    let bytes = [0x02, 0x00, 0x02, 0x00];
+    assert!(CtlRequest::decode(&bytes).is_err());
+}
+
+#[test]
+fn decode_nested_signed_recipe() {
+    let sig = SensorSignature {
+        key_id: KeyId([0x02; 8]),
+        signature: Signature::from_slice(&[0x03; 64]).unwrap(),
+    };
+
+    let signed = BlockRecipe::Signed(sig.clone(), Box::new(BlockRecipe::Sequenced(Sequence {
+        source: "web01".into(),
+        seq: 3,
+    }, b"ohai\n".to_vec())));
+    let mut bytes = Vec::new();
+    signed.encode(&mut bytes);
+    assert_eq!(BlockRecipe::decode(&bytes).unwrap(), signed);
+
+    // a signed recipe can't contain another one, this would allow unlimited nesting
+    let nested = BlockRecipe::Signed(sig.clone(), Box::new(signed));
+    let mut bytes = Vec::new();
+    nested.encode(&mut bytes);
+    assert!(BlockRecipe::decode(&bytes).is_err());
+
+    let req = CtlRequest::Write(None, nested, None);
+    let mut bytes = Vec::new();
+    req.encode(&mut bytes);
+    assert!(CtlRequest::decode(&bytes).is_err());
+
+    let mut bytes = Vec::new();
+    for _ in 0..10_000 {
+        bytes.push(0x06);
+        bytes.extend(&sig.key_id.0);
+        bytes.extend(sig.signature.0.iter());
+    }
+    bytes.extend(b"\x01\x00\x05ohai\n");
+    assert!(BlockRecipe::decode(&bytes).is_err());
+}
+
+#[test]
+fn encode_decode_hello() {
+    let req = CtlRequest::Hello(Hello::current());
+
+    let mut bytes = Vec::new();
+    req.encode(&mut bytes);
+    assert_eq!(CtlRequest::decode(&bytes).unwrap(), req);
+
+    let resp = CtlResponse::Hello(Hello {
+        version: 7,
+        capabilities: capabilities::BATCH,
+    });
+
+    let mut bytes = Vec::new();
+    resp.encode(&mut bytes);
+    assert_eq!(bytes, vec![0x04, 0x00, 0x07, 0x00, 0x00, 0x00, 0x01]);
+    assert_eq!(CtlResponse::decode(&bytes).unwrap(), resp);
+}
+
+#[test]
+fn encode_decode_nack() {
+    let resp = CtlResponse::nack(NackCode::RateLimited, "slow down");
+
+    let mut bytes = Vec::new();
+    resp.encode(&mut bytes);
+    assert_eq!(&bytes[..4], &[0x02, 0x03, 0x00, 0x09]);
+    assert_eq!(CtlResponse::decode(&bytes).unwrap(), resp);
+}
+
+#[test]
+fn decode_legacy_nack() {
+    let resp = CtlResponse::decode(&[0x02]).unwrap();
+    assert_eq!(resp, CtlResponse::nack(NackCode::Unknown, ""));
+}
+
+#[test]
+fn encode_decode_status() {
+    let req = CtlRequest::Status(None);
+
+    let mut bytes = Vec::new();
+    req.encode(&mut bytes);
+    assert_eq!(bytes, vec![0x04]);
+    assert_eq!(CtlRequest::decode(&bytes).unwrap(), req);
+
+    let resp = CtlResponse::Status(Status {
+        version: "0.3.1".into(),
+        uptime: 3600,
+        head: BlockPointer::from_slice(&[1; 32]).unwrap(),
+        session: BlockPointer::from_slice(&[2; 32]).unwrap(),
+        session_blocks: 42,
+        datadir: "/var/lib/tr1pd".into(),
+        sandbox: vec!["stage1".into(), "stage2".into(), "chroot".into()],
+        config_fingerprint: "abcdef".into(),
+    });
+
+    let mut bytes = Vec::new();
+    resp.encode(&mut bytes);
+    assert_eq!(&bytes[..7], &[0x05, 0x05, b'0', b'.', b'3', b'.', b'1']);
+    assert_eq!(&bytes[7..15], &[0, 0, 0, 0, 0, 0, 0x0e, 0x10]);
+    assert_eq!(CtlResponse::decode(&bytes).unwrap(), resp);
+
+    // truncated
+    assert!(CtlResponse::decode(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn hello_capabilities() {
+    let hello = Hello {
+        version: 1,
+        capabilities: capabilities::BATCH,
+    };
+
+    assert!(hello.has(capabilities::BATCH));
+    assert!(!hello.has(capabilities::ORIGIN));
+    assert!(!hello.has(capabilities::BATCH | capabilities::ORIGIN));
+}
+
+#[cfg(feature="zmq")]
+#[test]
+fn curve_allowlist() {
+    let (pk, _) = rpc::curve::gen_keypair();
+    let (other, _) = rpc::curve::gen_keypair();
+    let clients = vec![pk.clone()];
+
+    assert!(rpc::curve::is_allowed(&clients, b"CURVE", &[pk.0.to_vec()]));
+    assert!(!rpc::curve::is_allowed(&clients, b"CURVE", &[other.0.to_vec()]));
+    assert!(!rpc::curve::is_allowed(&clients, b"NULL", &[pk.0.to_vec()]));
+    assert!(!rpc::curve::is_allowed(&clients, b"CURVE", &[]));
+    // an empty allowlist rejects everybody
+    assert!(!rpc::curve::is_allowed(&[], b"CURVE", &[pk.0.to_vec()]));
+}
+
+#[test]
+fn client_recv_timeout() {
+    let path = socket_path("timeout");
+
+    // accepts connections but never replies
+    let _listener = UnixListener::bind(&path).unwrap();
+
+    let url = format!("unix://{}", path.to_str().unwrap());
+    let result = ClientBuilder::new(url)
+        .timeout(Some(Duration::from_millis(100)))
+        .connect();
+    let _ = fs::remove_file(&path);
+
+    match result {
+        Err(err) => match *err.kind() {
+            ErrorKind::Timeout => (),
+            _ => panic!("expected timeout, got {:?}", err),
+        },
+        Ok(_) => panic!("expected timeout"),
+    }
+}
+
+#[test]
+fn connect_without_handshake() {
+    let path = socket_path("nohello");
+    let url = format!("unix://{}", path.to_str().unwrap());
+
+    // a daemon that doesn't know the handshake yet
+    let mut server = Server::bind(&url).unwrap();
+    thread::spawn(move || {
+        loop {
+            let req = server.recv().unwrap();
+            let reply = match req.msg {
+                CtlRequest::Hello(_) => CtlResponse::nack(NackCode::InvalidRequest, "failed to decode request"),
+                _ => CtlResponse::Pong,
+            };
+            server.reply(req.token, &reply).unwrap();
+        }
+    });
+
+    let mut client = ClientBuilder::new(url).connect().unwrap();
+    let _ = fs::remove_file(&path);
+
+    assert_eq!(client.server().version, 0);
+    assert!(!client.server().has(capabilities::BATCH));
+    client.ping().unwrap();
+}
+
+#[test]
+fn split_line_fits_into_frames() {
+    let path = socket_path("split");
+    let url = format!("unix://{}", path.to_str().unwrap());
+
+    let mut server = Server::bind(&url).unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        loop {
+            let req = server.recv().unwrap();
+            let reply = match req.msg {
+                CtlRequest::Hello(_) => CtlResponse::Hello(Hello::current()),
+                CtlRequest::Write(_, recipe, _) => {
+                    tx.send(recipe).unwrap();
+                    CtlResponse::Ack(BlockPointer([0x01; 32]))
+                },
+                _ => CtlResponse::Pong,
+            };
+            server.reply(req.token, &reply).unwrap();
+        }
+    });
+
+    let parts: Vec<_> = (0..30).map(|i| vec![i as u8; recipe::MAX_LINE_PART]).collect();
+    let mut client = ClientBuilder::new(url).connect().unwrap();
+    let seq = Sequence {
+        source: "web01".into(),
+        seq: 3,
+    };
+    client.write_split_line(parts.clone(), None, Some(seq.clone())).unwrap();
+    let _ = fs::remove_file(&path);
+
+    // only the first request is numbered, the others continue the line
+    assert_eq!(parts.chunks(recipe::MAX_SPLIT_PARTS).count(), 3);
+    for (i, chunk) in parts.chunks(recipe::MAX_SPLIT_PARTS).enumerate() {
+        let expected = match i {
+            0 => BlockRecipe::SequencedLine(seq.clone(), chunk.to_vec()),
+            _ => BlockRecipe::ContinuedLine(parts[i * recipe::MAX_SPLIT_PARTS - 1].clone(), chunk.to_vec()),
+        };
+        let recipe = rx.recv().unwrap();
+        assert_eq!(recipe, expected);
+
+        let mut bytes = Vec::new();
+        CtlRequest::Write(None, recipe, None).encode(&mut bytes);
+        assert!(bytes.len() <= rpc::unix::MAX_FRAME_SIZE);
+    }
+}
+
+#[test]
+fn server_replies_out_of_order() {
+    let path = socket_path("router");
+    let url = format!("unix://{}", path.to_str().unwrap());
+
+    let mut server = Server::bind(&url).unwrap();
+    let (pending_tx, pending_rx) = mpsc::channel();
+    let (pong_tx, pong_rx) = mpsc::channel();
+
+    // answer the write from a different thread once the ping has been answered
+    thread::spawn(move || {
+        let token: ReplyToken = pending_rx.recv().unwrap();
+        pong_rx.recv().unwrap();
+        token.reply(&CtlResponse::Ack(BlockPointer([0x01; 32]))).unwrap();
+    });
+
+    thread::spawn(move || {
+        let mut pending = Some(pending_tx);
+        loop {
+            let req = server.recv().unwrap();
+            match req.msg {
+                CtlRequest::Write(..) => {
+                    pending.take().unwrap().send(req.token).unwrap();
+                },
+                CtlRequest::Hello(_) => server.reply(req.token, &CtlResponse::Hello(Hello::current())).unwrap(),
+                _ => server.reply(req.token, &CtlResponse::Pong).unwrap(),
+            }
+        }
+    });
+
+    let mut writer = ClientBuilder::new(url.clone()).connect().unwrap();
+    let write = thread::spawn(move || writer.write_block(BlockRecipe::Rekey).unwrap());
+
+    let mut pinger = ClientBuilder::new(url).connect().unwrap();
+    pinger.ping().unwrap();
+    pong_tx.send(()).unwrap();
+
+    let pointer = write.join().unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(pointer, BlockPointer([0x01; 32]));
+}
+
+#[test]
+fn slow_client_doesnt_stall_server() {
+    let path = socket_path("slow");
+    let url = format!("unix://{}", path.to_str().unwrap());
+
+    let mut server = Server::bind(&url).unwrap();
+    thread::spawn(move || {
+        loop {
+            let req = server.recv().unwrap();
+            let reply = match req.msg {
+                CtlRequest::Hello(_) => CtlResponse::Hello(Hello::current()),
+                _ => CtlResponse::Pong,
+            };
+            server.reply(req.token, &reply).unwrap();
+        }
+    });
+
+    // a client that stops in the middle of a frame
+    let mut slow = UnixStream::connect(&path).unwrap();
+    slow.write_all(&[0x00, 0x00, 0x00, 0x10, 0x01]).unwrap();
+
+    let mut client = ClientBuilder::new(url).connect().unwrap();
+    let _ = fs::remove_file(&path);
+    client.ping().unwrap();
+}
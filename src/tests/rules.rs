@@ -0,0 +1,150 @@
+use config::RuleConfig;
+use crypto::{self, SignRing};
+use engine::Engine;
+use hooks::{Event, HookRunner};
+use recipe::BlockRecipe;
+use rules::{Rule, Rules};
+use storage::{BlockStorage, MemoryStorage};
+
+use std::sync::Arc;
+
+
+fn rule(name: &str, contains: Option<&str>, regex: Option<&str>) -> RuleConfig {
+    RuleConfig {
+        name: name.into(),
+        contains: contains.map(|x| x.into()),
+        regex: regex.map(|x| x.into()),
+        hook: None,
+    }
+}
+
+#[test]
+fn match_rules() {
+    let rules = Rules::from_config(&[
+        rule("root login", Some("Accepted publickey for root"), None),
+        rule("failed password", None, Some(r"Failed password for (invalid user )?\w+")),
+    ]).unwrap();
+
+    assert_eq!(rules.matches(b"sshd[1337]: Accepted publickey for root from 10.0.0.1"), vec![0]);
+    assert_eq!(rules.matches(b"sshd[1337]: Failed password for invalid user admin"), vec![1]);
+    assert_eq!(rules.matches(b"sshd[1337]: Accepted publickey for user from 10.0.0.1"), Vec::<usize>::new());
+    assert_eq!(rules.matches(b"\xff\xfe Failed password for root"), vec![1]);
+}
+
+#[test]
+fn invalid_rules() {
+    assert!(Rule::from_config(&rule("empty", None, None)).is_err());
+    assert!(Rule::from_config(&rule("both", Some("root"), Some("root"))).is_err());
+    assert!(Rule::from_config(&rule("regex", None, Some("("))).is_err());
+
+    let mut config = rule("hook", Some("root"), None);
+    config.hook = Some(Vec::new());
+    assert!(Rule::from_config(&config).is_err());
+}
+
+#[test]
+fn engine_writes_alert() {
+    let (pk, sk) = crypto::gen_keypair();
+    let ring = SignRing::new(pk, sk);
+    let storage = MemoryStorage::new().into_engine();
+    let mut engine = Engine::start(storage, ring).unwrap();
+
+    let mut root = rule("root login", Some("for root"), None);
+    root.hook = Some(vec!["/usr/local/bin/notify-admin".into()]);
+    let rules = Rules::from_config(&[
+        root,
+        rule("publickey", Some("publickey"), None),
+    ]).unwrap();
+    let (hooks, events) = HookRunner::queue(8);
+    engine.set_rules(Arc::new(rules), Some(hooks));
+
+    // info and rekey
+    engine.recipe(BlockRecipe::Info(b"Accepted password for user".to_vec())).unwrap();
+    assert_eq!(engine.session_blocks(), 3);
+    assert!(events.try_recv().is_err());
+
+    // info and one alert for each rule instead of the rekey
+    engine.recipe(BlockRecipe::Info(b"Accepted publickey for root".to_vec())).unwrap();
+    assert_eq!(engine.session_blocks(), 6);
+
+    let second = engine.storage().get(engine.head()).unwrap();
+    let first = engine.storage().get(second.prev()).unwrap();
+    let info = first.prev().clone();
+    assert_eq!(first.msg().unwrap(), &format!("rule \"root login\" matched {:x}", info).into_bytes());
+    assert_eq!(second.msg().unwrap(), &format!("rule \"publickey\" matched {:x}", info).into_bytes());
+
+    // only the first rule has a hook
+    assert_eq!(events.try_recv().unwrap(), Event {
+        rule: 0,
+        name: "root login".into(),
+        block: format!("{:x}", info),
+        alert: format!("{:x}", first.sha3()),
+        payload: b"Accepted publickey for root".to_vec(),
+    });
+    assert!(events.try_recv().is_err());
+}
+
+#[test]
+fn hook_queue_is_bounded() {
+    let event = Event {
+        rule: 1,
+        name: "root login".into(),
+        block: "00".repeat(32),
+        alert: "11".repeat(32),
+        payload: b"Accepted publickey for root".to_vec(),
+    };
+
+    let (hooks, events) = HookRunner::queue(1);
+    hooks.run(event.clone()).unwrap();
+    // the engine doesn't wait for the helper
+    assert!(hooks.run(event.clone()).is_err());
+    assert_eq!(events.recv().unwrap(), event);
+
+    drop(events);
+    assert!(hooks.run(event).is_err());
+}
+
+#[test]
+fn rules_match_across_split_parts() {
+    let (pk, sk) = crypto::gen_keypair();
+    let ring = SignRing::new(pk, sk);
+    let storage = MemoryStorage::new().into_engine();
+    let mut engine = Engine::start(storage, ring).unwrap();
+
+    let mut root = rule("root login", Some("for root"), None);
+    root.hook = Some(vec!["/usr/local/bin/notify-admin".into()]);
+    let mut publickey = rule("publickey", Some("publickey"), None);
+    publickey.hook = Some(vec!["/usr/local/bin/notify-admin".into()]);
+    let rules = Rules::from_config(&[root, publickey]).unwrap();
+    let (hooks, events) = HookRunner::queue(8);
+    engine.set_rules(Arc::new(rules), Some(hooks));
+
+    engine.recipe(BlockRecipe::SplitLine(vec![
+        b"sshd[1337]: Accepted publickey fo".to_vec(),
+        b"r root from 10.0.0.1\n".to_vec(),
+    ])).unwrap();
+
+    // the second rule already matched the first part, it isn't reported again
+    let event = events.try_recv().unwrap();
+    assert_eq!((event.rule, &event.payload[..]), (1, &b"sshd[1337]: Accepted publickey fo"[..]));
+    let event = events.try_recv().unwrap();
+    assert_eq!((event.rule, &event.payload[..]), (0, &b"sshd[1337]: Accepted publickey for root from 10.0.0.1\n"[..]));
+    assert!(events.try_recv().is_err());
+
+    // the line continues in the next request
+    engine.recipe(BlockRecipe::ContinuedLine(
+        b"sshd[1337]: Accepted password fo".to_vec(),
+        vec![b"r root from 10.0.0.1\n".to_vec()],
+    )).unwrap();
+
+    let event = events.try_recv().unwrap();
+    assert_eq!(event.rule, 0);
+    assert!(events.try_recv().is_err());
+
+    let split = engine.storage().get(engine.head()).unwrap();
+    assert_eq!(split.msg().unwrap(), &b"tr1pd: line continues with 21 bytes in 1 blocks".to_vec());
+    let alert = engine.storage().get(split.prev()).unwrap();
+    let part = engine.storage().get(alert.prev()).unwrap();
+    assert!(part.continued());
+    assert!(part.split().unwrap().is_first());
+}
@@ -0,0 +1,90 @@
+use blocks::{Attribute, Block, KeyId, Sequence};
+use crypto::{self, SignRing};
+use engine::Engine;
+use recipe::BlockRecipe;
+use sensors::{SensorCheck, SensorKey, SensorRegistry};
+use storage::{MemoryStorage, BlockStorage};
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::process;
+
+
+fn sensor_key(name: &str) -> (SensorKey, crypto::PublicKey) {
+    let (pk, sk) = crypto::gen_keypair();
+
+    let path = env::temp_dir().join(format!("tr1pd-test-{}-{}.sk", name, process::id()));
+    File::create(&path).unwrap().write_all(&sk.0).unwrap();
+    let key = SensorKey::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(key.key_id(), KeyId::of(&pk));
+    (key, pk)
+}
+
+#[test]
+fn sensor_signatures() {
+    let (key, pk) = sensor_key("web01");
+    let (_, other) = sensor_key("db01");
+
+    let (daemon_pk, daemon_sk) = crypto::gen_keypair();
+    let storage = MemoryStorage::new().into_engine();
+    let mut engine = Engine::start(storage, SignRing::new(daemon_pk, daemon_sk)).unwrap();
+
+    let recipe = BlockRecipe::sequenced(Sequence {
+        source: "web01".into(),
+        seq: 1,
+    }, b"ohai\n".to_vec()).unwrap();
+    engine.recipe(key.sign(recipe).unwrap()).unwrap();
+
+    let storage = engine.storage();
+    let rekey = storage.get(&storage.get_head().unwrap()).unwrap();
+    let block = storage.get(rekey.prev()).unwrap();
+    assert_eq!(block.sensor_signature().unwrap().key_id, key.key_id());
+
+    let mut registry = SensorRegistry::default();
+    assert_eq!(registry.verify(&block), SensorCheck::UnknownKey(key.key_id()));
+    registry.insert("web01".into(), pk).unwrap();
+    registry.insert("db01".into(), other).unwrap();
+    assert_eq!(registry.verify(&block), SensorCheck::Valid("web01".into()));
+    assert!(registry.insert("web01-again".into(), pk).is_err());
+
+    // a compromised daemon can sign anything, but not for the sensor
+    let mut ring = SignRing::new(daemon_pk, crypto::gen_keypair().1);
+    ring.init();
+    let forged = Block::info_with_attributes(block.prev().clone(), &mut ring, b"wat\n".to_vec(), block.attributes().to_vec()).unwrap();
+    assert_eq!(registry.verify(&forged), SensorCheck::Invalid("web01".into()));
+
+    // the sequence number is covered as well
+    let renumbered = block.attributes().iter()
+        .map(|attr| match *attr {
+            Attribute::Sequence(ref seq) => Attribute::Sequence(Sequence { seq: 2, ..seq.clone() }),
+            ref attr => attr.clone(),
+        })
+        .collect();
+    let forged = Block::info_with_attributes(block.prev().clone(), &mut ring, b"ohai\n".to_vec(), renumbered).unwrap();
+    assert_eq!(registry.verify(&forged), SensorCheck::Invalid("web01".into()));
+
+    let unsigned = Block::info(block.prev().clone(), &mut ring, b"ohai\n".to_vec()).unwrap();
+    assert_eq!(registry.verify(&unsigned), SensorCheck::Unsigned);
+}
+
+#[test]
+fn sensor_signatures_single_messages() {
+    let (key, _) = sensor_key("tx");
+
+    let tx = BlockRecipe::Transaction(vec![b"sudo id\n".to_vec()]);
+    assert!(key.sign(tx.clone()).is_err());
+
+    let signed = key.sign(BlockRecipe::Info(b"ohai\n".to_vec())).unwrap();
+    let sig = match signed {
+        BlockRecipe::Signed(sig, _) => sig,
+        _ => panic!("recipe isn't signed"),
+    };
+
+    let (pk, sk) = crypto::gen_keypair();
+    let storage = MemoryStorage::new().into_engine();
+    let mut engine = Engine::start(storage, SignRing::new(pk, sk)).unwrap();
+    assert!(engine.recipe(BlockRecipe::Signed(sig, Box::new(tx))).is_err());
+}
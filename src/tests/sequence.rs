@@ -0,0 +1,84 @@
+use blocks::Sequence;
+use sequence::{SourceCounter, SourceTracker, Verdict, Writer};
+
+use std::env;
+use std::fs;
+use std::process;
+
+
+fn seq(source: &str, seq: u64) -> Sequence {
+    Sequence {
+        source: source.into(),
+        seq,
+    }
+}
+
+#[test]
+fn source_counter_persists() {
+    let path = env::temp_dir().join(format!("tr1pd-test-{}.seq", process::id()));
+    let _ = fs::remove_file(&path);
+
+    let mut counter = SourceCounter::open("web01", path.clone()).unwrap();
+    assert_eq!(counter.assign(2).unwrap(), vec![seq("web01", 1), seq("web01", 2)]);
+
+    // the sequence continues after a restart
+    let mut counter = SourceCounter::open("web01", path.clone()).unwrap();
+    assert_eq!(counter.next(), 3);
+    assert_eq!(counter.assign(1).unwrap(), vec![seq("web01", 3)]);
+
+    assert!(SourceCounter::open("web 01", path.clone()).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn source_tracker_gaps() {
+    let mut tracker = SourceTracker::default();
+    let writer = Writer::Uid(1000);
+
+    // the range may start in the middle of a sequence
+    assert_eq!(tracker.add(&writer, &seq("web01", 7)), Verdict::Ok);
+    assert_eq!(tracker.add(&writer, &seq("db01", 1)), Verdict::Ok);
+    assert_eq!(tracker.add(&writer, &seq("web01", 8)), Verdict::Ok);
+    assert_eq!(tracker.add(&writer, &seq("web01", 12)), Verdict::Gap(9, 11));
+    assert_eq!(tracker.add(&writer, &seq("web01", 10)), Verdict::Reordered);
+    assert_eq!(tracker.add(&writer, &seq("web01", 10)), Verdict::Duplicate);
+    assert_eq!(tracker.add(&writer, &seq("db01", 2)), Verdict::Ok);
+
+    let reports = tracker.reports();
+    assert!(reports[&(writer.clone(), "db01".to_string())].is_ok());
+
+    let web01 = &reports[&(writer.clone(), "web01".to_string())];
+    assert!(!web01.is_ok());
+    assert_eq!(web01.gaps, vec![(9, 9), (11, 11)]);
+    assert_eq!(web01.missing(), 2);
+    assert_eq!(web01.to_string(), "5 messages, #7 to #12, 2 missing, 1 duplicates, 1 reordered (gaps: #9, #11)");
+}
+
+#[test]
+fn source_tracker_scopes_writers() {
+    let mut tracker = SourceTracker::default();
+
+    // another client can't fill the gap
+    assert_eq!(tracker.add(&Writer::Uid(1000), &seq("web01", 1)), Verdict::Ok);
+    assert_eq!(tracker.add(&Writer::Uid(1000), &seq("web01", 3)), Verdict::Gap(2, 2));
+    assert_eq!(tracker.add(&Writer::ClientKey("key".into()), &seq("web01", 2)), Verdict::Ok);
+    assert_eq!(tracker.add(&Writer::Anonymous, &seq("web01", 2)), Verdict::Ok);
+    assert_eq!(tracker.reports().len(), 3);
+    assert_eq!(tracker.reports()[&(Writer::Uid(1000), "web01".to_string())].missing(), 1);
+}
+
+#[test]
+fn source_tracker_doesnt_overflow() {
+    let mut tracker = SourceTracker::default();
+    let writer = Writer::Anonymous;
+
+    assert_eq!(tracker.add(&writer, &seq("web01", 0)), Verdict::Ok);
+    assert_eq!(tracker.add(&writer, &seq("web01", u64::max_value())), Verdict::Gap(1, u64::max_value() - 1));
+    assert_eq!(tracker.add(&writer, &seq("web01", u64::max_value())), Verdict::Duplicate);
+    assert_eq!(tracker.reports()[&(writer.clone(), "web01".to_string())].missing(), u64::max_value() - 1);
+
+    let mut tracker = SourceTracker::default();
+    assert_eq!(tracker.add(&writer, &seq("db01", 1)), Verdict::Ok);
+    assert_eq!(tracker.add(&writer, &seq("db01", u64::max_value())), Verdict::Gap(2, u64::max_value() - 1));
+    assert_eq!(tracker.add(&writer, &seq("db01", 0)), Verdict::Duplicate);
+}
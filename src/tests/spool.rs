@@ -0,0 +1,111 @@
+use spool::{Spool, ErrorKind};
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process;
+
+
+fn spool_dir(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("tr1pd-test-{}-{}.spool", name, process::id()));
+    let _ = fs::remove_dir_all(&path);
+    path
+}
+
+#[test]
+fn spool_replay() {
+    let path = spool_dir("replay");
+
+    let mut spool = Spool::open(path.clone()).unwrap();
+    assert!(spool.is_empty());
+    assert_eq!(spool.push(b"ohai\n").unwrap().seq, 1);
+    assert_eq!(spool.push(b"wat\n").unwrap().seq, 2);
+    assert_eq!(spool.push(b"\xff\n").unwrap().seq, 3);
+
+    // the entries survive a restart
+    let mut spool = Spool::open(path.clone()).unwrap();
+    assert_eq!(spool.len(), 3);
+
+    let pending = spool.pending().unwrap();
+    let msgs: Vec<_> = pending.iter().map(|entry| entry.msg.clone()).collect();
+    assert_eq!(msgs, vec![b"ohai\n".to_vec(), b"wat\n".to_vec(), b"\xff\n".to_vec()]);
+
+    spool.replayed(1).unwrap();
+    assert_eq!(spool.len(), 2);
+    assert_eq!(spool.pending().unwrap()[0].seq, 2);
+
+    spool.replayed(3).unwrap();
+    assert!(spool.is_empty());
+    assert_eq!(fs::metadata(path.join("spool")).unwrap().len(), 0);
+
+    // sequence numbers continue after the spool has been truncated
+    let mut spool = Spool::open(path.clone()).unwrap();
+    assert!(spool.is_empty());
+    assert_eq!(spool.push(b"ohai\n").unwrap().seq, 4);
+
+    fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn spool_tampered() {
+    let path = spool_dir("tampered");
+
+    let mut spool = Spool::open(path.clone()).unwrap();
+    spool.push(b"Accepted publickey for root\n").unwrap();
+    spool.push(b"ohai\n").unwrap();
+
+    let mut buf = fs::read(path.join("spool")).unwrap();
+    // the first byte of the first message
+    buf[20] ^= 0x01;
+    fs::write(path.join("spool"), &buf).unwrap();
+
+    match *Spool::open(path.clone()).unwrap_err().kind() {
+        ErrorKind::InvalidTag(1) => (),
+        ref err => panic!("unexpected error: {:?}", err),
+    }
+
+    fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn spool_incomplete_entry() {
+    let path = spool_dir("incomplete");
+
+    let mut spool = Spool::open(path.clone()).unwrap();
+    spool.push(b"ohai\n").unwrap();
+    drop(spool);
+
+    // tr1pctl crashed while writing the second entry
+    let mut file = OpenOptions::new().append(true).open(path.join("spool")).unwrap();
+    file.write_all(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00]).unwrap();
+
+    let mut spool = Spool::open(path.clone()).unwrap();
+    assert_eq!(spool.len(), 1);
+    assert_eq!(spool.push(b"wat\n").unwrap().seq, 2);
+    assert_eq!(spool.pending().unwrap().len(), 2);
+
+    fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn spool_modified_length() {
+    let path = spool_dir("length");
+
+    let mut spool = Spool::open(path.clone()).unwrap();
+    spool.push(b"Accepted publickey for root\n").unwrap();
+    drop(spool);
+
+    // a larger length would make the entry look incomplete
+    let mut buf = fs::read(path.join("spool")).unwrap();
+    buf[18] = 0xff;
+    fs::write(path.join("spool"), &buf).unwrap();
+
+    match *Spool::open(path.clone()).unwrap_err().kind() {
+        ErrorKind::TrailingBytes(80) => (),
+        ref err => panic!("unexpected error: {:?}", err),
+    }
+    assert_eq!(fs::read(path.join("spool")).unwrap(), buf);
+
+    fs::remove_dir_all(path).unwrap();
+}
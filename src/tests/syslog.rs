@@ -0,0 +1,100 @@
+use blocks::Syslog;
+use syslog::{self, SyslogMessage};
+
+use std::io::Cursor;
+
+
+#[test]
+fn parse_rfc5424() {
+    let msg = b"<34>1 2003-10-11T22:14:15.003Z mymachine.example.com su - ID47 - 'su root' failed for lonvick on /dev/pts/8\n";
+    let parsed = syslog::parse(msg);
+
+    assert_eq!(parsed, SyslogMessage {
+        syslog: Syslog {
+            facility: 4,
+            severity: 2,
+            hostname: Some("mymachine.example.com".into()),
+            app_name: Some("su".into()),
+        },
+        bytes: msg[..msg.len() - 1].to_vec(),
+    });
+}
+
+#[test]
+fn parse_rfc5424_nil() {
+    let parsed = syslog::parse(b"<165>1 - - - - - -");
+
+    assert_eq!(parsed.syslog, Syslog {
+        facility: 20,
+        severity: 5,
+        hostname: None,
+        app_name: None,
+    });
+}
+
+#[test]
+fn parse_rfc3164() {
+    let parsed = syslog::parse(b"<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8");
+
+    assert_eq!(parsed.syslog, Syslog {
+        facility: 4,
+        severity: 2,
+        hostname: Some("mymachine".into()),
+        app_name: Some("su".into()),
+    });
+}
+
+#[test]
+fn parse_rfc3164_local() {
+    // syslog(3) doesn't send a hostname
+    let parsed = syslog::parse(b"<38>Feb  3 12:00:01 sshd[1337]: Accepted publickey for root\0");
+
+    assert_eq!(parsed, SyslogMessage {
+        syslog: Syslog {
+            facility: 4,
+            severity: 6,
+            hostname: None,
+            app_name: Some("sshd".into()),
+        },
+        bytes: b"<38>Feb  3 12:00:01 sshd[1337]: Accepted publickey for root".to_vec(),
+    });
+}
+
+#[test]
+fn parse_without_header() {
+    let parsed = syslog::parse(b"hello world");
+
+    assert_eq!(parsed, SyslogMessage {
+        syslog: Syslog {
+            facility: 1,
+            severity: 5,
+            hostname: None,
+            app_name: None,
+        },
+        bytes: b"hello world".to_vec(),
+    });
+}
+
+#[test]
+fn parse_invalid_pri() {
+    let parsed = syslog::parse(b"<999>hello world");
+    assert_eq!(parsed.syslog.facility, 1);
+    assert_eq!(parsed.syslog.severity, 5);
+    assert_eq!(parsed.bytes, b"<999>hello world".to_vec());
+}
+
+#[test]
+fn read_tcp_frames() {
+    let mut stream = Cursor::new(&b"11 <13>1 - - -<13>hello\n<13>world"[..]);
+
+    assert_eq!(syslog::read_frame(&mut stream).unwrap(), Some(b"<13>1 - - -".to_vec()));
+    assert_eq!(syslog::read_frame(&mut stream).unwrap(), Some(b"<13>hello\n".to_vec()));
+    assert_eq!(syslog::read_frame(&mut stream).unwrap(), Some(b"<13>world".to_vec()));
+    assert_eq!(syslog::read_frame(&mut stream).unwrap(), None);
+}
+
+#[test]
+fn read_tcp_frame_too_large() {
+    let mut stream = Cursor::new(&b"999999 <13>hello"[..]);
+    assert!(syslog::read_frame(&mut stream).is_err());
+}
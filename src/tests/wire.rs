@@ -1,4 +1,5 @@
-use blocks::{BlockPointer, Block};
+use blocks::{BlockPointer, Block, Attribute, Origin, Syslog, RekeyPolicy, Sequence, Spooled, TxPart};
+use blocks::{Codec, Commitment, Compressed, Envelope, KeyId, Recipient, SensorSignature};
 use blocks::{InitBlock, RekeyBlock, AlertBlock, InfoBlock};
 use crypto::{Signature, PublicKey};
 use wire::block;
@@ -474,3 +475,472 @@ fn parse_info_block_bytes() {
     let block = block(&bytes);
     assert_eq!(block, IResult::Done(EMPTY_SLICE, expected));
 }
+
+#[test]
+fn parse_info_block_attributes_bytes() {
+    let bytes = [
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, // previous block
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x04, // op
+        0x01, // number of attributes
+        0x01, // origin
+        0x00, 0x0c, // length
+        0x00, 0x00, 0x03, 0xe8, // uid
+        0x00, 0x00, 0x03, 0xe9, // gid
+        0x00, 0x00, 0x05, 0x39, // pid
+        0x00, 0x04, // length
+        0x6f, 0x68, 0x61, 0x69, // payload
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, // signature
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, // signature
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+    ];
+
+    let signature = Signature::from_slice(&[
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+    ]).unwrap();
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([
+                0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+                0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+                0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+                0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+            ]),
+            vec![Attribute::Origin(Origin {
+                uid: 1000,
+                gid: 1001,
+                pid: 1337,
+            })],
+            "ohai".as_bytes().to_vec(),
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected.clone()));
+
+    let mut encoded = Vec::new();
+    expected.encode(&mut encoded);
+    assert_eq!(&encoded[..], &bytes[..]);
+}
+
+#[test]
+fn parse_info_block_unknown_attribute() {
+    let signature = Signature::from_slice(&[0x01; 64]).unwrap();
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([0x01; 32]),
+            vec![Attribute::Unknown(0xff, vec![0x13, 0x37])],
+            "ohai".as_bytes().to_vec(),
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let mut bytes = Vec::new();
+    expected.encode(&mut bytes);
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected));
+}
+
+#[test]
+fn parse_info_block_syslog_attribute() {
+    let signature = Signature::from_slice(&[0x01; 64]).unwrap();
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([0x01; 32]),
+            vec![Attribute::Syslog(Syslog {
+                facility: 4,
+                severity: 6,
+                hostname: Some("mymachine".into()),
+                app_name: None,
+            })],
+            "ohai".as_bytes().to_vec(),
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let mut bytes = Vec::new();
+    expected.encode(&mut bytes);
+    assert_eq!(&bytes[33..50], &[
+        0x01, // number of attributes
+        0x02, // syslog
+        0x00, 0x0d, // length
+        0x04, 0x06, // facility, severity
+        0x09, 0x6d, 0x79, 0x6d, 0x61, 0x63, 0x68, 0x69, 0x6e, 0x65, // hostname
+        0x00, // app-name
+    ][..]);
+
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected));
+}
+
+#[test]
+fn parse_info_block_rekey_policy_attribute() {
+    let signature = Signature::from_slice(&[0x01; 64]).unwrap();
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([0x01; 32]),
+            vec![Attribute::RekeyPolicy(RekeyPolicy {
+                infos: Some(100),
+                seconds: None,
+                bytes: Some(65536),
+            })],
+            "rekey policy: 100 info blocks, 65536 bytes".as_bytes().to_vec(),
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let mut bytes = Vec::new();
+    expected.encode(&mut bytes);
+    assert_eq!(&bytes[33..61], &[
+        0x01, // number of attributes
+        0x03, // rekey policy
+        0x00, 0x18, // length
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x64, // infos
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // seconds
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, // bytes
+    ][..]);
+
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected));
+}
+
+#[test]
+fn parse_info_block_transaction_attribute() {
+    let signature = Signature::from_slice(&[0x01; 64]).unwrap();
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([0x01; 32]),
+            vec![Attribute::Transaction(TxPart {
+                index: 1,
+                count: 3,
+            })],
+            "ohai".as_bytes().to_vec(),
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let mut bytes = Vec::new();
+    expected.encode(&mut bytes);
+    assert_eq!(&bytes[33..41], &[
+        0x01, // number of attributes
+        0x04, // transaction
+        0x00, 0x04, // length
+        0x00, 0x01, // index
+        0x00, 0x03, // count
+    ][..]);
+
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected));
+}
+
+#[test]
+fn parse_info_block_spooled_attribute() {
+    let signature = Signature::from_slice(&[0x01; 64]).unwrap();
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([0x01; 32]),
+            vec![Attribute::Spooled(Spooled {
+                seq: 2,
+                time: 1514764800,
+            })],
+            "ohai".as_bytes().to_vec(),
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let mut bytes = Vec::new();
+    expected.encode(&mut bytes);
+    assert_eq!(&bytes[33..53], &[
+        0x01, // number of attributes
+        0x06, // spooled
+        0x00, 0x10, // length
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // seq
+        0x00, 0x00, 0x00, 0x00, 0x5a, 0x49, 0x7a, 0x00, // time
+    ][..]);
+
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected));
+}
+
+#[test]
+fn parse_info_block_request_id_attribute() {
+    let signature = Signature::from_slice(&[0x01; 64]).unwrap();
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([0x01; 32]),
+            vec![Attribute::RequestId("a1b2".into())],
+            "ohai".as_bytes().to_vec(),
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let mut bytes = Vec::new();
+    expected.encode(&mut bytes);
+    assert_eq!(&bytes[33..41], &[
+        0x01, // number of attributes
+        0x07, // request id
+        0x00, 0x04, // length
+        b'a', b'1', b'b', b'2',
+    ][..]);
+
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected));
+}
+
+#[test]
+fn parse_info_block_sequence_attribute() {
+    let signature = Signature::from_slice(&[0x01; 64]).unwrap();
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([0x01; 32]),
+            vec![Attribute::Sequence(Sequence {
+                source: "web01".into(),
+                seq: 3,
+            })],
+            "ohai".as_bytes().to_vec(),
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let mut bytes = Vec::new();
+    expected.encode(&mut bytes);
+    assert_eq!(&bytes[33..50], &[
+        0x01, // number of attributes
+        0x08, // sequence
+        0x00, 0x0d, // length
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, // seq
+        b'w', b'e', b'b', b'0', b'1',
+    ][..]);
+
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected));
+}
+
+#[test]
+fn parse_info_block_sensor_signature_attribute() {
+    let signature = Signature::from_slice(&[0x01; 64]).unwrap();
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([0x01; 32]),
+            vec![Attribute::SensorSignature(SensorSignature {
+                key_id: KeyId([0x02; 8]),
+                signature: Signature::from_slice(&[0x03; 64]).unwrap(),
+            })],
+            "ohai".as_bytes().to_vec(),
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let mut bytes = Vec::new();
+    expected.encode(&mut bytes);
+    assert_eq!(&bytes[33..45], &[
+        0x01, // number of attributes
+        0x09, // sensor signature
+        0x00, 0x48, // length
+        0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, // key id
+    ][..]);
+    assert_eq!(&bytes[45..109], &[0x03; 64][..]);
+
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected));
+}
+
+#[test]
+fn parse_info_block_sealed_attribute() {
+    let signature = Signature::from_slice(&[0x01; 64]).unwrap();
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([0x01; 32]),
+            vec![Attribute::Sealed(Envelope {
+                nonce: vec![0x02; 24],
+                recipients: vec![
+                    Recipient { key_id: KeyId([0x03; 8]), sealed_key: vec![0x04; 80] },
+                    Recipient { key_id: KeyId([0x05; 8]), sealed_key: vec![0x06; 80] },
+                ],
+            })],
+            vec![0x07; 20],
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let mut bytes = Vec::new();
+    expected.encode(&mut bytes);
+    assert_eq!(&bytes[33..37], &[
+        0x01, // number of attributes
+        0x0a, // sealed
+        0x00, 0xc9, // length
+    ][..]);
+    assert_eq!(&bytes[37..61], &[0x02; 24][..]);
+    assert_eq!(bytes[61], 0x02); // number of recipients
+
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected));
+}
+
+#[test]
+fn parse_info_block_commitment_attribute() {
+    let signature = Signature::from_slice(&[0x01; 64]).unwrap();
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([0x01; 32]),
+            vec![Attribute::Commitment(Commitment([0x02; 32]))],
+            Vec::new(),
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let mut bytes = Vec::new();
+    expected.encode(&mut bytes);
+    assert_eq!(&bytes[33..37], &[
+        0x01, // number of attributes
+        0x0b, // commitment
+        0x00, 0x20, // length
+    ][..]);
+    assert_eq!(&bytes[37..69], &[0x02; 32][..]);
+    assert_eq!(&bytes[69..71], &[0x00, 0x00][..]); // the payload isn't part of the block
+
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected));
+}
+
+#[test]
+fn parse_info_block_compressed_attribute() {
+    let signature = Signature::from_slice(&[0x01; 64]).unwrap();
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([0x01; 32]),
+            vec![
+                Attribute::Compressed(Compressed { codec: Codec::Deflate, len: 0x1000 }),
+                Attribute::Compressed(Compressed { codec: Codec::Unknown(0x7f), len: 0x20 }),
+            ],
+            vec![0x02; 20],
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let mut bytes = Vec::new();
+    expected.encode(&mut bytes);
+    assert_eq!(&bytes[33..50], &[
+        0x02, // number of attributes
+        0x0c, // compressed
+        0x00, 0x05, // length
+        0x01, // deflate
+        0x00, 0x00, 0x10, 0x00, // uncompressed length
+        0x0c, // compressed
+        0x00, 0x05, // length
+        0x7f, // unknown codec
+        0x00, 0x00, 0x00, 0x20, // uncompressed length
+    ][..]);
+
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected));
+}
+
+#[test]
+fn parse_info_block_continued_attribute() {
+    let signature = Signature::from_slice(&[0x01; 64]).unwrap();
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([0x01; 32]),
+            vec![
+                Attribute::Continued,
+                Attribute::Split(TxPart { index: 0, count: 2 }),
+            ],
+            vec![0x02; 20],
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let mut bytes = Vec::new();
+    expected.encode(&mut bytes);
+    assert_eq!(&bytes[33..44], &[
+        0x02, // number of attributes
+        0x0d, // continued
+        0x00, 0x00, // length
+        0x05, // split
+        0x00, 0x04, // length
+        0x00, 0x00, // index
+        0x00, 0x02, // count
+    ][..]);
+
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected));
+}
+
+#[test]
+fn parse_info_block_client_key_attribute() {
+    let signature = Signature::from_slice(&[0x01; 64]).unwrap();
+    let key = "rq:rM>}U?@Lns47E1%kR.o@n%FcmmsL/@{H8]yf7";
+
+    let expected = Block::new(
+        InfoBlock::from_network_with_attributes(
+            BlockPointer([0x01; 32]),
+            vec![Attribute::ClientKey(key.into())],
+            vec![0x02; 20],
+            signature,
+        ).into(),
+        signature,
+    );
+
+    let mut bytes = Vec::new();
+    expected.encode(&mut bytes);
+    assert_eq!(&bytes[33..37], &[
+        0x01, // number of attributes
+        0x0e, // client key
+        0x00, 0x28, // length
+    ][..]);
+    assert_eq!(&bytes[37..77], key.as_bytes());
+
+    let block = block(&bytes);
+    assert_eq!(block, IResult::Done(EMPTY_SLICE, expected.clone()));
+    assert_eq!(expected.client_key(), Some(key));
+}
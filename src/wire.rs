@@ -1,8 +1,13 @@
-use nom::{IResult, be_u8, be_u16};
-use blocks::{BlockPointer, InnerBlock, Block};
+use nom::{IResult, be_u8, be_u16, be_u32, be_u64};
+use blocks::{BlockPointer, InnerBlock, Block, Attribute, Origin, Syslog, RekeyPolicy, Sequence, Spooled, TxPart};
+use blocks::{Codec, Commitment, Compressed, Envelope, KeyId, Recipient, SensorSignature};
+use envelope::{NONCE_LEN, SEALED_KEY_LEN};
 use blocks::{InitBlock, RekeyBlock, AlertBlock, InfoBlock};
 use crypto::{PublicKey, Signature};
 
+use std::cmp;
+use std::str;
+
 
 mod errors {
     error_chain! {
@@ -45,6 +50,64 @@ pub fn len_to_u16_vec(i: usize) -> Result<[u8; 2]> {
     Ok(bytes)
 }
 
+/// Convert an integer to a byte array (big endian).
+///
+/// ```
+/// use tr1pd::wire::u32_to_vec;
+///
+/// assert_eq!(u32_to_vec(0x01020304), [1, 2, 3, 4]);
+/// ```
+#[inline]
+pub fn u32_to_vec(i: u32) -> [u8; 4] {
+    [
+        (i >> 24) as u8,
+        (i >> 16) as u8,
+        (i >> 8) as u8,
+        i as u8,
+    ]
+}
+
+/// Convert an integer to a byte array (big endian).
+///
+/// ```
+/// use tr1pd::wire::u64_to_vec;
+///
+/// assert_eq!(u64_to_vec(0x0102030405060708), [1, 2, 3, 4, 5, 6, 7, 8]);
+/// ```
+#[inline]
+pub fn u64_to_vec(i: u64) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    for (idx, byte) in bytes.iter_mut().enumerate() {
+        *byte = (i >> (56 - idx * 8)) as u8;
+    }
+    bytes
+}
+
+/// Write a string prefixed with its length, an 8 bit length if `max` fits
+/// into it and 16 bit otherwise. Longer strings are truncated on a char
+/// boundary.
+///
+/// ```
+/// use tr1pd::wire::encode_str;
+///
+/// let mut buf = Vec::new();
+/// encode_str(&mut buf, "ohai", 3);
+/// assert_eq!(buf, b"\x03oha");
+/// ```
+pub fn encode_str(buf: &mut Vec<u8>, value: &str, max: usize) {
+    let mut len = cmp::min(value.len(), cmp::min(max, u16::max_value() as usize));
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    if max <= u8::max_value() as usize {
+        buf.push(len as u8);
+    } else {
+        buf.extend(&len_to_u16_vec(len).expect("u16 can't overflow"));
+    }
+    buf.extend(value[..len].as_bytes());
+}
+
 named!(pub pointer<&[u8], BlockPointer>, map_res!(take!(32), BlockPointer::from_slice));
 named!(pub pubkey<&[u8], PublicKey>, map_opt!(take!(32), PublicKey::from_slice));
 named!(pub signature<&[u8], Signature>, map_opt!(take!(64), Signature::from_slice));
@@ -56,7 +119,8 @@ fn inner(input: &[u8]) -> IResult<&[u8], InnerBlock> {
             0x00 => apply!(init, prev) |
             0x01 => apply!(rekey, prev) |
             0x02 => apply!(alert, prev) |
-            0x03 => apply!(info, prev)
+            0x03 => apply!(info, prev) |
+            0x04 => apply!(info_attrs, prev)
         ) >>
         (inner)
     )
@@ -120,6 +184,258 @@ fn info(input: &[u8], prev: BlockPointer) -> IResult<&[u8], InnerBlock> {
     )
 }
 
+fn info_attrs(input: &[u8], prev: BlockPointer) -> IResult<&[u8], InnerBlock> {
+    do_parse!(input,
+        count: be_u8                            >>
+        attributes: count!(attribute, count as usize) >>
+        length: be_u16                          >>
+        bytes: take!(length)                    >>
+        signature: signature                    >>
+        ({
+            InfoBlock::from_network_with_attributes(
+                prev,
+                attributes,
+                bytes.to_vec(),
+                signature,
+            )
+        })
+    )
+}
+
+fn attribute(input: &[u8]) -> IResult<&[u8], Attribute> {
+    do_parse!(input,
+        kind: be_u8             >>
+        length: be_u16          >>
+        value: flat_map!(take!(length), apply!(attribute_value, kind)) >>
+        (value)
+    )
+}
+
+fn attribute_value(input: &[u8], kind: u8) -> IResult<&[u8], Attribute> {
+    match kind {
+        0x01 => origin(input),
+        0x02 => syslog(input),
+        0x03 => rekey_policy(input),
+        0x04 => map!(input, tx_part, Attribute::Transaction),
+        0x05 => map!(input, tx_part, Attribute::Split),
+        0x06 => spooled(input),
+        0x07 => map!(input, map_res!(take!(input.len()), str::from_utf8), |id| Attribute::RequestId(id.to_string())),
+        0x08 => sequence(input),
+        0x09 => sensor_signature(input),
+        0x0a => envelope(input),
+        0x0b => commitment(input),
+        0x0c => compressed(input),
+        0x0d => continued(input),
+        0x0e => map!(input, map_res!(take!(input.len()), str::from_utf8), |key| Attribute::ClientKey(key.to_string())),
+        _ => IResult::Done(&input[input.len()..], Attribute::Unknown(kind, input.to_vec())),
+    }
+}
+
+fn origin(input: &[u8]) -> IResult<&[u8], Attribute> {
+    do_parse!(input,
+        uid: be_u32 >>
+        gid: be_u32 >>
+        pid: be_u32 >>
+        eof!()      >>
+        ({
+            Attribute::Origin(Origin {
+                uid,
+                gid,
+                pid,
+            })
+        })
+    )
+}
+
+fn syslog(input: &[u8]) -> IResult<&[u8], Attribute> {
+    do_parse!(input,
+        facility: be_u8         >>
+        severity: be_u8         >>
+        hostname: short_str     >>
+        app_name: short_str     >>
+        eof!()                  >>
+        ({
+            Attribute::Syslog(Syslog {
+                facility,
+                severity,
+                hostname,
+                app_name,
+            })
+        })
+    )
+}
+
+fn rekey_policy(input: &[u8]) -> IResult<&[u8], Attribute> {
+    let limit = |x| if x == 0 { None } else { Some(x) };
+
+    do_parse!(input,
+        infos: be_u64   >>
+        seconds: be_u64 >>
+        bytes: be_u64   >>
+        eof!()          >>
+        ({
+            Attribute::RekeyPolicy(RekeyPolicy {
+                infos: limit(infos),
+                seconds: limit(seconds),
+                bytes: limit(bytes),
+            })
+        })
+    )
+}
+
+fn tx_part(input: &[u8]) -> IResult<&[u8], TxPart> {
+    do_parse!(input,
+        index: be_u16   >>
+        count: be_u16   >>
+        eof!()          >>
+        ({
+            TxPart {
+                index,
+                count,
+            }
+        })
+    )
+}
+
+fn spooled(input: &[u8]) -> IResult<&[u8], Attribute> {
+    do_parse!(input,
+        seq: be_u64     >>
+        time: be_u64    >>
+        eof!()          >>
+        ({
+            Attribute::Spooled(Spooled {
+                seq,
+                time,
+            })
+        })
+    )
+}
+
+fn sequence(input: &[u8]) -> IResult<&[u8], Attribute> {
+    do_parse!(input,
+        seq: be_u64                                                 >>
+        source: map_res!(take!(input.len() - 8), str::from_utf8)    >>
+        ({
+            Attribute::Sequence(Sequence {
+                source: source.to_string(),
+                seq,
+            })
+        })
+    )
+}
+
+pub fn key_id(input: &[u8]) -> IResult<&[u8], KeyId> {
+    map!(input, take!(8), |bytes: &[u8]| {
+        let mut id = [0; 8];
+        id.copy_from_slice(bytes);
+        KeyId(id)
+    })
+}
+
+fn sensor_signature(input: &[u8]) -> IResult<&[u8], Attribute> {
+    do_parse!(input,
+        key_id: key_id          >>
+        signature: signature    >>
+        eof!()                  >>
+        ({
+            Attribute::SensorSignature(SensorSignature {
+                key_id,
+                signature,
+            })
+        })
+    )
+}
+
+fn recipient(input: &[u8]) -> IResult<&[u8], Recipient> {
+    do_parse!(input,
+        key_id: key_id                  >>
+        sealed_key: take!(SEALED_KEY_LEN) >>
+        ({
+            Recipient {
+                key_id,
+                sealed_key: sealed_key.to_vec(),
+            }
+        })
+    )
+}
+
+fn envelope(input: &[u8]) -> IResult<&[u8], Attribute> {
+    do_parse!(input,
+        nonce: take!(NONCE_LEN)                             >>
+        count: be_u8                                        >>
+        recipients: count!(recipient, count as usize)       >>
+        eof!()                                              >>
+        ({
+            Attribute::Sealed(Envelope {
+                nonce: nonce.to_vec(),
+                recipients,
+            })
+        })
+    )
+}
+
+fn commitment(input: &[u8]) -> IResult<&[u8], Attribute> {
+    do_parse!(input,
+        digest: take!(32)   >>
+        eof!()              >>
+        ({
+            let mut commitment = [0; 32];
+            commitment.copy_from_slice(digest);
+            Attribute::Commitment(Commitment(commitment))
+        })
+    )
+}
+
+fn compressed(input: &[u8]) -> IResult<&[u8], Attribute> {
+    do_parse!(input,
+        codec: be_u8    >>
+        len: be_u32     >>
+        eof!()          >>
+        ({
+            Attribute::Compressed(Compressed {
+                codec: Codec::from_byte(codec),
+                len,
+            })
+        })
+    )
+}
+
+fn continued(input: &[u8]) -> IResult<&[u8], Attribute> {
+    do_parse!(input,
+        eof!() >>
+        (Attribute::Continued)
+    )
+}
+
+/// A string with an 8 bit length.
+pub fn short_string(input: &[u8]) -> IResult<&[u8], String> {
+    do_parse!(input,
+        length: be_u8                                   >>
+        value: map_res!(take!(length), str::from_utf8)  >>
+        (value.to_string())
+    )
+}
+
+/// A string with a 16 bit length.
+pub fn long_string(input: &[u8]) -> IResult<&[u8], String> {
+    do_parse!(input,
+        length: be_u16                                  >>
+        value: map_res!(take!(length), str::from_utf8)  >>
+        (value.to_string())
+    )
+}
+
+/// A string with an 8 bit length, an empty string is a missing value.
+fn short_str(input: &[u8]) -> IResult<&[u8], Option<String>> {
+    map!(input, short_string, |value: String| {
+        if value.is_empty() {
+            None
+        } else {
+            Some(value)
+        }
+    })
+}
+
 
 pub fn block(input: &[u8]) -> IResult<&[u8], Block> {
     do_parse!(input,